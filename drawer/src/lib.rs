@@ -27,6 +27,12 @@ pub struct Drawer {
     caption_size: f64 ,//标题字体大小
 }
 
+impl Default for Drawer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Drawer {
     pub fn new() -> Self {
         Self {
@@ -77,7 +83,7 @@ impl Drawer {
     /// caption为空串时表示不需要标题
     pub fn build_message(mut self, file_name: &str, caption: &str) -> Self {
         self.file_name = String::from(file_name);
-        if caption != "" {
+        if !caption.is_empty() {
             self.caption = Some(String::from(caption));
         }
         self
@@ -91,7 +97,7 @@ impl Drawer {
     ///进行绘图 TODO 图例
     pub fn draw(&mut self) -> Result<(), Box<dyn std::error::Error>> {
         //未限制坐标范围，则自动生成
-        if self.defined_coordinate_range_flag == false {
+        if !self.defined_coordinate_range_flag {
             self.auto_calculate_coordinate_range();
         }
 
@@ -120,7 +126,7 @@ impl Drawer {
         //画图
         loop {
             let pv_tuple = self.point_vec_list.pop();
-            if let None = pv_tuple{
+            if pv_tuple.is_none(){
                 break;
             }
             let pv_tuple=pv_tuple.unwrap();
@@ -130,7 +136,7 @@ impl Drawer {
                     2,
                     &pv_tuple.1,
                     &|c, s, st| {
-                        return EmptyElement::at(c) + Circle::new((0, 0), s, st.filled());
+                        EmptyElement::at(c) + Circle::new((0, 0), s, st.filled())
                     },
                 ),
             )?;
@@ -145,7 +151,7 @@ impl Drawer {
         let mut init_flag =false; //记录是否已经通过第一个点来初始化过
         for point_vec in &self.point_vec_list {
             for point_tuple in &point_vec.0 {
-                if init_flag==false{
+                if !init_flag{
                     init_flag=true;
                     self.min_x=point_tuple.0;
                     self.max_x=point_tuple.0;
@@ -198,7 +204,7 @@ mod tests {
                 5,
                 &BLUE,
                 &|c, s, st| {
-                    return EmptyElement::at(c) + Circle::new((0, 0), s, st.filled());
+                    EmptyElement::at(c) + Circle::new((0, 0), s, st.filled())
                 },
             ),
         )?;
@@ -0,0 +1,79 @@
+//! 比较遍历语法树与字节码虚拟机求值的速度，以及多线程采样的速度。只计时执行分析好的程序
+//! cargo bench -p interpreter --bench expression_vm
+use std::time::{Duration, Instant};
+
+use interpreter::{Interpreter, Program};
+
+const SAMPLES: usize = 1_000_000;
+const ROUNDS: usize = 15;
+
+const PROGRAMS: [(&str, &str); 4] = [
+    ("baseline", "draw(T, T)"),
+    ("arithmetic", "draw(T * cos(T) + 2 * sin(3 * T), T ** 2 / (1 + abs(T)) - exp(-T * T))"),
+    ("conditional", "draw(T, piecewise((T < 0.25, sqrt(T)), (T < 0.75 and T != 0.5, T ** 3), else if(T > 0.9, 1, ln(T))))"),
+    ("function", "draw(T, g(T, 2) + g(T * T, 3))"),
+];
+
+///分析一次后反复执行，计时只包括执行，不包括分析源程序
+struct Bench {
+    interpreter_obj: Interpreter,
+    program: Program,
+}
+
+impl Bench {
+    fn new(body: &str) -> Self {
+        let source = format!(
+            "Fn g(x, k) => k * x + sin(x) / k;\nFor T from 0 to 1 step {} {};",
+            1.0 / (SAMPLES - 1) as f64, body,
+        );
+        let mut interpreter_obj = Interpreter::from_str(&source);
+        interpreter_obj.set_coordinate_range(-10.0, 10.0, -10.0, 10.0);
+        let program = interpreter_obj.parse().unwrap();
+        Bench { interpreter_obj, program }
+    }
+
+    fn execute(&mut self, compile_expressions: bool, worker_threads: usize) -> Duration {
+        self.interpreter_obj.set_compile_expressions(compile_expressions);
+        self.interpreter_obj.set_worker_threads(worker_threads);
+        let started_at = Instant::now();
+        let output = self.interpreter_obj.execute(&self.program).unwrap();
+        let elapsed = started_at.elapsed();
+        assert_eq!(output.points().len(), SAMPLES);
+        elapsed
+    }
+
+    ///两种设置交替执行多轮，各取最快的一次，减少其他负载的干扰
+    fn compare(&mut self, first: (bool, usize), second: (bool, usize)) -> (Duration, Duration) {
+        let (mut first_fastest, mut second_fastest) = (Duration::MAX, Duration::MAX);
+        for _ in 0..ROUNDS {
+            first_fastest = first_fastest.min(self.execute(first.0, first.1));
+            second_fastest = second_fastest.min(self.execute(second.0, second.1));
+        }
+        (first_fastest, second_fastest)
+    }
+}
+
+fn main() {
+    println!("{} points per run, best of {} runs, parsing excluded", SAMPLES, ROUNDS);
+    for (name, body) in PROGRAMS {
+        let (tree_walker, vm) = Bench::new(body).compare((false, 1), (true, 1));
+        println!(
+            "{:<12} tree walker {:>10.2?}  bytecode vm {:>10.2?}  speedup {:.2}x",
+            name, tree_walker, vm, tree_walker.as_secs_f64() / vm.as_secs_f64(),
+        );
+    }
+
+    //只有一个核心时多线程没有意义
+    let worker_threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    if worker_threads == 1 {
+        return;
+    }
+    println!("{} worker threads", worker_threads);
+    for (name, body) in PROGRAMS {
+        let (single, parallel) = Bench::new(body).compare((true, 1), (true, worker_threads));
+        println!(
+            "{:<12} 1 thread {:>10.2?}  {} threads {:>10.2?}  speedup {:.2}x",
+            name, single, worker_threads, parallel, single.as_secs_f64() / parallel.as_secs_f64(),
+        );
+    }
+}
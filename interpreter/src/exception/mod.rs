@@ -1,10 +1,34 @@
+//异常类的构造函数统一返回基类Exception，以便直接向上传递
+#![allow(clippy::new_ret_no_self)]
+
+use std::fmt;
+use std::rc::Rc;
+use std::time::Duration;
+
 use crate::lexer::*;
 
 pub type Result<T> = std::result::Result<T, Exception>;
 
 //所有异常都要实现的trait
 pub trait ExceptionTrait {
-    fn print_exception(&self);
+    ///写出异常信息，由调用者决定输出到哪里
+    fn write_exception(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result;
+
+    ///一句话描述异常，用于诊断信息的标题
+    fn message(&self) -> String;
+
+    ///异常种类
+    fn kind(&self) -> ErrorKind;
+
+    ///出错的token（如果有）
+    fn token(&self) -> Option<&Token> {
+        None
+    }
+
+    ///期望的token类型（如果有）
+    fn expected_token_types(&self) -> &[TokenTypeEnum] {
+        &[]
+    }
 }
 
 //作为派生类的异常要实现的trait
@@ -12,34 +36,362 @@ trait BaseExceptionTrait {
     fn generate(sub_exception: Box<dyn ExceptionTrait>) -> Exception;
 }
 
-///异常
+///异常种类
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    //分析级异常
+    IllegalToken,
+    SyntaxError,
+
+    //运行时异常
+    UndefinedVariableError,
+    ArgumentNumberNotMatchError,
+    InvalidArgumentError,
+    RecursionLimitExceededError,
+    InvalidResolutionError,
+    ZeroStepError,
+    StepDirectionError,
+    NonFiniteLoopRangeError,
+    IterationLimitExceededError,
+    ForeignProgramError,
+
+    //资源限制，出现后立即停止解释
+    PointLimitExceededError,
+    StatementLimitExceededError,
+    TimeoutError,
+    CancelledError,
+
+    //被提升为错误的警告
+    PromotedWarning(WarningKind),
+}
+
+impl ErrorKind {
+    ///是否为分析级（词法、语法）异常
+    pub fn is_analysis_error(&self) -> bool {
+        matches!(self, ErrorKind::IllegalToken | ErrorKind::SyntaxError)
+    }
+
+    ///是否为运行时异常
+    pub fn is_runtime_error(&self) -> bool {
+        matches!(self, ErrorKind::UndefinedVariableError | ErrorKind::ArgumentNumberNotMatchError
+            | ErrorKind::InvalidArgumentError | ErrorKind::RecursionLimitExceededError | ErrorKind::InvalidResolutionError
+            | ErrorKind::ZeroStepError | ErrorKind::StepDirectionError
+            | ErrorKind::NonFiniteLoopRangeError | ErrorKind::IterationLimitExceededError
+            | ErrorKind::ForeignProgramError)
+            || self.is_resource_limit_error()
+    }
+
+    ///是否为超过资源限制或被取消的异常
+    pub fn is_resource_limit_error(&self) -> bool {
+        matches!(self, ErrorKind::PointLimitExceededError | ErrorKind::StatementLimitExceededError
+            | ErrorKind::TimeoutError | ErrorKind::CancelledError)
+    }
+}
+
+///异常。共享具体的异常，以便重复执行时再次报告分析时的错误
+#[derive(Clone)]
 pub struct Exception {
-    sub_exception: Box<dyn ExceptionTrait>,
+    sub_exception: Rc<dyn ExceptionTrait>,
 }
 
 impl BaseExceptionTrait for Exception {
     fn generate(sub_exception: Box<dyn ExceptionTrait>) -> Exception {
         Self {
-            sub_exception
+            sub_exception: Rc::from(sub_exception)
         }
     }
 }
 
 impl ExceptionTrait for Exception {
-    fn print_exception(&self) {
-        println!();
-        self.sub_exception.print_exception();
-        println!();
+    fn write_exception(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.sub_exception.write_exception(f)
+    }
+
+    fn message(&self) -> String {
+        self.sub_exception.message()
+    }
+
+    fn kind(&self) -> ErrorKind {
+        self.sub_exception.kind()
+    }
+
+    fn token(&self) -> Option<&Token> {
+        self.sub_exception.token()
+    }
+
+    fn expected_token_types(&self) -> &[TokenTypeEnum] {
+        self.sub_exception.expected_token_types()
+    }
+}
+
+impl fmt::Display for Exception {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_exception(f)
+    }
+}
+
+impl fmt::Debug for Exception {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_exception(f)
+    }
+}
+
+///对外暴露的解释错误，附带出错位置与源码片段。打印与否由调用者决定
+#[derive(Clone)]
+pub struct InterpretError {
+    exception: Exception,
+    //出错区间
+    span: Span,
+    //出错区间所在行的原文
+    source_line: Option<String>,
+    //补充说明，如正在分析的语句
+    note: Option<String>,
+}
+
+impl InterpretError {
+    pub fn new(exception: Exception, span: Span) -> Self {
+        Self {
+            exception,
+            span,
+            source_line: None,
+            note: None,
+        }
+    }
+
+    ///附上出错行的原文，用于展示源码片段
+    pub fn with_source_line(mut self, source_line: &str) -> Self {
+        self.source_line = Some(String::from(source_line));
+        self
+    }
+
+    ///附上补充说明
+    pub fn with_note(mut self, note: &str) -> Self {
+        self.note = Some(String::from(note));
+        self
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.exception.kind()
+    }
+
+    pub fn token(&self) -> Option<&Token> {
+        self.exception.token()
+    }
+
+    pub fn expected_token_types(&self) -> &[TokenTypeEnum] {
+        self.exception.expected_token_types()
+    }
+
+    ///出错位置，(line,col)
+    pub fn position(&self) -> (u32, u32) {
+        self.span.start()
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn source_line(&self) -> Option<&str> {
+        self.source_line.as_deref()
+    }
+
+    pub fn note(&self) -> Option<&str> {
+        self.note.as_deref()
+    }
+
+    ///一句话描述错误
+    pub fn message(&self) -> String {
+        self.exception.message()
+    }
+
+    ///传统格式的详细信息，即各异常自身打印的内容
+    pub fn detail(&self) -> String {
+        self.exception.to_string()
+    }
+}
+
+///仿照rustc的格式输出诊断信息：
+///```text
+///error[SyntaxError]: expected `)`, found `;`
+/// --> 1:19
+///  |
+///1 | ROT is 8*(2**(5-2);
+///  |                   ^
+///  = note: while parsing `ROT` statement
+///```
+fn write_diagnostic(f: &mut fmt::Formatter<'_>, level: &str, kind_name: &str, message: &str,
+                    span: Span, source_line: Option<&str>, note: Option<&str>) -> fmt::Result {
+    let (line, col) = span.start();
+    writeln!(f, "{}[{}]: {}", level, kind_name, message)?;
+
+    let line_number = line.to_string();
+    let gutter = " ".repeat(line_number.len());
+    writeln!(f, "{}--> {}:{}", gutter, line, col)?;
+
+    if let Some(source_line) = source_line {
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", line_number, source_line)?;
+        //跨行的区间只标注到行尾
+        let line_len = source_line.chars().count() as u32;
+        let start_col = col.max(1);
+        let end_col = if span.end().0 == line { span.end().1 } else { line_len };
+        let end_col = end_col.max(start_col);
+        //保留制表符以对齐
+        let padding: String = source_line.chars().take(start_col as usize - 1)
+            .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+            .collect();
+        let underline = "^".repeat((end_col - start_col + 1) as usize);
+        writeln!(f, "{} | {}{}", gutter, padding, underline)?;
+    }
+
+    if let Some(note) = note {
+        writeln!(f, "{} = note: {}", gutter, note)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for InterpretError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind_name = match self.kind() {
+            ErrorKind::PromotedWarning(warning_kind) => format!("{:?}", warning_kind),
+            kind => format!("{:?}", kind),
+        };
+        write_diagnostic(f, "error", &kind_name, &self.message(), self.span,
+                         self.source_line(), self.note())
+    }
+}
+
+impl fmt::Debug for InterpretError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InterpretError")
+            .field("kind", &self.kind())
+            .field("token", &self.token())
+            .field("expected_token_types", &self.expected_token_types())
+            .field("span", &self.span)
+            .finish()
+    }
+}
+
+impl std::error::Error for InterpretError {}
+
+///警告种类
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WarningKind {
+    //坐标含NaN而被丢弃的点
+    DiscardedNanPoints,
+    //超出坐标范围而被丢弃的点
+    OutOfRangePoints,
+    //负数的小数次方
+    NegativeBaseFractionalPower,
+    //PIECEWISE没有任何分支匹配
+    UnmatchedPiecewise,
+    //DEF覆盖了同名变量
+    ShadowedDefinition,
+    //定义后从未被使用的变量
+    UnusedVariable,
+}
+
+///警告。不会中断解释，随结果一起返回
+#[derive(Debug, Clone)]
+pub struct InterpretWarning {
+    kind: WarningKind,
+    message: String,
+    span: Span,
+    source_line: Option<String>,
+    note: Option<String>,
+}
+
+impl InterpretWarning {
+    pub fn new(kind: WarningKind, message: &str, span: Span) -> Self {
+        Self {
+            kind,
+            message: String::from(message),
+            span,
+            source_line: None,
+            note: None,
+        }
+    }
+
+    ///附上出错行的原文，用于展示源码片段
+    pub fn with_source_line(mut self, source_line: &str) -> Self {
+        self.source_line = Some(String::from(source_line));
+        self
+    }
+
+    ///附上补充说明
+    pub fn with_note(mut self, note: &str) -> Self {
+        self.note = Some(String::from(note));
+        self
+    }
+
+    pub fn kind(&self) -> WarningKind {
+        self.kind
+    }
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+    ///警告位置，(line,col)
+    pub fn position(&self) -> (u32, u32) {
+        self.span.start()
+    }
+    pub fn span(&self) -> Span {
+        self.span
+    }
+    pub fn source_line(&self) -> Option<&str> {
+        self.source_line.as_deref()
+    }
+    pub fn note(&self) -> Option<&str> {
+        self.note.as_deref()
+    }
+
+    ///将警告提升为错误
+    pub fn into_error(self) -> InterpretError {
+        InterpretError {
+            exception: PromotedWarningError::new(self.kind, &self.message),
+            span: self.span,
+            source_line: self.source_line,
+            note: self.note,
+        }
     }
 }
 
-impl std::fmt::Debug for Exception {
-    fn fmt(&self, _f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.print_exception();
-        Ok(())
+impl fmt::Display for InterpretWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_diagnostic(f, "warning", &format!("{:?}", self.kind), &self.message, self.span,
+                         self.source_line(), self.note())
+    }
+}
+
+///注册自定义函数、常数或设置外部参数时的错误
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegistrationError {
+    //名字不是字母开头、只包含字母和数字的词
+    InvalidName(String),
+    //名字与保留字、内置函数、内置常数或已注册的名字相同
+    NameCollision(String),
+    //源程序已经分析过，注册的名字不会再生效
+    AlreadyParsed(String),
+    //设置的外部参数没有用PARAM声明
+    UndeclaredParameter(String),
+}
+
+impl fmt::Display for RegistrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegistrationError::InvalidName(name) =>
+                write!(f, "`{}` is not a valid name: it must start with a letter and contain only letters and digits", name),
+            RegistrationError::NameCollision(name) =>
+                write!(f, "`{}` is already a reserved word, function, constant or special variable", name),
+            RegistrationError::AlreadyParsed(name) =>
+                write!(f, "cannot register `{}` after the program has been parsed", name),
+            RegistrationError::UndeclaredParameter(name) =>
+                write!(f, "`{}` is not declared by a PARAM statement", name),
+        }
     }
 }
 
+impl std::error::Error for RegistrationError {}
+
 ///编译时异常------
 struct AnalysisException {
     sub_exception: Box<dyn ExceptionTrait>,
@@ -55,9 +407,25 @@ impl BaseExceptionTrait for AnalysisException {
 }
 
 impl ExceptionTrait for AnalysisException {
-    fn print_exception(&self) {
-        println!("*** Analysis Error ***");
-        self.sub_exception.print_exception();
+    fn write_exception(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "*** Analysis Error ***")?;
+        self.sub_exception.write_exception(f)
+    }
+
+    fn message(&self) -> String {
+        self.sub_exception.message()
+    }
+
+    fn kind(&self) -> ErrorKind {
+        self.sub_exception.kind()
+    }
+
+    fn token(&self) -> Option<&Token> {
+        self.sub_exception.token()
+    }
+
+    fn expected_token_types(&self) -> &[TokenTypeEnum] {
+        self.sub_exception.expected_token_types()
     }
 }
 
@@ -75,8 +443,16 @@ impl IllegalTokenError {
 }
 
 impl ExceptionTrait for IllegalTokenError {
-    fn print_exception(&self) {
-        println!("Illegal Symbol: {}", self.lexeme);
+    fn write_exception(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Illegal Symbol: {}", self.lexeme)
+    }
+
+    fn message(&self) -> String {
+        format!("illegal symbol `{}`", self.lexeme)
+    }
+
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::IllegalToken
     }
 }
 
@@ -84,6 +460,8 @@ impl ExceptionTrait for IllegalTokenError {
 pub struct SyntaxError {
     token: Token,
     expect_token_types: Vec<TokenTypeEnum>,
+    //token的种类正确但不合法时的说明，如重复的形参名
+    message: Option<String>,
 }
 
 impl SyntaxError {
@@ -91,15 +469,48 @@ impl SyntaxError {
         AnalysisException::generate(Box::new(Self {
             token: token.clone(),
             expect_token_types: expect_token_type.to_vec(),
+            message: None,
+        }))
+    }
+
+    ///token的种类符合语法，但出现在这里不合法
+    pub fn with_message(token: &Token, message: &str) -> Exception {
+        AnalysisException::generate(Box::new(Self {
+            token: token.clone(),
+            expect_token_types: Vec::new(),
+            message: Some(String::from(message)),
         }))
     }
 }
 
 impl ExceptionTrait for SyntaxError {
-    fn print_exception(&self) {
-        println!("Syntax Error: {:?}", self.token);
-        println!("Expect: {:?}", self.expect_token_types);
-        println!("Found : {:?}", self.token.token_type());
+    fn write_exception(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Syntax Error: {:?}", self.token)?;
+        writeln!(f, "Expect: {:?}", self.expect_token_types)?;
+        writeln!(f, "Found : {:?}", self.token.token_type())
+    }
+
+    fn message(&self) -> String {
+        if let Some(message) = &self.message {
+            return message.clone();
+        }
+        let found = match self.token.token_type() {
+            TokenTypeEnum::NonToken => String::from("end of file"),
+            _ => format!("`{}`", self.token.lexeme()),
+        };
+        format!("expected {}, found {}", TokenTypeEnum::describe_list(&self.expect_token_types), found)
+    }
+
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::SyntaxError
+    }
+
+    fn token(&self) -> Option<&Token> {
+        Some(&self.token)
+    }
+
+    fn expected_token_types(&self) -> &[TokenTypeEnum] {
+        &self.expect_token_types
     }
 }
 
@@ -117,28 +528,56 @@ impl BaseExceptionTrait for RuntimeException {
 }
 
 impl ExceptionTrait for RuntimeException {
-    fn print_exception(&self) {
-        println!("*** Runtime Error ***");
-        self.sub_exception.print_exception();
+    fn write_exception(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "*** Runtime Error ***")?;
+        self.sub_exception.write_exception(f)
+    }
+
+    fn message(&self) -> String {
+        self.sub_exception.message()
+    }
+
+    fn kind(&self) -> ErrorKind {
+        self.sub_exception.kind()
+    }
+
+    fn token(&self) -> Option<&Token> {
+        self.sub_exception.token()
+    }
+
+    fn expected_token_types(&self) -> &[TokenTypeEnum] {
+        self.sub_exception.expected_token_types()
     }
 }
 
 ///未定义变量错误
 pub struct UndefinedVariableError {
-    variable_name: String,
+    token: Token,
 }
 
 impl UndefinedVariableError {
-    pub fn new(variable_name: &str) -> Exception {
+    pub fn new(token: &Token) -> Exception {
         RuntimeException::generate(Box::new(Self {
-            variable_name: variable_name.parse().unwrap()
+            token: token.clone()
         }))
     }
 }
 
 impl ExceptionTrait for UndefinedVariableError {
-    fn print_exception(&self) {
-        println!("Undefined Variable Error: {:?}", self.variable_name);
+    fn write_exception(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Undefined Variable Error: {:?}", self.token.lexeme())
+    }
+
+    fn message(&self) -> String {
+        format!("undefined variable `{}`", self.token.lexeme())
+    }
+
+    fn token(&self) -> Option<&Token> {
+        Some(&self.token)
+    }
+
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::UndefinedVariableError
     }
 }
 
@@ -162,15 +601,408 @@ impl ArgumentNumberNotMatchError {
 }
 
 impl ExceptionTrait for ArgumentNumberNotMatchError {
-    fn print_exception(&self) {
-        println!("Arguments' Number not Match Error:");
-        println!("At Function : {:?}",self.func_name);
-        print!("Expect : {:?}",self.target_num);
+    fn write_exception(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Arguments' Number not Match Error:")?;
+        writeln!(f, "At Function : {:?}",self.func_name)?;
+        write!(f, "Expect : {:?}",self.target_num)?;
         if self.variable_length_flag{
-            print!("+")
+            write!(f, "+")?;
         }
-        println!();
-        println!("Receive: {:?}",self.received_num);
+        writeln!(f)?;
+        writeln!(f, "Receive: {:?}",self.received_num)
+    }
+
+    fn message(&self) -> String {
+        let at_least = if self.variable_length_flag { "at least " } else { "" };
+        format!("function `{}` expects {}{} argument(s), but received {}",
+                self.func_name, at_least, self.target_num, self.received_num)
+    }
+
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::ArgumentNumberNotMatchError
+    }
+}
+
+///参数的值不合法，如CLAMP的下界大于上界
+pub struct InvalidArgumentError {
+    func_name: String,
+    reason: String,
+}
+
+impl InvalidArgumentError {
+    pub fn new(func_name: &str, reason: &str) -> Exception {
+        RuntimeException::generate(Box::new(Self {
+            func_name: String::from(func_name),
+            reason: String::from(reason),
+        }))
+    }
+}
+
+impl ExceptionTrait for InvalidArgumentError {
+    fn write_exception(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Invalid Argument Error:")?;
+        writeln!(f, "At Function : {:?}", self.func_name)?;
+        writeln!(f, "Reason : {}", self.reason)
+    }
+
+    fn message(&self) -> String {
+        format!("invalid argument(s) for function `{}`: {}", self.func_name, self.reason)
+    }
+
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::InvalidArgumentError
+    }
+}
+
+///函数递归过深错误
+pub struct RecursionLimitExceededError {
+    func_name: String,
+    max_depth: usize,
+}
+
+impl RecursionLimitExceededError {
+    pub fn new(func_name: &str, max_depth: usize) -> Exception {
+        RuntimeException::generate(Box::new(Self {
+            func_name: String::from(func_name),
+            max_depth,
+        }))
+    }
+}
+
+impl ExceptionTrait for RecursionLimitExceededError {
+    fn write_exception(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Recursion Limit Exceeded Error:")?;
+        writeln!(f, "At Function : {:?}", self.func_name)?;
+        writeln!(f, "Max Depth : {:?}", self.max_depth)
+    }
+
+    fn message(&self) -> String {
+        format!("function `{}` exceeded the maximum recursion depth of {}", self.func_name, self.max_depth)
+    }
+
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::RecursionLimitExceededError
+    }
+}
+
+///IMPLICIT的网格分辨率不是正整数
+pub struct InvalidResolutionError {
+    resolution: f64,
+}
+
+impl InvalidResolutionError {
+    pub fn new(resolution: f64) -> Exception {
+        RuntimeException::generate(Box::new(Self {
+            resolution,
+        }))
+    }
+}
+
+impl ExceptionTrait for InvalidResolutionError {
+    fn write_exception(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Invalid Resolution Error:")?;
+        writeln!(f, "Resolution : {:?}", self.resolution)
+    }
+
+    fn message(&self) -> String {
+        format!("resolution of IMPLICIT must be a positive integer, found {}", self.resolution)
+    }
+
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::InvalidResolutionError
+    }
+}
+///FOR的步长为0，循环永远不会结束
+pub struct ZeroStepError {
+    loop_variable: String,
+}
+
+impl ZeroStepError {
+    pub fn new(loop_variable: &str) -> Exception {
+        RuntimeException::generate(Box::new(Self {
+            loop_variable: String::from(loop_variable),
+        }))
+    }
+}
+
+impl ExceptionTrait for ZeroStepError {
+    fn write_exception(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Zero Step Error:")?;
+        writeln!(f, "Loop Variable : {:?}", self.loop_variable)
+    }
+
+    fn message(&self) -> String {
+        format!("step of loop variable `{}` is zero, the loop would never end", self.loop_variable)
+    }
+
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::ZeroStepError
+    }
+}
+
+///FOR的步长方向与FROM到TO的方向相反
+pub struct StepDirectionError {
+    loop_variable: String,
+    from: f64,
+    to: f64,
+    step: f64,
+}
+
+impl StepDirectionError {
+    pub fn new(loop_variable: &str, from: f64, to: f64, step: f64) -> Exception {
+        RuntimeException::generate(Box::new(Self {
+            loop_variable: String::from(loop_variable),
+            from,
+            to,
+            step,
+        }))
+    }
+}
+
+impl ExceptionTrait for StepDirectionError {
+    fn write_exception(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Step Direction Error:")?;
+        writeln!(f, "Loop Variable : {:?}", self.loop_variable)?;
+        writeln!(f, "From : {:?}, To : {:?}, Step : {:?}", self.from, self.to, self.step)
+    }
+
+    fn message(&self) -> String {
+        format!("step {} of loop variable `{}` never reaches {} from {}",
+                self.step, self.loop_variable, self.to, self.from)
+    }
+
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::StepDirectionError
+    }
+}
+
+///FOR的FROM、TO或STEP为NaN或无穷大
+pub struct NonFiniteLoopRangeError {
+    loop_variable: String,
+    //FROM、TO或STEP
+    bound: TokenTypeEnum,
+    value: f64,
+}
+
+impl NonFiniteLoopRangeError {
+    pub fn new(loop_variable: &str, bound: TokenTypeEnum, value: f64) -> Exception {
+        RuntimeException::generate(Box::new(Self {
+            loop_variable: String::from(loop_variable),
+            bound,
+            value,
+        }))
+    }
+}
+
+impl ExceptionTrait for NonFiniteLoopRangeError {
+    fn write_exception(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Non Finite Loop Range Error:")?;
+        writeln!(f, "Loop Variable : {:?}", self.loop_variable)?;
+        writeln!(f, "{:?} : {:?}", self.bound, self.value)
+    }
+
+    fn message(&self) -> String {
+        format!("{} of loop variable `{}` is {}, expected a finite number",
+                self.bound.describe(), self.loop_variable, self.value)
+    }
+
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::NonFiniteLoopRangeError
+    }
+}
+
+///一条FOR语句的迭代次数超过上限
+pub struct IterationLimitExceededError {
+    loop_variable: String,
+    iterations: f64,
+    max_iterations: usize,
+}
+
+impl IterationLimitExceededError {
+    pub fn new(loop_variable: &str, iterations: f64, max_iterations: usize) -> Exception {
+        RuntimeException::generate(Box::new(Self {
+            loop_variable: String::from(loop_variable),
+            iterations,
+            max_iterations,
+        }))
+    }
+}
+
+impl ExceptionTrait for IterationLimitExceededError {
+    fn write_exception(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Iteration Limit Exceeded Error:")?;
+        writeln!(f, "Loop Variable : {:?}", self.loop_variable)?;
+        writeln!(f, "Iterations : {:?}", self.iterations)?;
+        writeln!(f, "Max Iterations : {:?}", self.max_iterations)
+    }
+
+    fn message(&self) -> String {
+        format!("loop variable `{}` would take {} iterations, exceeding the maximum of {}",
+                self.loop_variable, self.iterations, self.max_iterations)
+    }
+
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::IterationLimitExceededError
+    }
+}
+
+///执行的程序由其他解释器分析得到，它的变量绑定在那个解释器的符号上
+pub struct ForeignProgramError;
+
+impl ForeignProgramError {
+    pub fn new() -> Exception {
+        RuntimeException::generate(Box::new(Self))
+    }
+}
+
+impl ExceptionTrait for ForeignProgramError {
+    fn write_exception(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Foreign Program Error")
+    }
+
+    fn message(&self) -> String {
+        String::from("the program was parsed by another interpreter")
+    }
+
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::ForeignProgramError
+    }
+}
+///资源限制------
+///点数超过上限
+pub struct PointLimitExceededError {
+    max_points: usize,
+}
+
+impl PointLimitExceededError {
+    pub fn new(max_points: usize) -> Exception {
+        RuntimeException::generate(Box::new(Self {
+            max_points,
+        }))
+    }
+}
+
+impl ExceptionTrait for PointLimitExceededError {
+    fn write_exception(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Point Limit Exceeded Error:")?;
+        writeln!(f, "Max Points : {:?}", self.max_points)
+    }
+
+    fn message(&self) -> String {
+        format!("exceeded the maximum of {} points", self.max_points)
+    }
+
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::PointLimitExceededError
+    }
+}
+
+///执行的语句数超过上限
+pub struct StatementLimitExceededError {
+    max_statements: usize,
+}
+
+impl StatementLimitExceededError {
+    pub fn new(max_statements: usize) -> Exception {
+        RuntimeException::generate(Box::new(Self {
+            max_statements,
+        }))
+    }
+}
+
+impl ExceptionTrait for StatementLimitExceededError {
+    fn write_exception(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Statement Limit Exceeded Error:")?;
+        writeln!(f, "Max Statements : {:?}", self.max_statements)
+    }
+
+    fn message(&self) -> String {
+        format!("exceeded the maximum of {} executed statements", self.max_statements)
+    }
+
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::StatementLimitExceededError
+    }
+}
+
+///解释超时
+pub struct TimeoutError {
+    timeout: Duration,
+}
+
+impl TimeoutError {
+    pub fn new(timeout: Duration) -> Exception {
+        RuntimeException::generate(Box::new(Self {
+            timeout,
+        }))
+    }
+}
+
+impl ExceptionTrait for TimeoutError {
+    fn write_exception(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Timeout Error:")?;
+        writeln!(f, "Timeout : {:?}", self.timeout)
+    }
+
+    fn message(&self) -> String {
+        format!("interpretation timed out after {:?}", self.timeout)
+    }
+
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::TimeoutError
+    }
+}
+
+///解释被取消
+pub struct CancelledError;
+
+impl CancelledError {
+    pub fn new() -> Exception {
+        RuntimeException::generate(Box::new(Self))
+    }
+}
+
+impl ExceptionTrait for CancelledError {
+    fn write_exception(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Cancelled Error")
+    }
+
+    fn message(&self) -> String {
+        String::from("interpretation was cancelled")
+    }
+
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::CancelledError
+    }
+}
+
+///被提升为错误的警告------
+pub struct PromotedWarningError {
+    warning_kind: WarningKind,
+    message: String,
+}
+
+impl PromotedWarningError {
+    pub fn new(warning_kind: WarningKind, message: &str) -> Exception {
+        Exception::generate(Box::new(Self {
+            warning_kind,
+            message: String::from(message),
+        }))
+    }
+}
+
+impl ExceptionTrait for PromotedWarningError {
+    fn write_exception(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "*** Warning Treated as Error ***")?;
+        writeln!(f, "{:?}: {}", self.warning_kind, self.message)
+    }
+
+    fn message(&self) -> String {
+        self.message.clone()
+    }
+
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::PromotedWarning(self.warning_kind)
     }
 }
 
@@ -181,8 +1013,57 @@ mod tests {
 
     #[test]
     fn test_print_exception() {
-        IllegalTokenError::new("123abc")
-            .print_exception();
+        let exception = IllegalTokenError::new("123abc");
+        println!("{}", exception);
+        assert_eq!(exception.to_string(), "*** Analysis Error ***\nIllegal Symbol: 123abc\n");
     }
-}
 
+    #[test]
+    fn test_interpret_error() {
+        let token = Token::generate_eof_token();
+        let error = InterpretError::new(
+            SyntaxError::new(&token, &[TokenTypeEnum::Semico, TokenTypeEnum::RBracket]),
+            Span::new((2, 5), (2, 5)),
+        );
+        assert_eq!(error.kind(), ErrorKind::SyntaxError);
+        assert!(error.kind().is_analysis_error());
+        assert_eq!(error.token().unwrap().token_type(), TokenTypeEnum::NonToken);
+        assert_eq!(error.expected_token_types(), &[TokenTypeEnum::Semico, TokenTypeEnum::RBracket]);
+        assert_eq!(error.position(), (2, 5));
+        assert_eq!(error.message(), "expected `;` or `)`, found end of file");
+        assert!(error.detail().contains("Expect: [Semico, RBracket]"));
+    }
+
+    #[test]
+    fn test_promote_warning() {
+        let warning = InterpretWarning::new(WarningKind::UnusedVariable, "variable `A` is never used", Span::new((1, 5), (1, 5)))
+            .with_source_line("DEF a = 1;");
+        assert!(warning.to_string().starts_with("warning[UnusedVariable]: variable `A` is never used"));
+
+        let error = warning.into_error();
+        assert_eq!(error.kind(), ErrorKind::PromotedWarning(WarningKind::UnusedVariable));
+        assert!(!error.kind().is_runtime_error());
+        assert!(error.to_string().starts_with("error[UnusedVariable]: variable `A` is never used"));
+        assert_eq!(error.source_line(), Some("DEF a = 1;"));
+    }
+
+    #[test]
+    fn test_render_diagnostic() {
+        let mut token = TokenBuilder::new().token_type(TokenTypeEnum::Semico).lexeme(";").build();
+        token.set_span(Span::new((1, 19), (1, 19)));
+        let error = InterpretError::new(SyntaxError::new(&token, &[TokenTypeEnum::RBracket]), token.span())
+            .with_source_line("ROT is 8*(2**(5-2);")
+            .with_note("while parsing `ROT` statement");
+
+        let rendered = error.to_string();
+        println!("{}", rendered);
+        assert_eq!(rendered, "\
+error[SyntaxError]: expected `)`, found `;`
+ --> 1:19
+  |
+1 | ROT is 8*(2**(5-2);
+  |                   ^
+  = note: while parsing `ROT` statement
+");
+    }
+}
@@ -1,6 +1,6 @@
 use std::collections::hash_map::HashMap;
-use std::fs::File;
 
+pub use text_reader::Source;
 pub use token_manager::*;
 
 mod text_reader;
@@ -11,45 +11,61 @@ pub struct Lexer {
     text_reader: text_reader::TextReader,
     //当前缓存（正在处理）的char
     curr_char: Option<char>,
+    //上一个被吃掉的char的位置，用于确定token的结尾
+    last_char_position: (u32, u32),
 
     //符号表
     token_match_map: HashMap<String, Token>,
 }
 
 impl Lexer {
-    pub fn new(file: File) -> Self {
-        let mut aim_text_reader = text_reader::TextReader::new(file);
+    pub fn new(source: Source) -> Self {
+        let mut aim_text_reader = text_reader::TextReader::new(source);
         //先吃一个作为缓存，以保证逻辑一致性
         let curr_char = aim_text_reader.eat_char();
         Lexer {
             text_reader: aim_text_reader,
             curr_char,
+            last_char_position: (1, 0),
             token_match_map: Token::generate_token_match_map(),
         }
     }
 
-    ///获取下一个token
+    ///获取下一个token，并记录其在源程序中的位置
     pub fn fetch_token(&mut self) -> Token {
         // println!("Debug: fetch_token");
-        //略过空白项
-        self.skip_whitespace();
-
-        if let Some(ch) = self.get_curr_char() {
-            //根据开头字符，分为三种情况进行拼接
-            if ch.is_ascii_digit() {
-                //1.数字开头。必须是double。吃掉小数点、数字、字母，最后一定要符合double格式
-                return self.collect_digit_token();
-            } else if ch.is_ascii_alphabetic() {
-                //2.字母开头。保留字、函数名、参数、常数。吃掉字母、数字，最后去Map进行匹配
-                return self.collect_word_token();
+        loop {
+            //略过空白项
+            self.skip_whitespace();
+            let start_position = self.get_char_position();
+
+            let mut token = if let Some(ch) = self.get_curr_char() {
+                //根据开头字符，分为三种情况进行拼接
+                if ch.is_ascii_digit() {
+                    //1.数字开头。必须是double。吃掉小数点、数字、字母，最后一定要符合double格式
+                    self.collect_digit_token()
+                } else if ch.is_ascii_alphabetic() {
+                    //2.字母开头。保留字、函数名、参数、常数。吃掉字母、数字，最后去Map进行匹配
+                    self.collect_word_token()
+                } else {
+                    //3.运算符、分隔符。只有单符号和双符号
+                    self.collect_special_token()
+                }
             } else {
-                //3.运算符、分隔符。只有单符号和双符号
-                return self.collect_special_token();
+                //None，表示EOF了，位置记为最后一个字符之后
+                let mut eof_token = Token::generate_eof_token();
+                let eof_position = (self.last_char_position.0, self.last_char_position.1 + 1);
+                eof_token.set_span(Span::new(eof_position, eof_position));
+                return eof_token;
+            };
+
+            //注释直接丢弃，继续查找下一个token，保证上层一直接收到有效的token
+            if token.token_type() == TokenTypeEnum::Comment {
+                continue;
             }
+            token.set_span(Span::new(start_position, self.last_char_position));
+            return token;
         }
-
-        //None，表示EOF了
-        return Token::generate_eof_token();
     }
 
     ///获取当前正在读取的字符的位置，(line,col)
@@ -57,6 +73,11 @@ impl Lexer {
         self.text_reader.get_char_position()
     }
 
+    ///获取源程序某一行的原文，行号从1开始
+    pub fn get_line_text(&self, line: u32) -> Option<&str> {
+        self.text_reader.get_line_text(line)
+    }
+
     ///略过空白项
     fn skip_whitespace(&mut self) {
         loop {
@@ -76,7 +97,7 @@ impl Lexer {
         loop {
             if let Some(ch) = self.get_curr_char() {
                 if *ch == '.' || ch.is_ascii_digit() || ch.is_ascii_alphabetic() {
-                    lexeme_char_vec.push(ch.clone());
+                    lexeme_char_vec.push(*ch);
                     self.read_new_char();
                     continue;
                 }
@@ -98,7 +119,7 @@ impl Lexer {
         loop {
             if let Some(ch) = self.get_curr_char() {
                 if ch.is_ascii_digit() || ch.is_ascii_alphabetic() {
-                    lexeme_char_vec.push(ch.clone());
+                    lexeme_char_vec.push(*ch);
                     self.read_new_char();
                     continue;
                 }
@@ -109,7 +130,7 @@ impl Lexer {
 
         match self.token_match_map.get(&lexeme) {
             Some(token) => {
-                return (*token).clone();
+                (*token).clone()
             }
             None => {
                 //可能是新的变量名
@@ -119,9 +140,9 @@ impl Lexer {
                         return Token::generate_err_token(&lexeme);
                     }
                 }
-                return TokenBuilder::new().token_type(TokenTypeEnum::Variable).lexeme(&lexeme).build();
+                TokenBuilder::new().token_type(TokenTypeEnum::Variable).lexeme(&lexeme).build()
             }
-        };
+        }
     }
 
     ///运算符、分隔符。只有单符号和双符号
@@ -141,6 +162,18 @@ impl Lexer {
             }
         }
 
+        //函数定义、比较
+        if aim_char == '=' || aim_char == '<' || aim_char == '>' || aim_char == '!' {
+            if let Some(ch) = self.get_curr_char() {
+                let double_symbol = format!("{}{}", aim_char, ch);
+                if let Some(token) = self.token_match_map.get(&double_symbol) {
+                    let token = token.clone();
+                    self.read_new_char();
+                    return token;
+                }
+            }
+        }
+
         //行注释
         if aim_char == '/' {
             //要看下一个符号是什么
@@ -158,13 +191,26 @@ impl Lexer {
                             break; //EOF
                         }
                     }
-                    //查找下一个token以返回，保证上层一直接收到有效的token
-                    return self.fetch_token();
+                    return self.token_match_map.get("//").unwrap().clone();
                 }
             }
         }
 
-        return self.token_match_map.get(&String::from(aim_char)).unwrap().clone();
+        match self.token_match_map.get(&String::from(aim_char)) {
+            Some(token) => token.clone(),
+            //不认识的符号
+            None => Token::generate_err_token(&String::from(aim_char)),
+        }
+    }
+
+    ///在符号表中注册新的token（如用户定义的函数），之后读到同名的词时会得到该token
+    pub fn register_token(&mut self, token: Token) {
+        self.token_match_map.insert(token.lexeme().clone(), token);
+    }
+
+    ///符号表中是否已有该词（保留字、函数名、常数等）
+    pub fn contains_word(&self, word: &str) -> bool {
+        self.token_match_map.contains_key(word)
     }
 
     ///获取curr_char
@@ -174,6 +220,7 @@ impl Lexer {
 
     ///读取新的char并覆盖当前curr_char
     fn read_new_char(&mut self) {
+        self.last_char_position = self.get_char_position();
         self.curr_char = self.text_reader.eat_char();
     }
 }
@@ -181,13 +228,14 @@ impl Lexer {
 #[cfg(test)]
 mod tests {
     use std::fs::File;
+    use std::io::{BufReader, Cursor};
 
     use super::*;
 
     #[test]
     fn test_lex() {
         let file = File::open("parse_test.txt").unwrap();
-        let mut lexer = Lexer::new(file);
+        let mut lexer = Lexer::new(Box::new(BufReader::new(file)));
 
         // let token = lexer.fetch_token();
         loop {
@@ -198,10 +246,57 @@ mod tests {
             }
         }
     }
-}
 
+    #[test]
+    fn test_lex_string() {
+        let mut lexer = Lexer::new(Box::new(Cursor::new(String::from("rot is pi**2;"))));
 
+        let mut token_types = Vec::new();
+        loop {
+            let token = lexer.fetch_token();
+            if token.token_type() == TokenTypeEnum::NonToken {
+                break;
+            }
+            token_types.push(token.token_type());
+        }
+        assert_eq!(token_types, vec![
+            TokenTypeEnum::Rot, TokenTypeEnum::Is, TokenTypeEnum::ConstId,
+            TokenTypeEnum::Power, TokenTypeEnum::ConstId, TokenTypeEnum::Semico,
+        ]);
+    }
 
+    #[test]
+    fn test_lex_comparison() {
+        let mut lexer = Lexer::new(Box::new(Cursor::new(String::from("< <= > >= == != = => !"))));
 
+        let mut token_types = Vec::new();
+        loop {
+            let token = lexer.fetch_token();
+            if token.token_type() == TokenTypeEnum::NonToken {
+                break;
+            }
+            token_types.push(token.token_type());
+        }
+        assert_eq!(token_types, vec![
+            TokenTypeEnum::Less, TokenTypeEnum::LessEqual, TokenTypeEnum::Greater, TokenTypeEnum::GreaterEqual,
+            TokenTypeEnum::Equal, TokenTypeEnum::NotEqual, TokenTypeEnum::Assign, TokenTypeEnum::Arrow,
+            TokenTypeEnum::ErrToken,
+        ]);
+    }
 
+    #[test]
+    fn test_token_span() {
+        let mut lexer = Lexer::new(Box::new(Cursor::new(String::from("rot is 12.5; // note\n  draw"))));
 
+        let spans: Vec<Span> = (0..5).map(|_| lexer.fetch_token().span()).collect();
+        assert_eq!(spans, vec![
+            Span::new((1, 1), (1, 3)),
+            Span::new((1, 5), (1, 6)),
+            Span::new((1, 8), (1, 11)),
+            Span::new((1, 12), (1, 12)),
+            Span::new((2, 3), (2, 6)),
+        ]);
+        assert_eq!(lexer.fetch_token().span(), Span::new((2, 7), (2, 7)));
+        assert_eq!(lexer.get_line_text(1), Some("rot is 12.5; // note"));
+    }
+}
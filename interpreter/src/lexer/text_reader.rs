@@ -1,9 +1,11 @@
-use std::fs::File;
-use std::io::{BufReader, BufRead};
+use std::io::BufRead;
 // use std::str::Chars;
 
+///输入源，任何实现了BufRead的对象都可以作为源程序
+pub type Source = Box<dyn BufRead>;
+
 pub struct TextReader {
-    aim_file_reader: BufReader<File>,
+    aim_reader: Source,
     line_buffer: Vec<char>,
     //line_buffer的索引，指向当前正在处理的char
     curr_handle_index: usize,
@@ -11,12 +13,15 @@ pub struct TextReader {
     //记录目前读取的位置
     curr_line: u32,
     curr_col: u32,
+
+    //已读入的所有行的原文（不转大写、不含换行符），用于报错时展示源码
+    read_lines: Vec<String>,
 }
 
 impl TextReader {
-    pub fn new(aim_file: File) -> Self {
+    pub fn new(aim_reader: Source) -> Self {
         TextReader {
-            aim_file_reader: BufReader::new(aim_file),
+            aim_reader,
             line_buffer: vec![],
             curr_handle_index: 0,
 
@@ -24,6 +29,8 @@ impl TextReader {
             curr_line: 1,
             //curr_char才应该是当前字符，读完才指向被读的字符
             curr_col: 0,
+
+            read_lines: Vec::new(),
         }
     }
 
@@ -41,7 +48,7 @@ impl TextReader {
         self.curr_handle_index += 1;
 
         //读完了缓存，就再读一行（一直返回None就意味着EOF）
-        if self.get_char() == None {
+        if self.get_char().is_none() {
             let _ = self.read_line();
             self.curr_handle_index = 0;
         }
@@ -68,24 +75,30 @@ impl TextReader {
         (self.curr_line, self.curr_col)
     }
 
+    ///获取已读入的某一行的原文，行号从1开始
+    pub fn get_line_text(&self, line: u32) -> Option<&str> {
+        let index = (line as usize).checked_sub(1)?;
+        self.read_lines.get(index).map(|line_text| line_text.as_str())
+    }
+
     /// 获取当前的char
     /// EOF时返回None
     fn get_char(&self) -> Option<char> {
-        return match self.line_buffer.get(self.curr_handle_index) {
-            Some(ch) => Some(ch.to_uppercase().next().unwrap()), //copy以防止数据被删除
-            None => None
-        };
+        self.line_buffer.get(self.curr_handle_index).map(|ch| ch.to_uppercase().next().unwrap())
     }
 
-    /// 从目标文件中读取一行存入缓存
+    /// 从输入源中读取一行存入缓存
     fn read_line(&mut self) -> Result<(), ()> {
         let mut line = String::new();
-        let size = self.aim_file_reader.read_line(&mut line).unwrap();
+        let size = self.aim_reader.read_line(&mut line).unwrap();
         self.line_buffer = line.chars().collect::<Vec<char>>();
-        return match size {
+        if size > 0 {
+            self.read_lines.push(String::from(line.trim_end_matches(['\r', '\n'])));
+        }
+        match size {
             0 => Err(()),
             _ => Ok(()),
-        };
+        }
     }
 }
 
@@ -136,13 +149,14 @@ impl TextReader {
 #[cfg(test)]
 mod tests {
     use std::fs::File;
+    use std::io::{BufReader, Cursor};
     use super::*;
 
     #[test]
     fn test_continuous_read() {
         let file = File::open("lex_test.txt").unwrap();
 
-        let mut tr = TextReader::new(file);
+        let mut tr = TextReader::new(Box::new(BufReader::new(file)));
 
         tr.eat_char();
         loop {
@@ -155,6 +169,21 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_read_string() {
+        let mut tr = TextReader::new(Box::new(Cursor::new(String::from("ab\nc"))));
+
+        let mut text = String::new();
+        while let Some(ch) = tr.eat_char() {
+            text.push(ch);
+        }
+        assert_eq!(text, "AB\nC");
+        assert_eq!(tr.get_char_position(), (2, 1));
+        assert_eq!(tr.get_line_text(1), Some("ab"));
+        assert_eq!(tr.get_line_text(2), Some("c"));
+        assert_eq!(tr.get_line_text(3), None);
+    }
+
     #[test]
     fn test_read_file() {
         // 打开文件并创建一个 BufReader
@@ -162,11 +191,9 @@ mod tests {
         let reader = BufReader::new(file);
 
         // 逐个字符地读取文件
-        for line in reader.lines() {
-            if let Ok(line) = line {
-                for character in line.chars() {
-                    println!("{}", character);
-                }
+        for line in reader.lines().map_while(Result::ok) {
+            for character in line.chars() {
+                println!("{}", character);
             }
         }
     }
@@ -1,7 +1,35 @@
 use std::collections::hash_map::HashMap;
-use std::rc::Rc;
+use std::sync::Arc;
 use crate::exception;
 
+///函数类型，输入参数列表，输出计算结果。可以在多个线程中同时调用
+pub type TokenFunc = Arc<dyn Fn(&[f64]) -> exception::Result<f64> + Send + Sync>;
+
+///源程序中的一段区间，首尾均为(line,col)，且包含尾字符
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct Span {
+    start: (u32, u32),
+    end: (u32, u32),
+}
+
+impl Span {
+    pub fn new(start: (u32, u32), end: (u32, u32)) -> Self {
+        Self { start, end }
+    }
+
+    pub fn start(&self) -> (u32, u32) {
+        self.start
+    }
+    pub fn end(&self) -> (u32, u32) {
+        self.end
+    }
+
+    ///合并两个区间，得到从self开头到other结尾的区间
+    pub fn merge(&self, other: &Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
 #[derive(Clone)]
 pub struct Token {
     //Token类型
@@ -11,7 +39,9 @@ pub struct Token {
     //数值
     value: f64,
     //函数
-    func: Rc<dyn Fn(&[f64]) -> exception::Result<f64>>,
+    func: TokenFunc,
+    //在源程序中的位置
+    span: Span,
 }
 
 ///用于建造Token（建造者模式）
@@ -23,7 +53,7 @@ pub struct TokenBuilder {
     //数值
     value: Option<f64>,
     //函数
-    func: Option<Rc<dyn Fn(&[f64]) -> exception::Result<f64>>>,
+    func: Option<TokenFunc>,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -40,12 +70,25 @@ pub enum TokenTypeEnum {
     From,
     To,
     Step,
+    Adaptive,
     Draw,
+    Polar,
+    Implicit,
+    Over,
+    Resolution,
     Def,
     Let,
+    Fn,
+    Do,
+    Begin,
+    End,
+    If,
+    Then,
+    Else,
+    Piecewise,
+    Param,
+    Default,
 
-    //for语句固定参数
-    T,
     //变量
     Variable,
 
@@ -57,6 +100,8 @@ pub enum TokenTypeEnum {
 
     //赋值
     Assign,
+    //函数定义中的=>
+    Arrow,
 
     //运算符
     Plus,
@@ -65,6 +110,19 @@ pub enum TokenTypeEnum {
     Div,
     Power,
 
+    //比较运算符
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+
+    //逻辑运算符
+    And,
+    Or,
+    Not,
+
     //函数名
     Func,
     //常数（数值字面量、命名常量）
@@ -76,6 +134,76 @@ pub enum TokenTypeEnum {
     ErrToken,
 }
 
+impl TokenTypeEnum {
+    ///用人类可读的方式描述token类型，用于报错
+    pub fn describe(&self) -> &'static str {
+        match self {
+            TokenTypeEnum::Comment => "a comment",
+            TokenTypeEnum::Origin => "`ORIGIN`",
+            TokenTypeEnum::Scale => "`SCALE`",
+            TokenTypeEnum::Rot => "`ROT`",
+            TokenTypeEnum::Is => "`IS`",
+            TokenTypeEnum::For => "`FOR`",
+            TokenTypeEnum::From => "`FROM`",
+            TokenTypeEnum::To => "`TO`",
+            TokenTypeEnum::Step => "`STEP`",
+            TokenTypeEnum::Adaptive => "`ADAPTIVE`",
+            TokenTypeEnum::Draw => "`DRAW`",
+            TokenTypeEnum::Polar => "`POLAR`",
+            TokenTypeEnum::Implicit => "`IMPLICIT`",
+            TokenTypeEnum::Over => "`OVER`",
+            TokenTypeEnum::Resolution => "`RESOLUTION`",
+            TokenTypeEnum::Def => "`DEF`",
+            TokenTypeEnum::Let => "`LET`",
+            TokenTypeEnum::Fn => "`FN`",
+            TokenTypeEnum::Do => "`DO`",
+            TokenTypeEnum::Begin => "`BEGIN`",
+            TokenTypeEnum::End => "`END`",
+            TokenTypeEnum::If => "`IF`",
+            TokenTypeEnum::Then => "`THEN`",
+            TokenTypeEnum::Else => "`ELSE`",
+            TokenTypeEnum::Piecewise => "`PIECEWISE`",
+            TokenTypeEnum::Param => "`PARAM`",
+            TokenTypeEnum::Default => "`DEFAULT`",
+            TokenTypeEnum::Variable => "a variable",
+            TokenTypeEnum::Semico => "`;`",
+            TokenTypeEnum::LBracket => "`(`",
+            TokenTypeEnum::RBracket => "`)`",
+            TokenTypeEnum::Comma => "`,`",
+            TokenTypeEnum::Assign => "`=`",
+            TokenTypeEnum::Arrow => "`=>`",
+            TokenTypeEnum::Plus => "`+`",
+            TokenTypeEnum::Minus => "`-`",
+            TokenTypeEnum::Mul => "`*`",
+            TokenTypeEnum::Div => "`/`",
+            TokenTypeEnum::Power => "`**`",
+            TokenTypeEnum::Less => "`<`",
+            TokenTypeEnum::LessEqual => "`<=`",
+            TokenTypeEnum::Greater => "`>`",
+            TokenTypeEnum::GreaterEqual => "`>=`",
+            TokenTypeEnum::Equal => "`==`",
+            TokenTypeEnum::NotEqual => "`!=`",
+            TokenTypeEnum::And => "`AND`",
+            TokenTypeEnum::Or => "`OR`",
+            TokenTypeEnum::Not => "`NOT`",
+            TokenTypeEnum::Func => "a function",
+            TokenTypeEnum::ConstId => "a number or constant",
+            TokenTypeEnum::NonToken => "end of file",
+            TokenTypeEnum::ErrToken => "an illegal symbol",
+        }
+    }
+
+    ///把多个期望的token类型连接成"a, b or c"的形式
+    pub fn describe_list(token_types: &[TokenTypeEnum]) -> String {
+        let descriptions: Vec<&str> = token_types.iter().map(|t| t.describe()).collect();
+        match descriptions.split_last() {
+            None => String::new(),
+            Some((last, [])) => String::from(*last),
+            Some((last, rest)) => format!("{} or {}", rest.join(", "), last),
+        }
+    }
+}
+
 impl std::fmt::Debug for Token {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "Token {{")?;
@@ -95,9 +223,12 @@ impl Token {
     pub fn value(&self) -> f64 {
         self.value
     }
-    pub fn func(&self) -> &Rc<dyn Fn(&[f64]) -> exception::Result<f64>> {
+    pub fn func(&self) -> &TokenFunc {
         &self.func
     }
+    pub fn span(&self) -> Span {
+        self.span
+    }
 
     // pub fn set_token_type(&mut self, token_type: TokenTypeEnum) {
     //     self.token_type = token_type;
@@ -109,9 +240,12 @@ impl Token {
     pub fn set_value(&mut self, value: f64) {
         self.value = value;
     }
-    pub fn set_func(&mut self, func: Rc<dyn Fn(&[f64]) -> exception::Result<f64>>) {
+    pub fn set_func(&mut self, func: TokenFunc) {
         self.func = func;
     }
+    pub fn set_span(&mut self, span: Span) {
+        self.span = span;
+    }
 
     //工具函数，判断参数数量是否等同于目标
     pub fn judge_arg_num_equal(func_name: &str, args: &[f64], target_num: usize) -> exception::Result<f64> {
@@ -141,9 +275,24 @@ impl Token {
         string_trans_token_map.insert(String::from("FROM"), TokenBuilder::new().token_type(TokenTypeEnum::From).lexeme("FROM").build());
         string_trans_token_map.insert(String::from("TO"), TokenBuilder::new().token_type(TokenTypeEnum::To).lexeme("TO").build());
         string_trans_token_map.insert(String::from("STEP"), TokenBuilder::new().token_type(TokenTypeEnum::Step).lexeme("STEP").build());
+        string_trans_token_map.insert(String::from("ADAPTIVE"), TokenBuilder::new().token_type(TokenTypeEnum::Adaptive).lexeme("ADAPTIVE").build());
         string_trans_token_map.insert(String::from("DRAW"), TokenBuilder::new().token_type(TokenTypeEnum::Draw).lexeme("DRAW").build());
+        string_trans_token_map.insert(String::from("POLAR"), TokenBuilder::new().token_type(TokenTypeEnum::Polar).lexeme("POLAR").build());
+        string_trans_token_map.insert(String::from("IMPLICIT"), TokenBuilder::new().token_type(TokenTypeEnum::Implicit).lexeme("IMPLICIT").build());
+        string_trans_token_map.insert(String::from("OVER"), TokenBuilder::new().token_type(TokenTypeEnum::Over).lexeme("OVER").build());
+        string_trans_token_map.insert(String::from("RESOLUTION"), TokenBuilder::new().token_type(TokenTypeEnum::Resolution).lexeme("RESOLUTION").build());
         string_trans_token_map.insert(String::from("DEF"), TokenBuilder::new().token_type(TokenTypeEnum::Def).lexeme("DEF").build());
         string_trans_token_map.insert(String::from("LET"), TokenBuilder::new().token_type(TokenTypeEnum::Let).lexeme("LET").build());
+        string_trans_token_map.insert(String::from("FN"), TokenBuilder::new().token_type(TokenTypeEnum::Fn).lexeme("FN").build());
+        string_trans_token_map.insert(String::from("DO"), TokenBuilder::new().token_type(TokenTypeEnum::Do).lexeme("DO").build());
+        string_trans_token_map.insert(String::from("BEGIN"), TokenBuilder::new().token_type(TokenTypeEnum::Begin).lexeme("BEGIN").build());
+        string_trans_token_map.insert(String::from("END"), TokenBuilder::new().token_type(TokenTypeEnum::End).lexeme("END").build());
+        string_trans_token_map.insert(String::from("IF"), TokenBuilder::new().token_type(TokenTypeEnum::If).lexeme("IF").build());
+        string_trans_token_map.insert(String::from("THEN"), TokenBuilder::new().token_type(TokenTypeEnum::Then).lexeme("THEN").build());
+        string_trans_token_map.insert(String::from("ELSE"), TokenBuilder::new().token_type(TokenTypeEnum::Else).lexeme("ELSE").build());
+        string_trans_token_map.insert(String::from("PIECEWISE"), TokenBuilder::new().token_type(TokenTypeEnum::Piecewise).lexeme("PIECEWISE").build());
+        string_trans_token_map.insert(String::from("PARAM"), TokenBuilder::new().token_type(TokenTypeEnum::Param).lexeme("PARAM").build());
+        string_trans_token_map.insert(String::from("DEFAULT"), TokenBuilder::new().token_type(TokenTypeEnum::Default).lexeme("DEFAULT").build());
 
         //分隔符
         string_trans_token_map.insert(String::from(";"), TokenBuilder::new().token_type(TokenTypeEnum::Semico).lexeme(";").build());
@@ -152,83 +301,129 @@ impl Token {
         string_trans_token_map.insert(String::from(","), TokenBuilder::new().token_type(TokenTypeEnum::Comma).lexeme(",").build());
 
         //赋值
-        string_trans_token_map.insert(String::from("="), TokenBuilder::new().token_type(TokenTypeEnum::Assign).lexeme("=").build());
+        string_trans_token_map.insert(String::from("="), TokenBuilder::new().token_type(TokenTypeEnum::Assign).lexeme("=").build());//"=>"前缀
+        string_trans_token_map.insert(String::from("=>"), TokenBuilder::new().token_type(TokenTypeEnum::Arrow).lexeme("=>").build());
 
         //运算符
         string_trans_token_map.insert(String::from("+"), TokenBuilder::new().token_type(TokenTypeEnum::Plus).lexeme("+")
-            .func(Rc::new(|args| {
+            .func(Arc::new(|args| {
                 Token::judge_arg_num_equal("+",args, 2)?;
                 Ok(args[0] + args[1])
             })).build());
         string_trans_token_map.insert(String::from("-"), TokenBuilder::new().token_type(TokenTypeEnum::Minus).lexeme("-")
-            .func(Rc::new(|args| {
+            .func(Arc::new(|args| {
                 Token::judge_arg_num_equal("-",args, 2)?;
                 Ok(args[0] - args[1])
             })).build());
         string_trans_token_map.insert(String::from("*"), TokenBuilder::new().token_type(TokenTypeEnum::Mul).lexeme("*")
-            .func(Rc::new(|args| {
+            .func(Arc::new(|args| {
                 Token::judge_arg_num_equal("*",args, 2)?;
                 Ok(args[0] * args[1])
             })).build());//"**"前缀
         string_trans_token_map.insert(String::from("/"), TokenBuilder::new().token_type(TokenTypeEnum::Div).lexeme("/")
-            .func(Rc::new(|args| {
+            .func(Arc::new(|args| {
                 Token::judge_arg_num_equal("/",args, 2)?;
                 Ok(args[0] / args[1])
             })).build());//"//"前缀
         string_trans_token_map.insert(String::from("**"), TokenBuilder::new().token_type(TokenTypeEnum::Power).lexeme("**")
-            .func(Rc::new(|args| {
+            .func(Arc::new(|args| {
                 Token::judge_arg_num_equal("**",args, 2)?;
                 Ok(args[0].powf(args[1]))
             })).build());
 
+        //比较运算符，结果为1（真）或0（假）
+        string_trans_token_map.insert(String::from("<"), TokenBuilder::new().token_type(TokenTypeEnum::Less).lexeme("<")
+            .func(Arc::new(|args| {
+                Token::judge_arg_num_equal("<",args, 2)?;
+                Ok(f64::from(args[0] < args[1]))
+            })).build());//"<="前缀
+        string_trans_token_map.insert(String::from("<="), TokenBuilder::new().token_type(TokenTypeEnum::LessEqual).lexeme("<=")
+            .func(Arc::new(|args| {
+                Token::judge_arg_num_equal("<=",args, 2)?;
+                Ok(f64::from(args[0] <= args[1]))
+            })).build());
+        string_trans_token_map.insert(String::from(">"), TokenBuilder::new().token_type(TokenTypeEnum::Greater).lexeme(">")
+            .func(Arc::new(|args| {
+                Token::judge_arg_num_equal(">",args, 2)?;
+                Ok(f64::from(args[0] > args[1]))
+            })).build());//">="前缀
+        string_trans_token_map.insert(String::from(">="), TokenBuilder::new().token_type(TokenTypeEnum::GreaterEqual).lexeme(">=")
+            .func(Arc::new(|args| {
+                Token::judge_arg_num_equal(">=",args, 2)?;
+                Ok(f64::from(args[0] >= args[1]))
+            })).build());
+        string_trans_token_map.insert(String::from("=="), TokenBuilder::new().token_type(TokenTypeEnum::Equal).lexeme("==")
+            .func(Arc::new(|args| {
+                Token::judge_arg_num_equal("==",args, 2)?;
+                Ok(f64::from(args[0] == args[1]))
+            })).build());
+        string_trans_token_map.insert(String::from("!="), TokenBuilder::new().token_type(TokenTypeEnum::NotEqual).lexeme("!=")
+            .func(Arc::new(|args| {
+                Token::judge_arg_num_equal("!=",args, 2)?;
+                Ok(f64::from(args[0] != args[1]))
+            })).build());
+
+        //逻辑运算符。需要短路求值，由语法树节点直接实现
+        string_trans_token_map.insert(String::from("AND"), TokenBuilder::new().token_type(TokenTypeEnum::And).lexeme("AND").build());
+        string_trans_token_map.insert(String::from("OR"), TokenBuilder::new().token_type(TokenTypeEnum::Or).lexeme("OR").build());
+        string_trans_token_map.insert(String::from("NOT"), TokenBuilder::new().token_type(TokenTypeEnum::Not).lexeme("NOT").build());
+
         //函数名
         string_trans_token_map.insert(String::from("SIN"), TokenBuilder::new().token_type(TokenTypeEnum::Func).lexeme("SIN")
-            .func(Rc::new(|args| {
+            .func(Arc::new(|args| {
                 Token::judge_arg_num_equal("SIN",args, 1)?;
                 Ok(args[0].sin())
             })).build());
         string_trans_token_map.insert(String::from("COS"), TokenBuilder::new().token_type(TokenTypeEnum::Func).lexeme("COS")
-            .func(Rc::new(|args| {
+            .func(Arc::new(|args| {
                 Token::judge_arg_num_equal("COS",args, 1)?;
                 Ok(args[0].cos())
             })).build());
         string_trans_token_map.insert(String::from("TAN"), TokenBuilder::new().token_type(TokenTypeEnum::Func).lexeme("TAN")
-            .func(Rc::new(|args| {
+            .func(Arc::new(|args| {
                 Token::judge_arg_num_equal("TAN",args, 1)?;
                 Ok(args[0].tan())
             })).build());
         string_trans_token_map.insert(String::from("LN"), TokenBuilder::new().token_type(TokenTypeEnum::Func).lexeme("LN")
-            .func(Rc::new(|args| {
+            .func(Arc::new(|args| {
                 Token::judge_arg_num_equal("LN",args, 1)?;
                 Ok(args[0].ln())
             })).build());
         string_trans_token_map.insert(String::from("EXP"), TokenBuilder::new().token_type(TokenTypeEnum::Func).lexeme("EXP")
-            .func(Rc::new(|args| {
+            .func(Arc::new(|args| {
                 Token::judge_arg_num_equal("EXP",args, 1)?;
                 Ok(args[0].exp())
             })).build());
         string_trans_token_map.insert(String::from("SQRT"), TokenBuilder::new().token_type(TokenTypeEnum::Func).lexeme("SQRT")
-            .func(Rc::new(|args| {
+            .func(Arc::new(|args| {
                 Token::judge_arg_num_equal("SQRT",args, 1)?;
                 Ok(args[0].sqrt())
             })).build());
         string_trans_token_map.insert(String::from("ABS"), TokenBuilder::new().token_type(TokenTypeEnum::Func).lexeme("ABS")
-            .func(Rc::new(|args| {
+            .func(Arc::new(|args| {
                 Token::judge_arg_num_equal("ABS",args, 1)?;
                 Ok(args[0].abs())
             })).build());
         string_trans_token_map.insert(String::from("MAX"), TokenBuilder::new().token_type(TokenTypeEnum::Func).lexeme("MAX")
-            .func(Rc::new(|args| {
-                Token::judge_arg_num_equal("MAX",args, 2)?;
-                Ok(args[0].max(args[1]))
+            .func(Arc::new(|args| {
+                Token::judge_arg_num_least("MAX",args, 1)?;
+                //f64::max会忽略NaN，这里有参数为NaN时结果也为NaN
+                if args.iter().any(|arg| arg.is_nan()) {
+                    return Ok(f64::NAN);
+                }
+                Ok(args[1..].iter().fold(args[0], |ans, arg| ans.max(*arg)))
             })).build());
         string_trans_token_map.insert(String::from("MIN"), TokenBuilder::new().token_type(TokenTypeEnum::Func).lexeme("MIN")
-            .func(Rc::new(|args| {
-                Token::judge_arg_num_equal("MIN",args, 2)?;
-                Ok(args[0].min(args[1]))
+            .func(Arc::new(|args| {
+                Token::judge_arg_num_least("MIN",args, 1)?;
+                //f64::min会忽略NaN，这里有参数为NaN时结果也为NaN
+                if args.iter().any(|arg| arg.is_nan()) {
+                    return Ok(f64::NAN);
+                }
+                Ok(args[1..].iter().fold(args[0], |ans, arg| ans.min(*arg)))
             })).build());
         string_trans_token_map.insert(String::from("AVER"), TokenBuilder::new().token_type(TokenTypeEnum::Func).lexeme("AVER")
-            .func(Rc::new(|args| {
+            .func(Arc::new(|args| {
                 Token::judge_arg_num_least("AVER",args, 1)?;
                 let mut ans = 0.0;
                 for arg in args {
@@ -238,14 +433,152 @@ impl Token {
                 Ok(ans)
             })).build());
 
-        //参数
-        string_trans_token_map.insert(String::from("T"), TokenBuilder::new().token_type(TokenTypeEnum::T).lexeme("T").build());
+
+        //反三角函数与双曲函数
+        string_trans_token_map.insert(String::from("ASIN"), TokenBuilder::new().token_type(TokenTypeEnum::Func).lexeme("ASIN")
+            .func(Arc::new(|args| {
+                Token::judge_arg_num_equal("ASIN",args, 1)?;
+                Ok(args[0].asin())
+            })).build());
+        string_trans_token_map.insert(String::from("ACOS"), TokenBuilder::new().token_type(TokenTypeEnum::Func).lexeme("ACOS")
+            .func(Arc::new(|args| {
+                Token::judge_arg_num_equal("ACOS",args, 1)?;
+                Ok(args[0].acos())
+            })).build());
+        string_trans_token_map.insert(String::from("ATAN"), TokenBuilder::new().token_type(TokenTypeEnum::Func).lexeme("ATAN")
+            .func(Arc::new(|args| {
+                Token::judge_arg_num_equal("ATAN",args, 1)?;
+                Ok(args[0].atan())
+            })).build());
+        string_trans_token_map.insert(String::from("ATAN2"), TokenBuilder::new().token_type(TokenTypeEnum::Func).lexeme("ATAN2")
+            .func(Arc::new(|args| {
+                Token::judge_arg_num_equal("ATAN2",args, 2)?;
+                Ok(args[0].atan2(args[1]))
+            })).build());
+        string_trans_token_map.insert(String::from("SINH"), TokenBuilder::new().token_type(TokenTypeEnum::Func).lexeme("SINH")
+            .func(Arc::new(|args| {
+                Token::judge_arg_num_equal("SINH",args, 1)?;
+                Ok(args[0].sinh())
+            })).build());
+        string_trans_token_map.insert(String::from("COSH"), TokenBuilder::new().token_type(TokenTypeEnum::Func).lexeme("COSH")
+            .func(Arc::new(|args| {
+                Token::judge_arg_num_equal("COSH",args, 1)?;
+                Ok(args[0].cosh())
+            })).build());
+        string_trans_token_map.insert(String::from("TANH"), TokenBuilder::new().token_type(TokenTypeEnum::Func).lexeme("TANH")
+            .func(Arc::new(|args| {
+                Token::judge_arg_num_equal("TANH",args, 1)?;
+                Ok(args[0].tanh())
+            })).build());
+
+        //对数
+        string_trans_token_map.insert(String::from("LOG10"), TokenBuilder::new().token_type(TokenTypeEnum::Func).lexeme("LOG10")
+            .func(Arc::new(|args| {
+                Token::judge_arg_num_equal("LOG10",args, 1)?;
+                Ok(args[0].log10())
+            })).build());
+        string_trans_token_map.insert(String::from("LOG2"), TokenBuilder::new().token_type(TokenTypeEnum::Func).lexeme("LOG2")
+            .func(Arc::new(|args| {
+                Token::judge_arg_num_equal("LOG2",args, 1)?;
+                Ok(args[0].log2())
+            })).build());
+        string_trans_token_map.insert(String::from("LOG"), TokenBuilder::new().token_type(TokenTypeEnum::Func).lexeme("LOG")
+            .func(Arc::new(|args| {
+                Token::judge_arg_num_equal("LOG",args, 2)?;
+                Ok(args[1].log(args[0]))
+            })).build());
+
+        //取整与符号
+        string_trans_token_map.insert(String::from("FLOOR"), TokenBuilder::new().token_type(TokenTypeEnum::Func).lexeme("FLOOR")
+            .func(Arc::new(|args| {
+                Token::judge_arg_num_equal("FLOOR",args, 1)?;
+                Ok(args[0].floor())
+            })).build());
+        string_trans_token_map.insert(String::from("CEIL"), TokenBuilder::new().token_type(TokenTypeEnum::Func).lexeme("CEIL")
+            .func(Arc::new(|args| {
+                Token::judge_arg_num_equal("CEIL",args, 1)?;
+                Ok(args[0].ceil())
+            })).build());
+        string_trans_token_map.insert(String::from("ROUND"), TokenBuilder::new().token_type(TokenTypeEnum::Func).lexeme("ROUND")
+            .func(Arc::new(|args| {
+                Token::judge_arg_num_equal("ROUND",args, 1)?;
+                Ok(args[0].round())
+            })).build());
+        string_trans_token_map.insert(String::from("TRUNC"), TokenBuilder::new().token_type(TokenTypeEnum::Func).lexeme("TRUNC")
+            .func(Arc::new(|args| {
+                Token::judge_arg_num_equal("TRUNC",args, 1)?;
+                Ok(args[0].trunc())
+            })).build());
+        string_trans_token_map.insert(String::from("SIGN"), TokenBuilder::new().token_type(TokenTypeEnum::Func).lexeme("SIGN")
+            .func(Arc::new(|args| {
+                Token::judge_arg_num_equal("SIGN",args, 1)?;
+                Ok(sign(args[0]))
+            })).build());
+        string_trans_token_map.insert(String::from("MOD"), TokenBuilder::new().token_type(TokenTypeEnum::Func).lexeme("MOD")
+            .func(Arc::new(|args| {
+                Token::judge_arg_num_equal("MOD",args, 2)?;
+                Ok(floored_mod(args[0], args[1]))
+            })).build());
+        string_trans_token_map.insert(String::from("HYPOT"), TokenBuilder::new().token_type(TokenTypeEnum::Func).lexeme("HYPOT")
+            .func(Arc::new(|args| {
+                Token::judge_arg_num_equal("HYPOT",args, 2)?;
+                Ok(args[0].hypot(args[1]))
+            })).build());
+
+        //变参求和、求积
+        string_trans_token_map.insert(String::from("SUM"), TokenBuilder::new().token_type(TokenTypeEnum::Func).lexeme("SUM")
+            .func(Arc::new(|args| {
+                Token::judge_arg_num_least("SUM",args, 1)?;
+                Ok(args.iter().sum())
+            })).build());
+        string_trans_token_map.insert(String::from("PROD"), TokenBuilder::new().token_type(TokenTypeEnum::Func).lexeme("PROD")
+            .func(Arc::new(|args| {
+                Token::judge_arg_num_least("PROD",args, 1)?;
+                Ok(args.iter().product())
+            })).build());
+
+        //插值与限制范围
+        string_trans_token_map.insert(String::from("CLAMP"), TokenBuilder::new().token_type(TokenTypeEnum::Func).lexeme("CLAMP")
+            .func(Arc::new(|args| {
+                Token::judge_arg_num_equal("CLAMP",args, 3)?;
+                //与MAX、MIN一致，有参数为NaN时结果为NaN
+                if args.iter().any(|arg| arg.is_nan()) {
+                    return Ok(f64::NAN);
+                }
+                if args[1] > args[2] {
+                    return Err(exception::InvalidArgumentError::new(
+                        "CLAMP", &format!("lower bound {} is greater than upper bound {}", args[1], args[2]),
+                    ));
+                }
+                Ok(args[0].clamp(args[1], args[2]))
+            })).build());
+        string_trans_token_map.insert(String::from("LERP"), TokenBuilder::new().token_type(TokenTypeEnum::Func).lexeme("LERP")
+            .func(Arc::new(|args| {
+                Token::judge_arg_num_equal("LERP",args, 3)?;
+                Ok(args[0] + (args[1] - args[0]) * args[2])
+            })).build());
+
+        //阶乘与伽马函数
+        string_trans_token_map.insert(String::from("FACTORIAL"), TokenBuilder::new().token_type(TokenTypeEnum::Func).lexeme("FACTORIAL")
+            .func(Arc::new(|args| {
+                Token::judge_arg_num_equal("FACTORIAL",args, 1)?;
+                Ok(factorial(args[0]))
+            })).build());
+        string_trans_token_map.insert(String::from("GAMMA"), TokenBuilder::new().token_type(TokenTypeEnum::Func).lexeme("GAMMA")
+            .func(Arc::new(|args| {
+                Token::judge_arg_num_equal("GAMMA",args, 1)?;
+                Ok(gamma(args[0]))
+            })).build());
 
         //常数
         string_trans_token_map.insert(String::from("PI"), TokenBuilder::new().token_type(TokenTypeEnum::ConstId).lexeme("PI")
             .value(std::f64::consts::PI).build());
         string_trans_token_map.insert(String::from("E"), TokenBuilder::new().token_type(TokenTypeEnum::ConstId).lexeme("E")
             .value(std::f64::consts::E).build());//"EXP"前缀
+        string_trans_token_map.insert(String::from("TAU"), TokenBuilder::new().token_type(TokenTypeEnum::ConstId).lexeme("TAU")
+            .value(std::f64::consts::TAU).build());
+        string_trans_token_map.insert(String::from("PHI"), TokenBuilder::new().token_type(TokenTypeEnum::ConstId).lexeme("PHI")
+            .value(GOLDEN_RATIO).build());
 
         //注释
         string_trans_token_map.insert(String::from("//"), TokenBuilder::new().token_type(TokenTypeEnum::Comment).lexeme("//").build());//
@@ -265,6 +598,74 @@ impl Token {
     }
 }
 
+//黄金分割比
+const GOLDEN_RATIO: f64 = 1.618_033_988_749_895;
+
+//Lanczos近似的参数（g = 7，9项）
+const LANCZOS_G: f64 = 7.0;
+const LANCZOS_COEFFICIENTS: [f64; 9] = [
+    0.999_999_999_999_809_9,
+    676.520_368_121_885_1,
+    -1_259.139_216_722_402_8,
+    771.323_428_777_653_1,
+    -176.615_029_162_140_6,
+    12.507_343_278_686_905,
+    -0.138_571_095_265_720_12,
+    9.984_369_578_019_572e-6,
+    1.505_632_735_149_311_6e-7,
+];
+
+///伽马函数，使用Lanczos近似。x < 0.5时使用反射公式，0与负整数处为NaN
+fn gamma(x: f64) -> f64 {
+    if x <= 0.0 && x == x.floor() {
+        return f64::NAN;
+    }
+    if x == f64::INFINITY {
+        return x;
+    }
+    if x < 0.5 {
+        return std::f64::consts::PI / ((std::f64::consts::PI * x).sin() * gamma(1.0 - x));
+    }
+    let x = x - 1.0;
+    let mut sum = LANCZOS_COEFFICIENTS[0];
+    for (i, coefficient) in LANCZOS_COEFFICIENTS.iter().enumerate().skip(1) {
+        sum += coefficient / (x + i as f64);
+    }
+    let t = x + LANCZOS_G + 0.5;
+    //t ** (x + 0.5)分两半计算，避免x较大时先于exp(-t)溢出
+    let half_power = t.powf((x + 0.5) / 2.0);
+    (2.0 * std::f64::consts::PI).sqrt() * half_power * ((-t).exp() * half_power) * sum
+}
+
+///阶乘。不超过170的非负整数直接连乘以保证精确，其他值为gamma(n + 1)
+fn factorial(n: f64) -> f64 {
+    if (0.0..=170.0).contains(&n) && n == n.floor() {
+        return (1..=n as u32).fold(1.0, |ans, i| ans * f64::from(i));
+    }
+    gamma(n + 1.0)
+}
+
+///符号函数，0（包括-0）为0，NaN为NaN
+fn sign(x: f64) -> f64 {
+    if x > 0.0 {
+        1.0
+    } else if x < 0.0 {
+        -1.0
+    } else {
+        x
+    }
+}
+
+///向下取整的取模，结果与除数同号
+fn floored_mod(a: f64, b: f64) -> f64 {
+    let remainder = a % b;
+    if remainder != 0.0 && (remainder < 0.0) != (b < 0.0) {
+        remainder + b
+    } else {
+        remainder
+    }
+}
+
 impl TokenBuilder {
     pub fn new() -> Self {
         TokenBuilder {
@@ -290,7 +691,7 @@ impl TokenBuilder {
         self
     }
 
-    pub fn func(mut self, func: Rc<dyn Fn(&[f64]) -> exception::Result<f64>>) -> Self {
+    pub fn func(mut self, func: TokenFunc) -> Self {
         self.func = Some(func);
         self
     }
@@ -300,7 +701,8 @@ impl TokenBuilder {
             token_type: self.token_type.unwrap(),
             lexeme: self.lexeme.unwrap(),
             value: 0.0,
-            func: Rc::new(|_args| { Ok(0.0) }),
+            func: Arc::new(|_args| { Ok(0.0) }),
+            span: Span::default(),
         };
 
         if let Some(value) = self.value {
@@ -315,5 +717,106 @@ impl TokenBuilder {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use crate::exception::ExceptionTrait;
+    use super::*;
+
+    fn call(name: &str, args: &[f64]) -> exception::Result<f64> {
+        let token_match_map = Token::generate_token_match_map();
+        (token_match_map[name].func())(args)
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() <= 1e-12 * expected.abs().max(1.0), "{} != {}", actual, expected);
+    }
 
+    #[test]
+    fn test_trigonometric_functions() {
+        assert_close(call("ASIN", &[1.0]).unwrap(), std::f64::consts::FRAC_PI_2);
+        assert_close(call("ACOS", &[-1.0]).unwrap(), std::f64::consts::PI);
+        assert_close(call("ATAN", &[1.0]).unwrap(), std::f64::consts::FRAC_PI_4);
+        assert_close(call("ATAN2", &[1.0, -1.0]).unwrap(), 3.0 * std::f64::consts::FRAC_PI_4);
+        assert_close(call("SINH", &[1.0]).unwrap(), (std::f64::consts::E - 1.0 / std::f64::consts::E) / 2.0);
+        assert_close(call("COSH", &[0.0]).unwrap(), 1.0);
+        assert_close(call("TANH", &[0.5]).unwrap(), 0.5_f64.tanh());
+        assert!(call("ASIN", &[2.0]).unwrap().is_nan());
+    }
+
+    #[test]
+    fn test_logarithm_and_rounding_functions() {
+        assert_close(call("LOG10", &[1000.0]).unwrap(), 3.0);
+        assert_close(call("LOG2", &[0.125]).unwrap(), -3.0);
+        assert_close(call("LOG", &[3.0, 81.0]).unwrap(), 4.0);
+        assert_eq!(call("FLOOR", &[-1.5]).unwrap(), -2.0);
+        assert_eq!(call("CEIL", &[-1.5]).unwrap(), -1.0);
+        assert_eq!(call("ROUND", &[2.5]).unwrap(), 3.0);
+        assert_eq!(call("TRUNC", &[-2.7]).unwrap(), -2.0);
+        assert_eq!(call("SIGN", &[-3.0]).unwrap(), -1.0);
+        assert_eq!(call("SIGN", &[0.0]).unwrap(), 0.0);
+        assert!(call("SIGN", &[f64::NAN]).unwrap().is_nan());
+        assert_eq!(call("MOD", &[7.0, 3.0]).unwrap(), 1.0);
+        assert_eq!(call("MOD", &[-7.0, 3.0]).unwrap(), 2.0);
+        assert_eq!(call("MOD", &[7.0, -3.0]).unwrap(), -2.0);
+        assert!(call("MOD", &[1.0, 0.0]).unwrap().is_nan());
+        assert_eq!(call("HYPOT", &[3.0, 4.0]).unwrap(), 5.0);
+    }
 
+    #[test]
+    fn test_variadic_functions() {
+        assert_eq!(call("MAX", &[1.0, 5.0, -2.0]).unwrap(), 5.0);
+        assert_eq!(call("MIN", &[1.0, 5.0, -2.0]).unwrap(), -2.0);
+        assert_eq!(call("MAX", &[4.0]).unwrap(), 4.0);
+        assert!(call("MAX", &[1.0, f64::NAN, 5.0]).unwrap().is_nan());
+        assert!(call("MIN", &[f64::NAN, 1.0]).unwrap().is_nan());
+        assert_eq!(call("SUM", &[1.0, 2.0, 3.5]).unwrap(), 6.5);
+        assert_eq!(call("PROD", &[2.0, 3.0, 4.0]).unwrap(), 24.0);
+        assert_eq!(call("CLAMP", &[5.0, 0.0, 2.0]).unwrap(), 2.0);
+        assert_eq!(call("CLAMP", &[-1.0, 0.0, 2.0]).unwrap(), 0.0);
+        assert_eq!(call("CLAMP", &[1.0, 1.0, 1.0]).unwrap(), 1.0);
+        //有参数为NaN时结果为NaN，下界大于上界时报错
+        for args in [[f64::NAN, 0.0, 5.0], [1.0, f64::NAN, 5.0], [1.0, 0.0, f64::NAN]] {
+            assert!(call("CLAMP", &args).unwrap().is_nan());
+        }
+        let error = call("CLAMP", &[1.0, 3.0, 2.0]).unwrap_err();
+        assert_eq!(error.kind(), exception::ErrorKind::InvalidArgumentError);
+        assert_eq!(error.message(), "invalid argument(s) for function `CLAMP`: lower bound 3 is greater than upper bound 2");
+        assert_eq!(call("LERP", &[2.0, 6.0, 0.25]).unwrap(), 3.0);
+    }
+
+    #[test]
+    fn test_gamma_functions() {
+        assert_eq!(call("FACTORIAL", &[0.0]).unwrap(), 1.0);
+        assert_eq!(call("FACTORIAL", &[10.0]).unwrap(), 3_628_800.0);
+        assert!(call("FACTORIAL", &[170.0]).unwrap().is_finite());
+        assert_eq!(call("FACTORIAL", &[171.0]).unwrap(), f64::INFINITY);
+        let sqrt_pi = std::f64::consts::PI.sqrt();
+        assert_close(call("FACTORIAL", &[0.5]).unwrap(), sqrt_pi / 2.0);
+        assert_close(call("GAMMA", &[0.5]).unwrap(), sqrt_pi);
+        assert_close(call("GAMMA", &[5.0]).unwrap(), 24.0);
+        assert_close(call("GAMMA", &[-0.5]).unwrap(), -2.0 * sqrt_pi);
+        assert_close(call("GAMMA", &[100.0]).unwrap(), factorial(99.0));
+        assert!(call("GAMMA", &[-2.0]).unwrap().is_nan());
+        assert!(call("FACTORIAL", &[-1.0]).unwrap().is_nan());
+    }
+
+    #[test]
+    fn test_function_argument_number() {
+        for (name, args) in [("ATAN2", &[1.0][..]), ("LOG", &[2.0]), ("MOD", &[1.0, 2.0, 3.0]), ("CLAMP", &[1.0, 2.0]),
+                             ("LERP", &[]), ("FLOOR", &[1.0, 2.0]), ("GAMMA", &[])] {
+            let error = call(name, args).unwrap_err();
+            assert_eq!(error.kind(), exception::ErrorKind::ArgumentNumberNotMatchError, "{}", name);
+        }
+        for name in ["MAX", "MIN", "SUM", "PROD"] {
+            assert!(call(name, &[]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_constants() {
+        let token_match_map = Token::generate_token_match_map();
+        assert_eq!(token_match_map["TAU"].value(), 2.0 * std::f64::consts::PI);
+        assert_close(token_match_map["PHI"].value(), (1.0 + 5.0_f64.sqrt()) / 2.0);
+        assert_eq!(token_match_map["PHI"].token_type(), TokenTypeEnum::ConstId);
+    }
+}
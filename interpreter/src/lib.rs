@@ -1,8 +1,19 @@
 use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor, Read};
 
 mod lexer;
 mod parser;
 mod exception;
+mod options;
+
+pub use exception::{ErrorKind, InterpretError, InterpretWarning, RegistrationError, WarningKind};
+pub use options::{AdaptiveOptions, CancellationToken, InterpreterOptions, ResourceLimits};
+pub use parser::{
+    Assignment, CoordinateSystem, Expression, ForBody, ForStatement, FunctionDefinition, ImplicitStatement,
+    InterpretOutput, ParameterDeclaration, ParameterStatement, PointSeries, Program, ProgramStatement, SeriesKind,
+    Statement, Transform,
+};
+pub use lexer::{Span, Token, TokenTypeEnum};
 
 pub struct Interpreter {
     parser: parser::ParserManager,
@@ -10,31 +21,987 @@ pub struct Interpreter {
 
 impl Interpreter {
     pub fn new(file: File) -> Self {
+        Self::from_buf_reader(BufReader::new(file))
+    }
+
+    ///从任意实现了Read的输入源读取程序，如标准输入、网络流
+    pub fn from_reader<R: Read + 'static>(reader: R) -> Self {
+        Self::from_buf_reader(BufReader::new(reader))
+    }
+
+    ///从已带缓冲的输入源读取程序
+    pub fn from_buf_reader<R: BufRead + 'static>(reader: R) -> Self {
         Interpreter {
-            parser: parser::ParserManager::new(file),
+            parser: parser::ParserManager::new(Box::new(reader)),
         }
     }
 
+    ///直接解释内存中的程序文本
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(source: &str) -> Self {
+        Self::from_buf_reader(Cursor::new(String::from(source)))
+    }
+
     pub fn set_coordinate_range(&mut self, min_x: f64, max_x: f64, min_y: f64, max_y: f64) {
         self.parser.set_coordinate_range(min_x, max_x, min_y, max_y);
     }
 
-    ///开始解释，返回结果集
-    pub fn interpret(&mut self) -> Result<Vec<(f64, f64)>, ()> {
+    ///注册自定义函数，在表达式中以函数名调用，参数数量必须等于arity。名字不区分大小写，
+    ///不能与保留字、内置函数、内置常数、已注册的名字以及T、X、Y相同，且要在分析源程序之前注册。
+    ///函数应当只依赖参数：参数都是常数的调用会被提前求值，多线程绘制时也会在工作线程中调用
+    pub fn register_function<F>(&mut self, name: &str, arity: usize, func: F) -> Result<(), RegistrationError>
+    where F: Fn(&[f64]) -> f64 + Send + Sync + 'static {
+        self.parser.register_function(name, arity, func)
+    }
+
+    ///注册自定义常数，名字的限制与register_function相同
+    pub fn register_constant(&mut self, name: &str, value: f64) -> Result<(), RegistrationError> {
+        self.parser.register_constant(name, value)
+    }
+
+    ///设置外部参数，在表达式中以参数名引用，优先于PARAM语句的默认值。参数名不区分大小写。
+    ///参数必须由PARAM声明：会先分析源程序（因此要在注册函数、常数之后调用），名字非法或没有声明时返回错误。
+    ///两次执行之间可以修改参数的值
+    pub fn set_parameter(&mut self, name: &str, value: f64) -> Result<(), RegistrationError> {
+        self.parser.set_parameter(name, value)
+    }
+
+    ///程序用PARAM声明的参数，按声明顺序排列。需要先分析源程序，有分析错误时返回所有错误
+    pub fn parameters(&mut self) -> Result<Vec<ParameterDeclaration>, Vec<InterpretError>> {
+        self.parse().map(|program| program.parameters())
+    }
+
+    ///设置解释器选项，控制调试信息的输出
+    pub fn set_options(&mut self, options: InterpreterOptions) {
+        self.parser.set_options(options);
+    }
+
+    ///设置资源限制：总点数、执行的语句数、递归深度、超时与取消标记。超过后立即停止解释。
+    ///会替换之前的所有限制，包括set_max_recursion_depth设置的递归深度
+    pub fn set_resource_limits(&mut self, resource_limits: ResourceLimits) {
+        self.parser.set_resource_limits(resource_limits);
+    }
+
+    ///interpret出错后，取出出错前已经生成的点序列与警告
+    pub fn take_partial_output(&mut self) -> Option<InterpretOutput> {
+        self.parser.take_partial_output()
+    }
+
+    ///设置每条FOR语句的最大迭代次数，超过后产生运行时错误
+    pub fn set_max_loop_iterations(&mut self, max_loop_iterations: usize) {
+        self.parser.set_max_loop_iterations(max_loop_iterations);
+    }
+
+    ///设置ADAPTIVE采样的最大细分层数、点数预算与距离容差
+    pub fn set_adaptive_options(&mut self, adaptive_options: AdaptiveOptions) {
+        self.parser.set_adaptive_options(adaptive_options);
+    }
+
+    ///是否把循环中反复计算的表达式编译为字节码，由栈式虚拟机执行。默认开启，关闭时直接遍历语法树求值
+    pub fn set_compile_expressions(&mut self, compile_expressions: bool) {
+        self.parser.set_compile_expressions(compile_expressions);
+    }
+
+    ///是否在执行循环前化简表达式：折叠常数子树、消去恒等运算，并提前计算不依赖循环变量的子树。默认开启
+    pub fn set_optimize_expressions(&mut self, optimize_expressions: bool) {
+        self.parser.set_optimize_expressions(optimize_expressions);
+    }
+
+    ///设置计算FOR DRAW的点时最多使用的线程数，默认为1。
+    ///只有表达式编译为字节码、迭代次数足够多时才会使用多个线程，点的顺序、警告与错误都与单线程时相同
+    pub fn set_worker_threads(&mut self, worker_threads: usize) {
+        self.parser.set_worker_threads(worker_threads);
+    }
+
+    ///设置用户函数（FN）的最大递归深度，超过后产生运行时错误。
+    ///等价于在当前的资源限制上设置max_recursion_depth，其他限制不变
+    pub fn set_max_recursion_depth(&mut self, max_recursion_depth: usize) {
+        self.parser.set_max_recursion_depth(max_recursion_depth);
+    }
+
+    ///设置最多报告的错误数量。达到该数量后停止解释
+    pub fn set_max_error_count(&mut self, max_error_count: usize) {
+        self.parser.set_max_error_count(max_error_count);
+    }
+
+    ///设置是否把警告提升为错误
+    pub fn set_warnings_as_errors(&mut self, warnings_as_errors: bool) {
+        self.parser.set_warnings_as_errors(warnings_as_errors);
+    }
+
+    ///开始解释，返回结果集与警告。
+    ///出错时跳过出错的语句继续解释，最后返回所有InterpretError，是否打印由调用者决定。
+    ///源程序只在第一次调用时分析，之后每次调用都重新执行分析得到的程序，两次调用之间可以修改坐标范围等设置
+    pub fn interpret(&mut self) -> Result<InterpretOutput, Vec<InterpretError>> {
         self.parser.parse()
     }
+
+    ///只分析源程序、不执行，得到可以多次执行的程序。有语法或语义错误时返回所有错误。
+    ///与interpret共用同一次分析的结果，可以在interpret之前或之后调用
+    pub fn parse(&mut self) -> Result<Program, Vec<InterpretError>> {
+        let (program, errors) = self.parser.parse_program();
+        if !errors.is_empty() {
+            return Err(errors.into_iter().map(|(_, e)| e).collect());
+        }
+        Ok(program)
+    }
+
+    ///执行本解释器分析得到的程序。每次执行都从默认的坐标变换开始、重新生成所有点序列，
+    ///两次执行之间可以修改坐标范围、资源限制等设置。其他解释器分析得到的程序报ForeignProgramError
+    pub fn execute(&mut self, program: &Program) -> Result<InterpretOutput, Vec<InterpretError>> {
+        self.parser.execute_program(program, Vec::new())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    ///警告的种类与消息，用于比较两次输出的警告
+    fn warning_messages(output: &InterpretOutput) -> Vec<(WarningKind, String)> {
+        output.warnings().iter().map(|w| (w.kind(), String::from(w.message()))).collect()
+    }
+
     #[test]
     fn test_interpret() {
         let file = File::open("parse_test.txt").unwrap();
         let mut interpreter_obj = Interpreter::new(file);
         // interpreter_obj.set_coordinate_range(-100.0, 200.0, -100.0, 200.0);
-        let point_result = interpreter_obj.interpret().unwrap();
+        let point_result = interpreter_obj.interpret().unwrap().into_points();
         println!("point_result: {:?}",point_result);
     }
+
+    #[test]
+    fn test_interpret_from_str() {
+        let mut interpreter_obj = Interpreter::from_str("For T from 1 to 3 step 1 draw(T, 2*T);");
+        let point_result = interpreter_obj.interpret().unwrap().into_points();
+        assert_eq!(point_result, vec![(1.0, 2.0), (2.0, 4.0), (3.0, 6.0)]);
+    }
+
+    #[test]
+    fn test_interpret_series() {
+        let mut interpreter_obj = Interpreter::from_str(
+            "For T from 0 to 1 step 1 draw(T, T);\nOrigin is (1, 2);\nRot is PI;\nFor T from 1 to 2 step 0.5 draw(T, 0);"
+        );
+        let output = interpreter_obj.interpret().unwrap();
+        let series = output.series();
+        assert_eq!(series.len(), 2);
+
+        assert_eq!(series[0].span(), Span::new((1, 1), (1, 35)));
+        assert_eq!(series[0].t_range(), (0.0, 1.0));
+        assert_eq!(series[0].t_step(), 1.0);
+        assert_eq!(series[0].transform().origin(), (0.0, 0.0));
+        assert_eq!(series[0].points(), &[(0.0, 0.0), (1.0, 1.0)]);
+
+        assert_eq!(series[1].span().start(), (4, 1));
+        assert_eq!(series[1].t_range(), (1.0, 2.0));
+        assert_eq!(series[1].t_step(), 0.5);
+        assert_eq!(series[1].transform().origin(), (1.0, 2.0));
+        assert_eq!(series[1].transform().scale(), (1.0, 1.0));
+        assert_eq!(series[1].transform().rot(), std::f64::consts::PI);
+        assert_eq!(series[1].points().len(), 3);
+
+        //展平后保持语句顺序
+        let points = output.points();
+        assert_eq!(points.len(), 5);
+        assert_eq!(points[..2], [(0.0, 0.0), (1.0, 1.0)]);
+    }
+
+    #[test]
+    fn test_interpret_loop_variable() {
+        let mut interpreter_obj = Interpreter::from_str(
+            "Def u = 100;\nDef x = 2*T;\nFor u from 1 to 2 step 1 draw(u, u*10);\nFor T from 1 to 2 step 1 draw(x, u);"
+        );
+        let output = interpreter_obj.interpret().unwrap();
+        //循环变量在DRAW中遮蔽同名变量；默认循环变量T可以在DEF中使用
+        assert_eq!(output.points(), vec![(1.0, 10.0), (2.0, 20.0), (2.0, 100.0), (4.0, 100.0)]);
+        assert_eq!(output.series()[0].loop_variable(), "U");
+        assert_eq!(output.series()[1].loop_variable(), "T");
+
+        //循环变量只在循环体中可见
+        let mut interpreter_obj = Interpreter::from_str(
+            "For theta from 0 to 1 step 1 draw(theta, theta);\nRot is theta;"
+        );
+        let errors = interpreter_obj.interpret().unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind(), ErrorKind::UndefinedVariableError);
+        assert_eq!(errors[0].position(), (2, 8));
+    }
+
+    #[test]
+    fn test_interpret_block() {
+        let mut interpreter_obj = Interpreter::from_str("\
+For a from 1 to 2 step 1 do begin
+    Origin is (0, a*10);
+    For T from 0 to 1 step 1 draw(T, a);
+    Draw(a, 0);
+    For i from 0 to 1 step 1 do begin
+        Draw(i, T);
+    end;
+end;");
+        let output = interpreter_obj.interpret().unwrap();
+        //每次执行内层循环都生成一个点序列，直接DRAW的点归入外层循环的点序列
+        let series: Vec<(&str, Vec<(f64, f64)>)> = output.series().iter()
+            .map(|s| (s.loop_variable(), s.points().to_vec()))
+            .collect();
+        assert_eq!(series, vec![
+            ("A", vec![(1.0, 10.0), (2.0, 20.0)]),
+            ("T", vec![(0.0, 11.0), (1.0, 11.0)]),
+            ("I", vec![(0.0, 10.0), (1.0, 10.0)]),
+            ("T", vec![(0.0, 22.0), (1.0, 22.0)]),
+            ("I", vec![(0.0, 20.0), (1.0, 20.0)]),
+        ]);
+        assert_eq!(output.series()[1].transform().origin(), (0.0, 10.0));
+
+        //块中出错时跳过整个块
+        let mut interpreter_obj = Interpreter::from_str(
+            "For a from 1 to 2 step 1 do begin\n  Def x = 1;\n  Draw(a, a);\nend;\nRot is 45 is 8;"
+        );
+        let errors = interpreter_obj.interpret().unwrap_err();
+        let error_positions: Vec<(u32, u32)> = errors.iter().map(|e| e.position()).collect();
+        assert_eq!(error_positions, vec![(2, 3), (5, 11)]);
+        assert_eq!(errors[0].message(), "expected `ORIGIN`, `SCALE`, `ROT`, `FOR`, `IF`, `IMPLICIT`, `DRAW` or `END`, found `DEF`");
+    }
+
+    #[test]
+    fn test_interpret_condition() {
+        let mut interpreter_obj = Interpreter::from_str("\
+Fn fact(n) => if(n <= 1, 1, n * fact(n - 1));
+For T from -2 to 2 step 1 draw(T, if(T < 0 or T == 2, -1, fact(T + 2)));
+For T from 0 to 3 step 1 draw(T, (T >= 1 and not T > 2) + (T != 1));");
+        let output = interpreter_obj.interpret().unwrap();
+        assert_eq!(output.series()[0].points(), &[(-2.0, -1.0), (-1.0, -1.0), (0.0, 2.0), (1.0, 6.0), (2.0, -1.0)]);
+        assert_eq!(output.series()[1].points(), &[(0.0, 1.0), (1.0, 1.0), (2.0, 2.0), (3.0, 1.0)]);
+
+        //IF语句，分支可以是块，ELSE可以省略
+        let mut interpreter_obj = Interpreter::from_str("\
+For i from 1 to 4 step 1 do begin
+    if i == 2 then draw(i, 0)
+    else if i > 2 then begin
+        draw(i, 1);
+        draw(i, 2);
+    end;
+end;
+If 1 < 0 then Origin is (1, 1) else Origin is (2, 2);
+For T from 0 to 0 step 1 draw(T, T);");
+        let output = interpreter_obj.interpret().unwrap();
+        assert_eq!(output.series()[0].points(), &[(2.0, 0.0), (3.0, 1.0), (3.0, 2.0), (4.0, 1.0), (4.0, 2.0)]);
+        assert_eq!(output.series()[1].points(), &[(2.0, 2.0)]);
+    }
+
+    #[test]
+    fn test_interpret_polar() {
+        let mut interpreter_obj = Interpreter::from_str("\
+origin is (1, 0);
+For T from 0 to 3 step 1 draw polar(2, T * pi / 2);
+For T from 0 to 1 step 1 do begin draw polar(T, 0); draw(T, 0); end;");
+        let output = interpreter_obj.interpret().unwrap();
+        //先转换为直角坐标，再平移
+        let expected = [(3.0, 0.0), (1.0, 2.0), (-1.0, 0.0), (1.0, -2.0)];
+        for (point, expected) in output.series()[0].points().iter().zip(expected.iter()) {
+            assert!((point.0 - expected.0).abs() < 1e-9 && (point.1 - expected.1).abs() < 1e-9);
+        }
+        assert_eq!(output.series()[1].points(), &[(1.0, 0.0), (1.0, 0.0), (2.0, 0.0), (2.0, 0.0)]);
+    }
+
+    #[test]
+    fn test_interpret_implicit() {
+        let mut interpreter_obj = Interpreter::from_str("\
+origin is (10, 0);
+implicit x**2 + y**2 - 4 over (-3, 3, -3, 3) resolution 30;
+For X from 0 to 1 step 1 do begin implicit x - 0.5 over (0, 1, 0, 1) resolution 1; draw(X, 0); end;");
+        let output = interpreter_obj.interpret().unwrap();
+        assert!(output.warnings().is_empty());
+
+        //每相邻两个点是一条线段，端点都在平移后的圆上
+        let circle = &output.series()[0];
+        assert_eq!(circle.kind(), SeriesKind::Contour);
+        assert!(!circle.points().is_empty() && circle.points().len().is_multiple_of(2));
+        for (x, y) in circle.points() {
+            assert!((((x - 10.0).powi(2) + y.powi(2)).sqrt() - 2.0).abs() < 0.05);
+        }
+
+        //表达式中的X遮蔽了循环变量，执行后循环变量不受影响
+        assert_eq!(output.series()[1].kind(), SeriesKind::Curve);
+        assert_eq!(output.series()[1].points(), &[(10.0, 0.0), (11.0, 0.0)]);
+        assert_eq!(output.series()[2].points(), &[(10.5, 0.0), (10.5, 1.0)]);
+        assert_eq!(output.series().len(), 4);
+    }
+
+    #[test]
+    fn test_interpret_implicit_error() {
+        let mut interpreter_obj = Interpreter::from_str("implicit x - y over (0, 1, 0, 1) resolution 0.5;");
+        let errors = interpreter_obj.interpret().unwrap_err();
+        assert_eq!(errors[0].kind(), ErrorKind::InvalidResolutionError);
+        assert_eq!(errors[0].message(), "resolution of IMPLICIT must be a positive integer, found 0.5");
+    }
+
+    #[test]
+    fn test_interpret_adaptive() {
+        let mut interpreter_obj = Interpreter::from_str("\
+For T from -1 to 1 step 0.5 adaptive draw(T, 1/T);
+For T from 0 to 1 step 0.5 adaptive draw(T, 2*T);");
+        interpreter_obj.set_coordinate_range(-10.0, 10.0, -10.0, 10.0);
+        interpreter_obj.set_adaptive_options(AdaptiveOptions::new().tolerance(0.01));
+        let output = interpreter_obj.interpret().unwrap();
+
+        //在陡峭处细分，所有点仍在曲线上，并且在0处断开
+        let hyperbola = &output.series()[0];
+        assert!(hyperbola.points().len() > 50);
+        assert!(hyperbola.points().windows(2).all(|w| w[0].0 < w[1].0));
+        for (x, y) in hyperbola.points() {
+            assert!((x * y - 1.0).abs() < 1e-9);
+        }
+        assert_eq!(hyperbola.discontinuities().len(), 1);
+        let break_index = hyperbola.discontinuities()[0];
+        assert!(hyperbola.points()[break_index - 1].0 < 0.0 && hyperbola.points()[break_index].0 > 0.0);
+
+        //直线上相邻两点的距离不超过容差
+        let line = &output.series()[1];
+        assert!(line.discontinuities().is_empty());
+        let tolerance = 0.01 * 20.0 * 2.0_f64.sqrt();
+        assert!(line.points().windows(2).all(|w| (w[1].0 - w[0].0).hypot(w[1].1 - w[0].1) <= tolerance));
+    }
+
+    #[test]
+    fn test_interpret_loop_step() {
+        let mut interpreter_obj = Interpreter::from_str("\
+For T from 3 to 1 step -1 draw(T, 0);
+For T from 0 to 1 step 0.1 draw(T, 0);
+For T from 2 to 2 step -5 draw(T, 0);");
+        let output = interpreter_obj.interpret().unwrap();
+        //STEP为负时递减
+        assert_eq!(output.series()[0].points(), &[(3.0, 0.0), (2.0, 0.0), (1.0, 0.0)]);
+        //由下标计算循环变量，不会累积误差
+        let points = output.series()[1].points();
+        assert_eq!(points.len(), 11);
+        assert_eq!(points[3], (0.30000000000000004, 0.0));
+        assert_eq!(points[10], (1.0, 0.0));
+        assert_eq!(output.series()[2].points(), &[(2.0, 0.0)]);
+    }
+
+    #[test]
+    fn test_interpret_loop_step_error() {
+        let mut interpreter_obj = Interpreter::from_str("\
+For T from 0 to 1 step 0 draw(T, 0);
+For T from 0 to 1 step -0.1 draw(T, 0);
+For T from 0 to 1/0 step 1 draw(T, 0);
+For T from 0 to 0/0 step 1 draw(T, 0);
+For T from 0 to 100 step 1 draw(T, 0);
+For T from 0 to 9 step 1 draw(T, 0);");
+        interpreter_obj.set_max_loop_iterations(10);
+        let errors = interpreter_obj.interpret().unwrap_err();
+        let errors: Vec<(ErrorKind, String)> = errors.iter().map(|e| (e.kind(), e.message())).collect();
+        assert_eq!(errors, vec![
+            (ErrorKind::ZeroStepError, String::from("step of loop variable `T` is zero, the loop would never end")),
+            (ErrorKind::StepDirectionError, String::from("step -0.1 of loop variable `T` never reaches 1 from 0")),
+            (ErrorKind::NonFiniteLoopRangeError, String::from("`TO` of loop variable `T` is inf, expected a finite number")),
+            (ErrorKind::NonFiniteLoopRangeError, String::from("`TO` of loop variable `T` is NaN, expected a finite number")),
+            (ErrorKind::IterationLimitExceededError, String::from("loop variable `T` would take 101 iterations, exceeding the maximum of 10")),
+        ]);
+        assert!(errors.iter().all(|(kind, _)| kind.is_runtime_error()));
+    }
+
+    #[test]
+    fn test_interpret_resource_limits() {
+        let source = "\
+For T from 1 to 10 step 1 draw(T, 0);
+For T from 1 to 10 step 1 do begin draw(T, 1); end;
+ROT is undefined_var;";
+        let mut interpreter_obj = Interpreter::from_str(source);
+        interpreter_obj.set_resource_limits(ResourceLimits::new().max_points(15));
+        let errors = interpreter_obj.interpret().unwrap_err();
+        //超过限制后立即停止，不会再报告之后的错误
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind(), ErrorKind::PointLimitExceededError);
+        assert_eq!(errors[0].message(), "exceeded the maximum of 15 points");
+        //已经生成的点仍然可以取出
+        let partial_output = interpreter_obj.take_partial_output().unwrap();
+        assert_eq!(partial_output.series()[0].points().len(), 10);
+        assert_eq!(partial_output.series()[1].points().len(), 5);
+        assert!(interpreter_obj.take_partial_output().is_none());
+
+        //循环体中的语句每次迭代都会计数：两条FOR语句本身，加上第一个循环的10次DRAW，第二个循环只能执行8次DRAW
+        let mut interpreter_obj = Interpreter::from_str(source);
+        interpreter_obj.set_resource_limits(ResourceLimits::new().max_statements(20));
+        let errors = interpreter_obj.interpret().unwrap_err();
+        assert_eq!(errors[0].kind(), ErrorKind::StatementLimitExceededError);
+        assert_eq!(interpreter_obj.take_partial_output().unwrap().points().len(), 10 + 8);
+
+        //ADAPTIVE的每个采样点（包括细分出的点）也计为一条语句
+        let source_adaptive = "For T from 0 to 9 step 1 adaptive draw(T, T);\nFor T from -1 to 1 step 1 adaptive draw(T, 1 / T);";
+        let statement_count = |max_statements: usize| {
+            let mut interpreter_obj = Interpreter::from_str(source_adaptive);
+            interpreter_obj.set_resource_limits(ResourceLimits::new().max_statements(max_statements));
+            interpreter_obj.interpret().map(|_| ()).map_err(|errors| errors[0].kind())
+        };
+        assert!(statement_count(1000).is_ok());
+        //两条FOR语句与13个初始点之外，细分出的点也被计数
+        assert_eq!(statement_count(2 + 10 + 3), Err(ErrorKind::StatementLimitExceededError));
+        assert_eq!(statement_count(5), Err(ErrorKind::StatementLimitExceededError));
+
+        let mut interpreter_obj = Interpreter::from_str(source);
+        interpreter_obj.set_resource_limits(ResourceLimits::new().timeout(std::time::Duration::ZERO));
+        let errors = interpreter_obj.interpret().unwrap_err();
+        assert_eq!(errors[0].kind(), ErrorKind::TimeoutError);
+        assert!(errors[0].kind().is_resource_limit_error());
+
+        let mut interpreter_obj = Interpreter::from_str("Fn f(n) => f(n); For T from 0 to 1 step 1 draw(T, f(T));");
+        interpreter_obj.set_resource_limits(ResourceLimits::new().max_recursion_depth(8));
+        let errors = interpreter_obj.interpret().unwrap_err();
+        assert_eq!(errors[0].message(), "function `F` exceeded the maximum recursion depth of 8");
+
+        //两种设置方式修改的是同一个限制：set_max_recursion_depth保留其他限制，set_resource_limits整体替换
+        let mut interpreter_obj = Interpreter::from_str(
+            "Fn f(n) => f(n);\nOrigin is (f(0), 0);\nFor T from 0 to 9 step 1 draw(T, T);"
+        );
+        interpreter_obj.set_resource_limits(ResourceLimits::new().max_statements(5).max_recursion_depth(8));
+        interpreter_obj.set_max_recursion_depth(4);
+        let errors = interpreter_obj.interpret().unwrap_err();
+        assert_eq!(errors[0].message(), "function `F` exceeded the maximum recursion depth of 4");
+        assert_eq!(errors[1].kind(), ErrorKind::StatementLimitExceededError);
+        interpreter_obj.set_resource_limits(ResourceLimits::new());
+        let errors = interpreter_obj.interpret().unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message(), "function `F` exceeded the maximum recursion depth of 256");
+    }
+
+    #[test]
+    fn test_interpret_cancellation() {
+        let cancellation_token = CancellationToken::new();
+        let mut interpreter_obj = Interpreter::from_str("For T from 1 to 10 step 1 draw(T, 0);");
+        interpreter_obj.set_resource_limits(ResourceLimits::new().cancellation_token(&cancellation_token));
+        //可以从其他线程取消
+        let remote_token = cancellation_token.clone();
+        std::thread::spawn(move || remote_token.cancel()).join().unwrap();
+        let errors = interpreter_obj.interpret().unwrap_err();
+        assert_eq!(errors[0].kind(), ErrorKind::CancelledError);
+        assert_eq!(errors[0].message(), "interpretation was cancelled");
+        assert!(interpreter_obj.take_partial_output().unwrap().points().is_empty());
+    }
+
+    #[test]
+    fn test_interpret_compile_expressions() {
+        let source = "\
+Fn fact(n) => if(n <= 1, 1, n * fact(n - 1));
+Fn g(x, y) => x * y + fact(3);
+Def a = T ** 0.5;
+For T from -2 to 2 step 0.25 draw(g(T, 2), a + piecewise((T > 1, 1), (T < -1 or not T < 0, 2)));
+For T from 0 to 6 step 0.5 draw polar(T, T / 2);
+For T from -1 to 1 step 0.5 adaptive draw(T, 1 / T);
+implicit x*x + y*y - 1 over (-2, 2, -2, 2) resolution 8;";
+        let interpret = |compile_expressions: bool| {
+            let mut interpreter_obj = Interpreter::from_str(source);
+            interpreter_obj.set_coordinate_range(-10.0, 10.0, -10.0, 10.0);
+            interpreter_obj.set_compile_expressions(compile_expressions);
+            interpreter_obj.interpret().unwrap()
+        };
+        let (compiled, tree_walked) = (interpret(true), interpret(false));
+        assert_eq!(compiled.series().len(), 4);
+        for (compiled, tree_walked) in compiled.series().iter().zip(tree_walked.series()) {
+            assert_eq!(compiled.points(), tree_walked.points());
+            assert_eq!(compiled.discontinuities(), tree_walked.discontinuities());
+        }
+        //警告的计数与语法树求值一致
+        assert_eq!(warning_messages(&compiled), warning_messages(&tree_walked));
+        let warning_kinds: Vec<WarningKind> = compiled.warnings().iter().map(|w| w.kind()).collect();
+        assert!(warning_kinds.contains(&WarningKind::NegativeBaseFractionalPower));
+        assert!(warning_kinds.contains(&WarningKind::UnmatchedPiecewise));
+    }
+
+    #[test]
+    fn test_interpret_worker_threads() {
+        let source = "\
+Fn f(n) => if(n <= 0, 0, f(n - 1));
+For T from 0 to 19999 step 1 draw(T, piecewise((T < 5000, T / 2), (T > 12000, -T)));
+For T from 0 to 30000 step 3 draw polar(T / 10, T);
+For T from 0 to 19999 step 1 draw(T, f(if(T > 15000, 1000, 1)));
+For T from 0 to 10 step 1 draw(T, T);";
+        let interpret = |worker_threads: usize, resource_limits: ResourceLimits| {
+            let mut interpreter_obj = Interpreter::from_str(source);
+            interpreter_obj.set_coordinate_range(-1e5, 1e5, -1e5, 1e5);
+            interpreter_obj.set_worker_threads(worker_threads);
+            interpreter_obj.set_resource_limits(resource_limits);
+            let errors = interpreter_obj.interpret().unwrap_err();
+            let errors: Vec<(ErrorKind, String)> = errors.iter().map(|e| (e.kind(), e.message())).collect();
+            (errors, interpreter_obj.take_partial_output().unwrap())
+        };
+        for resource_limits in [ResourceLimits::new(), ResourceLimits::new().max_points(25000)] {
+            let (expected_errors, expected) = interpret(1, resource_limits.clone());
+            let (errors, output) = interpret(4, resource_limits);
+            //点的顺序、警告与错误都和单线程时相同
+            assert_eq!(errors, expected_errors);
+            assert_eq!(output.series().len(), expected.series().len());
+            for (series, expected_series) in output.series().iter().zip(expected.series()) {
+                assert_eq!(series.points(), expected_series.points());
+            }
+            assert_eq!(warning_messages(&output), warning_messages(&expected));
+        }
+
+        let (errors, output) = interpret(4, ResourceLimits::new());
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, ErrorKind::RecursionLimitExceededError);
+        //出错前的点仍然保留
+        assert_eq!(output.series()[2].points().len(), 15001);
+        assert_eq!(output.series()[3].points().len(), 11);
+        assert!(output.warnings().iter().any(|w| w.kind() == WarningKind::UnmatchedPiecewise));
+    }
+
+    #[test]
+    fn test_interpret_optimize_expressions() {
+        let source = "\
+Fn fact(n) => if(n <= 1, 1, n * fact(n - 1));
+Def angle = T * PI / 4;
+For S from 1 to 3 step 1 do begin
+    For T from -1 to 1 step 0.5 draw(-T * 1 + 0, S * cos(angle) + fact(S + 2) ** 1 + 0 * ((-2) ** 0.5 > 0));
+    For T from 0 to 1 step 0.5 adaptive draw(T, if(S > 1, sqrt(T), 1 / T));
+end;
+implicit x*x + y*y - fact(2) / 2 over (-2, 2, -2, 2) resolution 8;";
+        let interpret = |optimize_expressions: bool, compile_expressions: bool| {
+            let mut interpreter_obj = Interpreter::from_str(source);
+            interpreter_obj.set_coordinate_range(-200.0, 200.0, -200.0, 200.0);
+            interpreter_obj.set_optimize_expressions(optimize_expressions);
+            interpreter_obj.set_compile_expressions(compile_expressions);
+            interpreter_obj.interpret().unwrap()
+        };
+        let expected = interpret(false, false);
+        assert_eq!(expected.series().len(), 7);
+        assert_eq!(expected.series()[0].points().len(), 5);
+        assert!(expected.warnings().iter().any(|w| w.kind() == WarningKind::NegativeBaseFractionalPower));
+        for (optimize_expressions, compile_expressions) in [(true, false), (true, true)] {
+            let output = interpret(optimize_expressions, compile_expressions);
+            for (series, expected_series) in output.series().iter().zip(expected.series()) {
+                assert_eq!(series.points(), expected_series.points());
+                assert_eq!(series.discontinuities(), expected_series.discontinuities());
+            }
+            //不能提前求值的负数的小数次方，警告次数不变
+            assert_eq!(warning_messages(&output), warning_messages(&expected));
+        }
+    }
+
+    #[test]
+    fn test_interpret_optimize_signed_zero() {
+        //-T + 0与0 - T在T为0时得到+0，化简后若得到-0，ATAN2的结果会从PI变为-PI
+        let source = "For T from 0 to 1 step 1 draw(atan2(-T + 0, -1), atan2(0 - T, -1));";
+        let interpret = |optimize_expressions: bool, compile_expressions: bool| {
+            let mut interpreter_obj = Interpreter::from_str(source);
+            interpreter_obj.set_optimize_expressions(optimize_expressions);
+            interpreter_obj.set_compile_expressions(compile_expressions);
+            interpreter_obj.interpret().unwrap().into_points()
+        };
+        let expected = interpret(false, false);
+        assert_eq!(expected[0], (std::f64::consts::PI, std::f64::consts::PI));
+        for compile_expressions in [false, true] {
+            assert_eq!(interpret(true, compile_expressions), expected);
+        }
+    }
+
+    #[test]
+    fn test_interpret_piecewise() {
+        let mut interpreter_obj = Interpreter::from_str("\
+For T from -2 to 2 step 1 draw(T, piecewise((T < 0, -T), (T == 0, 10), else T**2));
+For T from -1 to 1 step 1 draw(T, piecewise((T > 0, 1), (T < 0, 1/0 > 0)));");
+        let output = interpreter_obj.interpret().unwrap();
+        assert_eq!(output.series()[0].points(), &[(-2.0, 2.0), (-1.0, 1.0), (0.0, 10.0), (1.0, 1.0), (2.0, 4.0)]);
+        assert_eq!(output.series()[1].points(), &[(-1.0, 1.0), (1.0, 1.0)]);
+
+        //没有分支匹配时结果为NaN，并给出警告
+        assert_eq!(warning_messages(&output), vec![
+            (WarningKind::DiscardedNanPoints, String::from("1 point(s) discarded because a coordinate is NaN")),
+            (WarningKind::UnmatchedPiecewise, String::from("no branch of PIECEWISE matched 1 time(s), producing NaN")),
+        ]);
+    }
+
+    #[test]
+    fn test_interpret_function() {
+        let mut interpreter_obj = Interpreter::from_str(
+            "Def a = 100;\nFn f(a, b) => a*T + b;\nFn g() => f(1, 0) + a;\nFor T from 1 to 2 step 1 draw(f(2, 1), g());"
+        );
+        let output = interpreter_obj.interpret().unwrap();
+        assert_eq!(output.points(), vec![(3.0, 101.0), (5.0, 102.0)]);
+
+        //重新定义函数，之前的调用不受影响
+        let mut interpreter_obj = Interpreter::from_str(
+            "Fn f(x) => x;\nDef p = f(1);\nFn f(x) => 2*x;\nFor T from 1 to 1 step 1 draw(p, f(1));"
+        );
+        let output = interpreter_obj.interpret().unwrap();
+        assert_eq!(output.points(), vec![(1.0, 2.0)]);
+        assert_eq!(output.warnings()[0].kind(), WarningKind::ShadowedDefinition);
+        assert_eq!(output.warnings()[0].message(), "`F` shadows the function defined at 1:4");
+    }
+
+    #[test]
+    fn test_interpret_function_error() {
+        let mut interpreter_obj = Interpreter::from_str(
+            "Fn f(x) => x;\nOrigin is (f(1, 2), 0);\nOrigin is (x, 0);\nFn r(x) => r(x + 1);\nOrigin is (r(0), 0);\nFn sin(x) => x;"
+        );
+        interpreter_obj.set_max_recursion_depth(10);
+        let errors = interpreter_obj.interpret().unwrap_err();
+        let error_kinds: Vec<(ErrorKind, u32)> = errors.iter().map(|e| (e.kind(), e.position().0)).collect();
+        assert_eq!(error_kinds, vec![
+            (ErrorKind::ArgumentNumberNotMatchError, 2),
+            (ErrorKind::UndefinedVariableError, 3),
+            (ErrorKind::RecursionLimitExceededError, 5),
+            (ErrorKind::SyntaxError, 6),
+        ]);
+        assert_eq!(errors[2].message(), "function `R` exceeded the maximum recursion depth of 10");
+
+        //形参名重复时报错，并指向重复的形参
+        let errors = Interpreter::from_str("Fn f(x, y, x) => x;").interpret().unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind(), ErrorKind::SyntaxError);
+        assert_eq!(errors[0].message(), "parameter `X` is declared more than once");
+        assert_eq!(errors[0].position(), (1, 12));
+    }
+
+    #[test]
+    fn test_interpret_options() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        #[derive(Clone)]
+        struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+        impl std::io::Write for SharedBuffer {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.borrow_mut().write(buf)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let buffer = SharedBuffer(Rc::new(RefCell::new(Vec::new())));
+        let mut interpreter_obj = Interpreter::from_str("For T from 1 to 2 step 1 draw(T, 2);");
+        interpreter_obj.set_options(InterpreterOptions::new()
+            .trace_statements(true)
+            .dump_ast(true)
+            .dump_points(true)
+            .sink(Box::new(buffer.clone())));
+        interpreter_obj.interpret().unwrap();
+
+        let output = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+        assert!(output.starts_with("Debug: parse a statement, begin token: "));
+        assert!(output.contains("->/$ 2.0\n"));
+        assert!(output.ends_with("Debug: Add Point: (1.0, 2.0)\nDebug: Add Point: (2.0, 2.0)\n"));
+    }
+
+    #[test]
+    fn test_interpret_parse_and_execute() {
+        let source = "\
+Def b = T;
+Origin is (b, 0);
+Def a = 1;
+For T from 0 to 1 step 1 draw(T, a);
+Let a = 2;
+For T from 0 to 1 step 1 draw(T, a);
+Origin is (5, 5);";
+        let mut interpreter_obj = Interpreter::from_str(source);
+        let program = interpreter_obj.parse().unwrap();
+        //LET在执行到它时才生效
+        let expected = vec![(0.0, 1.0), (1.0, 1.0), (0.0, 2.0), (1.0, 2.0)];
+        assert_eq!(interpreter_obj.execute(&program).unwrap().into_points(), expected);
+        //再次执行时坐标变换、循环变量与表达式变量都从头开始
+        assert_eq!(interpreter_obj.execute(&program).unwrap().into_points(), expected);
+
+        //两次执行之间可以修改设置
+        interpreter_obj.set_coordinate_range(-10.0, 10.0, -10.0, 1.5);
+        interpreter_obj.set_compile_expressions(false);
+        let output = interpreter_obj.execute(&program).unwrap();
+        assert_eq!(output.points(), vec![(0.0, 1.0), (1.0, 1.0)]);
+        assert_eq!(output.warnings()[0].kind(), WarningKind::OutOfRangePoints);
+
+        //执行时的错误每次都会报告
+        let mut interpreter_obj = Interpreter::from_str("Def a = 1;\nRot is a;\nFor T from 0 to 1 step 0 draw(T, T);");
+        let program = interpreter_obj.parse().unwrap();
+        for _ in 0..2 {
+            let errors = interpreter_obj.execute(&program).unwrap_err();
+            assert_eq!(errors.len(), 1);
+            assert_eq!(errors[0].kind(), ErrorKind::ZeroStepError);
+            assert_eq!(errors[0].position().0, 3);
+        }
+
+        //分析时的警告随程序保存，执行时与执行产生的警告一起输出
+        let mut interpreter_obj = Interpreter::from_str("Fn f(x) => x;\nFn f(x) => 2 * x;\nFor T from 0 to 0 step 1 draw(T, f(1));");
+        let program = interpreter_obj.parse().unwrap();
+        let kinds: Vec<WarningKind> = program.warnings().map(|w| w.kind()).collect();
+        assert_eq!(kinds, vec![WarningKind::ShadowedDefinition]);
+        assert_eq!(interpreter_obj.execute(&program).unwrap().warnings()[0].position(), (2, 4));
+
+        //分析出错时不会得到程序
+        let errors = Interpreter::from_str("Rot is 1;\nScale is (1;").parse().unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind(), ErrorKind::SyntaxError);
+
+        //程序的变量绑定在分析它的解释器上，不能由其他解释器执行
+        let errors = Interpreter::from_str(source).execute(&program).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind(), ErrorKind::ForeignProgramError);
+        assert_eq!(errors[0].message(), "the program was parsed by another interpreter");
+    }
+
+    #[test]
+    fn test_interpret_program_statements() {
+        let mut interpreter_obj = Interpreter::from_str("\
+Param a default 2;
+Fn f(x, y) => x * y;
+Def b = f(a, 3);
+For T from 0 to 10 step 1 do begin
+    If T > 5 then Draw(T, b);
+end;
+Implicit X * Y - 1 over (1, 2, 3, 4) resolution 8;");
+        let program = interpreter_obj.parse().unwrap();
+        let statements: Vec<&Statement> = program.statements().iter().map(|s| s.statement()).collect();
+        let [Statement::Param(param), Statement::Fn(function), Statement::Def(def), Statement::For(for_statement),
+             Statement::Implicit(implicit)] = statements[..] else {
+            panic!("unexpected statements: {:?}", program);
+        };
+        assert_eq!((param.name(), param.default().constant_value()), ("A", Some(2.0)));
+        assert_eq!((function.name(), function.parameter_names()), ("F", &[String::from("X"), String::from("Y")][..]));
+        assert!(function.body_tree().contains("Mul"));
+        assert_eq!((def.variable_name(), def.expression().span()), ("B", Span::new((3, 9), (3, 15))));
+
+        assert_eq!(for_statement.loop_variable_name(), "T");
+        assert_eq!(for_statement.to().constant_value(), Some(10.0));
+        assert!(!for_statement.is_nested());
+        let ForBody::Block(body) = for_statement.body() else {
+            panic!("expected a block: {:?}", for_statement);
+        };
+        let [Statement::If(condition, then_branch, else_branch)] = &body[..] else {
+            panic!("expected an IF statement: {:?}", body);
+        };
+        assert_eq!(condition.span(), Span::new((5, 8), (5, 12)));
+        assert!(matches!(then_branch[..], [Statement::Draw(CoordinateSystem::Cartesian, ..)]));
+        assert!(else_branch.is_empty());
+
+        assert_eq!(implicit.bounds().iter().map(|b| b.constant_value().unwrap()).collect::<Vec<_>>(), vec![1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(implicit.resolution().span(), Span::new((7, 49), (7, 49)));
+        //Debug输出语句的结构，不含语法树
+        assert!(format!("{:?}", program).contains("loop_variable_name: \"T\""));
+    }
+
+    #[test]
+    fn test_interpret_repeatedly() {
+        let source = "Def a = 2;\nFor T from 0 to 2 step 1 draw(T, a * T);\nFor T from 0 to 1 step 1 draw(T, T ** 0.5);";
+        let mut interpreter_obj = Interpreter::from_str(source);
+        let expected = vec![(0.0, 0.0), (1.0, 2.0), (2.0, 4.0), (0.0, 0.0), (1.0, 1.0)];
+        assert_eq!(interpreter_obj.interpret().unwrap().into_points(), expected);
+        //再次解释时不再读取源程序，结果与第一次相同
+        assert_eq!(interpreter_obj.interpret().unwrap().into_points(), expected);
+
+        //修改坐标范围后重新执行，超出范围的点被丢弃
+        interpreter_obj.set_coordinate_range(-1.0, 1.5, -1.0, 1.5);
+        let output = interpreter_obj.interpret().unwrap();
+        assert_eq!(output.points(), vec![(0.0, 0.0), (0.0, 0.0), (1.0, 1.0)]);
+        assert_eq!(output.warnings()[0].kind(), WarningKind::OutOfRangePoints);
+        interpreter_obj.set_coordinate_range(-10.0, 10.0, -10.0, 10.0);
+        assert_eq!(interpreter_obj.interpret().unwrap().into_points(), expected);
+        //interpret之后仍然可以取得程序
+        let program = interpreter_obj.parse().unwrap();
+        assert_eq!(interpreter_obj.execute(&program).unwrap().into_points(), expected);
+
+        //分析时的错误每次都会报告，出错前的点也每次都会生成
+        let mut interpreter_obj = Interpreter::from_str("For T from 0 to 1 step 1 draw(T, T);\nRot is (1;");
+        for _ in 0..2 {
+            let errors = interpreter_obj.interpret().unwrap_err();
+            assert_eq!(errors.len(), 1);
+            assert_eq!(errors[0].kind(), ErrorKind::SyntaxError);
+            assert_eq!(interpreter_obj.take_partial_output().unwrap().into_points(), vec![(0.0, 0.0), (1.0, 1.0)]);
+        }
+    }
+
+    #[test]
+    fn test_interpret_parameters() {
+        let source = "\
+Param A default 1;
+Param F default 2 * A;
+For T from 0 to 1 step 1 draw(T, A * T + F);";
+        let mut interpreter_obj = Interpreter::from_str(source);
+        let names: Vec<String> = interpreter_obj.parameters().unwrap().iter().map(|p| String::from(p.name())).collect();
+        assert_eq!(names, vec!["A", "F"]);
+        assert_eq!(interpreter_obj.parameters().unwrap()[0].span(), Span::new((1, 7), (1, 7)));
+        //没有设置时使用默认值，默认值可以引用之前的参数
+        assert_eq!(interpreter_obj.interpret().unwrap().into_points(), vec![(0.0, 2.0), (1.0, 3.0)]);
+        //两次执行之间修改参数，参数名不区分大小写
+        interpreter_obj.set_parameter("a", 3.0).unwrap();
+        assert_eq!(interpreter_obj.interpret().unwrap().into_points(), vec![(0.0, 6.0), (1.0, 9.0)]);
+        interpreter_obj.set_parameter("F", 0.0).unwrap();
+        interpreter_obj.set_compile_expressions(false);
+        assert_eq!(interpreter_obj.interpret().unwrap().into_points(), vec![(0.0, 0.0), (1.0, 3.0)]);
+
+        //非法的名字与未声明的参数，分析前后都一样报错
+        assert_eq!(interpreter_obj.set_parameter("1a", 1.0), Err(RegistrationError::InvalidName(String::from("1A"))));
+        assert_eq!(interpreter_obj.set_parameter("sin", 1.0), Err(RegistrationError::NameCollision(String::from("SIN"))));
+        assert_eq!(interpreter_obj.set_parameter("K", 1.0), Err(RegistrationError::UndeclaredParameter(String::from("K"))));
+        let source = "For T from 0 to 1 step 1 draw(T, K);";
+        let mut interpreter_obj = Interpreter::from_str(source);
+        assert_eq!(interpreter_obj.set_parameter("K", 5.0), Err(RegistrationError::UndeclaredParameter(String::from("K"))));
+        let errors = interpreter_obj.interpret().unwrap_err();
+        assert_eq!(errors[0].kind(), ErrorKind::UndefinedVariableError);
+
+        //T、X、Y不能作为参数名，与注册名字时的检查相同
+        for name in ["T", "x", "Y"] {
+            let source = format!("Param {} default 1;\nFor T from 0 to 0 step 1 draw(T, 0);", name);
+            let errors = Interpreter::from_str(&source).interpret().unwrap_err();
+            assert_eq!(errors.len(), 1);
+            assert_eq!(errors[0].kind(), ErrorKind::SyntaxError);
+            assert_eq!(errors[0].position(), (1, 7));
+            assert_eq!(errors[0].message(), format!(
+                "`{}` is already a reserved word, function, constant or special variable", name.to_ascii_uppercase()
+            ));
+        }
+        assert_eq!(Interpreter::from_str("Param A default 1;").set_parameter("t", 1.0),
+                   Err(RegistrationError::NameCollision(String::from("T"))));
+
+        //set_parameter会先分析源程序，之后不能再注册函数与常数，因此要先注册
+        let source = "Param A default 1;\nFor T from 0 to 0 step 1 draw(Offset, A);";
+        let mut interpreter_obj = Interpreter::from_str(source);
+        interpreter_obj.register_constant("Offset", 10.0).unwrap();
+        interpreter_obj.set_parameter("A", 2.0).unwrap();
+        assert_eq!(interpreter_obj.register_constant("K", 1.0), Err(RegistrationError::AlreadyParsed(String::from("K"))));
+        assert_eq!(interpreter_obj.interpret().unwrap().into_points(), vec![(10.0, 2.0)]);
+
+        //重复声明产生警告，DEF变量优先于同名参数
+        let source = "Param A default 1;\nParam A default 2;\nDef A = 7;\nFor T from 0 to 0 step 1 draw(T, A);";
+        let output = Interpreter::from_str(source).interpret().unwrap();
+        assert_eq!(output.points(), vec![(0.0, 7.0)]);
+        assert_eq!(output.warnings()[0].kind(), WarningKind::ShadowedDefinition);
+        assert_eq!(output.warnings()[0].position(), (2, 7));
+    }
+
+    #[test]
+    fn test_interpret_registered_functions() {
+        let source = "For T from 0 to 2 step 1 draw(T, Blend(Offset, 2 * Offset, T / 2));\nRot is Blend(1, 2);";
+        let mut interpreter_obj = Interpreter::from_str(source);
+        interpreter_obj.register_function("blend", 3, |args| args[0] + (args[1] - args[0]) * args[2]).unwrap();
+        interpreter_obj.register_constant("Offset", 10.0).unwrap();
+        let errors = interpreter_obj.interpret().unwrap_err();
+        //参数数量不匹配在执行时报告
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind(), ErrorKind::ArgumentNumberNotMatchError);
+        assert_eq!(errors[0].position().0, 2);
+        let points = interpreter_obj.take_partial_output().unwrap().into_points();
+        assert_eq!(points, vec![(0.0, 10.0), (1.0, 15.0), (2.0, 20.0)]);
+
+        //名字冲突与非法名字
+        let mut interpreter_obj = Interpreter::from_str("For T from 0 to 1 step 1 draw(T, Twice(T));");
+        let collision = |name: &str| RegistrationError::NameCollision(String::from(name));
+        assert_eq!(interpreter_obj.register_constant("pi", 3.0), Err(collision("PI")));
+        assert_eq!(interpreter_obj.register_function("Sin", 1, |args| args[0]), Err(collision("SIN")));
+        assert_eq!(interpreter_obj.register_constant("for", 1.0), Err(collision("FOR")));
+        assert_eq!(interpreter_obj.register_constant("t", 1.0), Err(collision("T")));
+        assert_eq!(interpreter_obj.register_constant("2x", 1.0), Err(RegistrationError::InvalidName(String::from("2X"))));
+        assert_eq!(interpreter_obj.register_constant("a_b", 1.0), Err(RegistrationError::InvalidName(String::from("A_B"))));
+        interpreter_obj.register_function("twice", 1, |args| 2.0 * args[0]).unwrap();
+        assert_eq!(interpreter_obj.register_function("TWICE", 1, |args| args[0]), Err(collision("TWICE")));
+        assert_eq!(interpreter_obj.interpret().unwrap().into_points(), vec![(0.0, 0.0), (1.0, 2.0)]);
+        //分析之后不能再注册
+        assert_eq!(interpreter_obj.register_constant("K", 1.0), Err(RegistrationError::AlreadyParsed(String::from("K"))));
+    }
+
+    #[test]
+    fn test_interpret_math_functions() {
+        let source = "For T from 0 to 3 step 1 draw(max(T, 1, 2) + mod(T, 2), log10(10 ** T) * sign(T - 1) + round(tau / phi));";
+        let expected = vec![(2.0, 4.0), (3.0, 4.0), (2.0, 6.0), (4.0, 7.0)];
+        for compile_expressions in [true, false] {
+            let mut interpreter_obj = Interpreter::from_str(source);
+            interpreter_obj.set_compile_expressions(compile_expressions);
+            assert_eq!(interpreter_obj.interpret().unwrap().into_points(), expected);
+        }
+
+        //MAX、MIN、CLAMP的参数中有NaN时结果为NaN，这样的点被丢弃
+        let source = "For T from 0 to 3 step 1 draw(max(T, sqrt(T - 2)), min(T, clamp(sqrt(T - 2), 0, 5)));";
+        for compile_expressions in [true, false] {
+            let mut interpreter_obj = Interpreter::from_str(source);
+            interpreter_obj.set_compile_expressions(compile_expressions);
+            let output = interpreter_obj.interpret().unwrap();
+            assert_eq!(output.points(), vec![(2.0, 0.0), (3.0, 1.0)]);
+            assert_eq!(output.warnings()[0].kind(), WarningKind::DiscardedNanPoints);
+        }
+
+        //CLAMP的下界大于上界时报错
+        let errors = Interpreter::from_str("For T from 0 to 1 step 1 draw(T, clamp(T, 2, 1));").interpret().unwrap_err();
+        assert_eq!(errors[0].kind(), ErrorKind::InvalidArgumentError);
+
+        //新增的常数与函数名是保留字，不能再用作变量名
+        for source in ["For phi from 0 to 1 step 1 draw(phi, 0);", "Def tau = 1;", "Fn f(mod) => mod;"] {
+            let errors = Interpreter::from_str(source).interpret().unwrap_err();
+            assert_eq!(errors[0].kind(), ErrorKind::SyntaxError);
+        }
+    }
+
+    #[test]
+    fn test_interpret_from_reader() {
+        let source: &[u8] = b"Origin is (1, 1);\nFor T from 0 to 1 step 1 draw(T, T);";
+        let mut interpreter_obj = Interpreter::from_reader(source);
+        let point_result = interpreter_obj.interpret().unwrap().into_points();
+        assert_eq!(point_result, vec![(1.0, 1.0), (2.0, 2.0)]);
+    }
+
+    #[test]
+    fn test_interpret_warnings() {
+        let source = "\
+Def a = 1;
+Def b = 2;
+Def a = T;
+For T from -1 to 1 step 1 draw(a, T**0.5);
+Scale is (100000, 1);
+For T from 1 to 2 step 1 draw(T, T);";
+        let output = Interpreter::from_str(source).interpret().unwrap();
+        for warning in output.warnings() {
+            println!("{}", warning);
+        }
+        let warning_kinds: Vec<(WarningKind, u32)> = output.warnings().iter()
+            .map(|w| (w.kind(), w.position().0)).collect();
+        assert_eq!(warning_kinds, vec![
+            (WarningKind::ShadowedDefinition, 3),
+            (WarningKind::UnusedVariable, 1),
+            (WarningKind::DiscardedNanPoints, 4),
+            (WarningKind::NegativeBaseFractionalPower, 4),
+            (WarningKind::OutOfRangePoints, 6),
+            (WarningKind::UnusedVariable, 2),
+        ]);
+        assert_eq!(output.warnings()[2].message(), "1 point(s) discarded because a coordinate is NaN");
+        assert_eq!(output.points(), &[(0.0, 0.0), (1.0, 1.0)]);
+    }
+
+    #[test]
+    fn test_interpret_warnings_as_errors() {
+        let mut interpreter_obj = Interpreter::from_str("Def unused = 1;\nROT is 45 is 8;");
+        interpreter_obj.set_warnings_as_errors(true);
+        let errors = interpreter_obj.interpret().unwrap_err();
+        let error_kinds: Vec<ErrorKind> = errors.iter().map(|e| e.kind()).collect();
+        assert_eq!(error_kinds, vec![
+            ErrorKind::PromotedWarning(WarningKind::UnusedVariable),
+            ErrorKind::SyntaxError,
+        ]);
+    }
+
+    #[test]
+    fn test_interpret_error() {
+        let file = File::open("error_test.txt").unwrap();
+        let mut interpreter_obj = Interpreter::new(file);
+        let errors = interpreter_obj.interpret().unwrap_err();
+        assert_eq!(errors.len(), 1);
+        let error = &errors[0];
+        println!("{}", error);
+        assert_eq!(error.kind(), ErrorKind::SyntaxError);
+        assert_eq!(error.token().unwrap().token_type(), TokenTypeEnum::ConstId);
+        assert_eq!(error.token().unwrap().lexeme(), "45");
+        assert_eq!(error.expected_token_types().len(), 10);
+        assert_eq!(error.position(), (2, 1));
+        assert_eq!(error.span(), Span::new((2, 1), (2, 2)));
+        assert_eq!(error.source_line(), Some("45 is 8;"));
+    }
+
+    #[test]
+    fn test_interpret_diagnostic() {
+        let mut interpreter_obj = Interpreter::from_str("ROT is 1;\nFor T from 1 to 2 step 1 draw(T  T);");
+        let error = interpreter_obj.interpret().unwrap_err().remove(0);
+        let rendered = error.to_string();
+        println!("{}", rendered);
+        assert!(rendered.starts_with("error[SyntaxError]: expected `,`, found `T`"));
+        assert!(rendered.contains("2 | For T from 1 to 2 step 1 draw(T  T);\n"));
+        assert!(rendered.contains("  |                                  ^\n"));
+        assert_eq!(error.note(), Some("while parsing `FOR` statement starting at 2:1"));
+    }
 }
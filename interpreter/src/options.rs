@@ -0,0 +1,295 @@
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+///解释器选项，控制调试信息的输出（建造者模式）
+///默认不输出任何调试信息；开启后输出到调用者指定的sink，未指定时输出到标准输出
+pub struct InterpreterOptions {
+    //每个被匹配的token
+    trace_tokens: bool,
+    //每条语句的开始
+    trace_statements: bool,
+    //每棵表达式语法树
+    dump_ast: bool,
+    //每个被添加的点
+    dump_points: bool,
+    //调试信息的输出目标
+    sink: Box<dyn Write>,
+}
+
+impl Default for InterpreterOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InterpreterOptions {
+    pub fn new() -> Self {
+        Self {
+            trace_tokens: false,
+            trace_statements: false,
+            dump_ast: false,
+            dump_points: false,
+            sink: Box::new(std::io::stdout()),
+        }
+    }
+
+    ///开启全部调试信息
+    pub fn verbose(self) -> Self {
+        self.trace_tokens(true)
+            .trace_statements(true)
+            .dump_ast(true)
+            .dump_points(true)
+    }
+
+    pub fn trace_tokens(mut self, trace_tokens: bool) -> Self {
+        self.trace_tokens = trace_tokens;
+        self
+    }
+
+    pub fn trace_statements(mut self, trace_statements: bool) -> Self {
+        self.trace_statements = trace_statements;
+        self
+    }
+
+    pub fn dump_ast(mut self, dump_ast: bool) -> Self {
+        self.dump_ast = dump_ast;
+        self
+    }
+
+    pub fn dump_points(mut self, dump_points: bool) -> Self {
+        self.dump_points = dump_points;
+        self
+    }
+
+    ///指定调试信息的输出目标
+    pub fn sink(mut self, sink: Box<dyn Write>) -> Self {
+        self.sink = sink;
+        self
+    }
+}
+
+///ADAPTIVE采样的选项（建造者模式）
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AdaptiveOptions {
+    //每个初始区间最多细分的层数
+    max_depth: u32,
+    //每条FOR语句最多额外计算的点数
+    point_budget: usize,
+    //相邻两点的最大距离，以坐标范围对角线长度的比例表示
+    tolerance: f64,
+}
+
+impl Default for AdaptiveOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AdaptiveOptions {
+    pub fn new() -> Self {
+        Self {
+            max_depth: 12,
+            point_budget: 20000,
+            tolerance: 0.002,
+        }
+    }
+
+    pub fn max_depth(mut self, max_depth: u32) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    pub fn point_budget(mut self, point_budget: usize) -> Self {
+        self.point_budget = point_budget;
+        self
+    }
+
+    pub fn tolerance(mut self, tolerance: f64) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    pub fn get_max_depth(&self) -> u32 {
+        self.max_depth
+    }
+
+    pub fn get_point_budget(&self) -> usize {
+        self.point_budget
+    }
+
+    pub fn get_tolerance(&self) -> f64 {
+        self.tolerance
+    }
+}
+
+///协作式的取消标记，可以从其他线程取消正在进行的解释
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+///解释时的资源限制（建造者模式），默认不限制
+///超过限制时立即停止解释，已经生成的点可以通过Interpreter::take_partial_output获取
+#[derive(Debug, Clone, Default)]
+pub struct ResourceLimits {
+    //所有点序列中的点数之和
+    max_points: Option<usize>,
+    //执行过的语句数，循环体中的语句每次迭代都会计数
+    max_statements: Option<usize>,
+    //用户函数的最大递归深度
+    max_recursion_depth: Option<usize>,
+    //从开始解释算起的时间
+    timeout: Option<Duration>,
+    cancellation_token: Option<CancellationToken>,
+}
+
+impl ResourceLimits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn max_points(mut self, max_points: usize) -> Self {
+        self.max_points = Some(max_points);
+        self
+    }
+
+    pub fn max_statements(mut self, max_statements: usize) -> Self {
+        self.max_statements = Some(max_statements);
+        self
+    }
+
+    pub fn max_recursion_depth(mut self, max_recursion_depth: usize) -> Self {
+        self.max_recursion_depth = Some(max_recursion_depth);
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn cancellation_token(mut self, cancellation_token: &CancellationToken) -> Self {
+        self.cancellation_token = Some(cancellation_token.clone());
+        self
+    }
+
+    pub fn get_max_points(&self) -> Option<usize> {
+        self.max_points
+    }
+
+    pub fn get_max_statements(&self) -> Option<usize> {
+        self.max_statements
+    }
+
+    pub fn get_max_recursion_depth(&self) -> Option<usize> {
+        self.max_recursion_depth
+    }
+
+    pub fn get_timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    pub fn get_cancellation_token(&self) -> Option<&CancellationToken> {
+        self.cancellation_token.as_ref()
+    }
+}
+
+///按照选项输出调试信息。各组件共享同一个Tracer
+pub struct Tracer {
+    options: InterpreterOptions,
+}
+
+impl Tracer {
+    pub fn new() -> Self {
+        Self {
+            options: InterpreterOptions::new(),
+        }
+    }
+
+    pub fn set_options(&mut self, options: InterpreterOptions) {
+        self.options = options;
+    }
+
+    pub fn is_dump_ast_enabled(&self) -> bool {
+        self.options.dump_ast
+    }
+
+    pub fn trace_token(&mut self, token: &crate::lexer::Token) {
+        if self.options.trace_tokens {
+            self.write_line(&format!("Debug: match token: {:?}", token));
+        }
+    }
+
+    pub fn trace_statement(&mut self, begin_token: &crate::lexer::Token) {
+        if self.options.trace_statements {
+            self.write_line(&format!("Debug: parse a statement, begin token: {:?}", begin_token));
+        }
+    }
+
+    pub fn dump_point(&mut self, point: &(f64, f64)) {
+        if self.options.dump_points {
+            self.write_line(&format!("Debug: Add Point: {:?}", point));
+        }
+    }
+
+    ///原样输出一段文本，如语法树
+    pub fn write_text(&mut self, text: &str) {
+        //调试信息输出失败不应影响解释
+        let _ = self.options.sink.write_all(text.as_bytes());
+    }
+
+    fn write_line(&mut self, line: &str) {
+        let _ = writeln!(self.options.sink, "{}", line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::lexer::Token;
+
+    ///可以在外部读取内容的sink
+    #[derive(Clone)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_tracer_sink() {
+        let buffer = SharedBuffer(Rc::new(RefCell::new(Vec::new())));
+        let mut tracer = Tracer::new();
+        tracer.set_options(InterpreterOptions::new().dump_points(true).sink(Box::new(buffer.clone())));
+
+        tracer.trace_token(&Token::generate_eof_token());
+        tracer.dump_point(&(1.0, 2.5));
+
+        let output = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+        assert_eq!(output, "Debug: Add Point: (1.0, 2.5)\n");
+    }
+}
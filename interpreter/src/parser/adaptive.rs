@@ -0,0 +1,153 @@
+use crate::exception;
+use crate::options::AdaptiveOptions;
+
+///一个采样点：循环变量的值、变换前的坐标、变换后的坐标
+#[derive(Debug, Copy, Clone)]
+pub struct Sample {
+    pub t: f64,
+    pub point: (f64, f64),
+    pub screen: (f64, f64),
+}
+
+impl Sample {
+    fn is_nan(&self) -> bool {
+        self.screen.0.is_nan() || self.screen.1.is_nan()
+    }
+}
+
+///自适应采样的结果，按循环变量的顺序排列
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AdaptiveEvent {
+    //变换前的坐标
+    Point((f64, f64)),
+    //前后两个点之间不连续
+    Break,
+}
+
+fn distance(a: (f64, f64), b: (f64, f64)) -> f64 {
+    (a.0 - b.0).hypot(a.1 - b.1)
+}
+
+///在初始采样点之间递归二分，直到相邻两点在屏幕上足够近、且中点足够贴近两点连线。
+///细分到最大层数仍然无法满足时认为曲线在此处不连续，不会把两侧连起来
+pub struct AdaptiveSampler<F: FnMut(f64) -> exception::Result<Sample>> {
+    sample: F,
+    max_depth: u32,
+    budget_left: usize,
+    //屏幕上允许的最大距离
+    tolerance: f64,
+    events: Vec<AdaptiveEvent>,
+}
+
+impl<F: FnMut(f64) -> exception::Result<Sample>> AdaptiveSampler<F> {
+    ///diagonal为坐标范围对角线的长度
+    pub fn new(sample: F, options: &AdaptiveOptions, diagonal: f64) -> Self {
+        AdaptiveSampler {
+            sample,
+            max_depth: options.get_max_depth(),
+            budget_left: options.get_point_budget(),
+            tolerance: options.get_tolerance() * diagonal,
+            events: Vec::new(),
+        }
+    }
+
+    ///对初始采样点依次细分。初始点无论是否为NaN都会输出，由调用者统计被丢弃的点
+    pub fn run(mut self, ts: impl Iterator<Item=f64>) -> exception::Result<Vec<AdaptiveEvent>> {
+        let mut prev: Option<Sample> = None;
+        for t in ts {
+            let curr = (self.sample)(t)?;
+            if let Some(prev) = prev {
+                self.refine(prev, curr, 0)?;
+            }
+            self.events.push(AdaptiveEvent::Point(curr.point));
+            prev = Some(curr);
+        }
+        Ok(self.events)
+    }
+
+    fn refine(&mut self, begin: Sample, end: Sample, depth: u32) -> exception::Result<()> {
+        if begin.is_nan() && end.is_nan() {
+            return Ok(());
+        }
+        //NaN的边界（如ln在0附近）或跳跃处，细分到底仍然不连续
+        let continuous = !begin.is_nan() && !end.is_nan() && distance(begin.screen, end.screen) <= self.tolerance;
+        if depth >= self.max_depth {
+            if !continuous {
+                self.add_break();
+            }
+            return Ok(());
+        }
+        //点数用完后不再细分，但不能据此判断不连续
+        if self.budget_left == 0 {
+            return Ok(());
+        }
+
+        let middle = (self.sample)((begin.t + end.t) / 2.0)?;
+        self.budget_left -= 1;
+        let flat = !middle.is_nan() && {
+            let chord_middle = ((begin.screen.0 + end.screen.0) / 2.0, (begin.screen.1 + end.screen.1) / 2.0);
+            distance(middle.screen, chord_middle) <= self.tolerance / 4.0
+        };
+        if continuous && flat {
+            return Ok(());
+        }
+
+        self.refine(begin, middle, depth + 1)?;
+        if !middle.is_nan() {
+            self.events.push(AdaptiveEvent::Point(middle.point));
+        }
+        self.refine(middle, end, depth + 1)
+    }
+
+    ///相邻的不连续标记只保留一个
+    fn add_break(&mut self) {
+        if let Some(AdaptiveEvent::Point(_)) = self.events.last() {
+            self.events.push(AdaptiveEvent::Break);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sampler_events(f: impl Fn(f64) -> f64, ts: &[f64], options: &AdaptiveOptions) -> Vec<AdaptiveEvent> {
+        let sample = |t: f64| {
+            let point = (t, f(t));
+            Ok(Sample { t, point, screen: point })
+        };
+        AdaptiveSampler::new(sample, options, 1.0).run(ts.iter().copied()).unwrap()
+    }
+
+    #[test]
+    fn test_adaptive_line() {
+        //直线只按距离细分，中点都在连线上
+        let options = AdaptiveOptions::new().tolerance(0.25);
+        let events = sampler_events(|t| t, &[0.0, 1.0], &options);
+        let points: Vec<AdaptiveEvent> = [0.0, 0.125, 0.25, 0.375, 0.5, 0.625, 0.75, 0.875, 1.0].iter()
+            .map(|&t| AdaptiveEvent::Point((t, t))).collect();
+        assert_eq!(events, points);
+    }
+
+    #[test]
+    fn test_adaptive_discontinuity() {
+        //阶跃函数在0.3处断开，两侧的点越来越靠近0.3，但不会被连起来
+        let options = AdaptiveOptions::new().tolerance(0.5).max_depth(6);
+        let events = sampler_events(|t| if t < 0.3 { 0.0 } else { 10.0 }, &[0.0, 1.0], &options);
+        let break_index = events.iter().position(|e| *e == AdaptiveEvent::Break).unwrap();
+        assert_eq!(events.iter().filter(|e| **e == AdaptiveEvent::Break).count(), 1);
+        match (events[break_index - 1], events[break_index + 1]) {
+            (AdaptiveEvent::Point(before), AdaptiveEvent::Point(after)) => {
+                assert!(before.0 < 0.3 && after.0 >= 0.3 && after.0 - before.0 < 0.02);
+            }
+            _ => panic!("break should be surrounded by points"),
+        }
+    }
+
+    #[test]
+    fn test_adaptive_budget() {
+        let options = AdaptiveOptions::new().tolerance(0.0001).point_budget(5);
+        let events = sampler_events(|t| t, &[0.0, 1.0], &options);
+        assert_eq!(events.len(), 2 + 5);
+    }
+}
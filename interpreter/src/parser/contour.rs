@@ -0,0 +1,130 @@
+///网格上的采样值，values[j * (resolution + 1) + i]是点(x_i, y_j)处的值
+pub struct Grid<'a> {
+    x_range: (f64, f64),
+    y_range: (f64, f64),
+    resolution: usize,
+    values: &'a [f64],
+}
+
+impl<'a> Grid<'a> {
+    pub fn new(x_range: (f64, f64), y_range: (f64, f64), resolution: usize, values: &'a [f64]) -> Self {
+        assert_eq!(values.len(), (resolution + 1) * (resolution + 1));
+        Grid {
+            x_range,
+            y_range,
+            resolution,
+            values,
+        }
+    }
+
+    ///第i列网格线的横坐标
+    pub fn x(&self, i: usize) -> f64 {
+        self.x_range.0 + (self.x_range.1 - self.x_range.0) * i as f64 / self.resolution as f64
+    }
+
+    ///第j行网格线的纵坐标
+    pub fn y(&self, j: usize) -> f64 {
+        self.y_range.0 + (self.y_range.1 - self.y_range.0) * j as f64 / self.resolution as f64
+    }
+
+    fn value(&self, i: usize, j: usize) -> f64 {
+        self.values[j * (self.resolution + 1) + i]
+    }
+}
+
+///线段的两个端点
+pub type Segment = ((f64, f64), (f64, f64));
+
+///在两个角点之间按线性插值找到零点，两者同号时没有零点
+fn edge_crossing(p1: (f64, f64), v1: f64, p2: (f64, f64), v2: f64) -> Option<(f64, f64)> {
+    if (v1 > 0.0) == (v2 > 0.0) {
+        return None;
+    }
+    let ratio = v1 / (v1 - v2);
+    Some((p1.0 + (p2.0 - p1.0) * ratio, p1.1 + (p2.1 - p1.1) * ratio))
+}
+
+///marching squares，求出F(x,y)=0在每个网格中的线段。含NaN的网格会被跳过
+pub fn marching_squares(grid: &Grid) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    for j in 0..grid.resolution {
+        for i in 0..grid.resolution {
+            //左下、右下、右上、左上四个角点
+            let corners = [
+                ((grid.x(i), grid.y(j)), grid.value(i, j)),
+                ((grid.x(i + 1), grid.y(j)), grid.value(i + 1, j)),
+                ((grid.x(i + 1), grid.y(j + 1)), grid.value(i + 1, j + 1)),
+                ((grid.x(i), grid.y(j + 1)), grid.value(i, j + 1)),
+            ];
+            if corners.iter().any(|(_, value)| value.is_nan()) {
+                continue;
+            }
+
+            //下、右、上、左四条边上的零点
+            let crossings: Vec<Option<(f64, f64)>> = (0..4).map(|k| {
+                let (p1, v1) = corners[k];
+                let (p2, v2) = corners[(k + 1) % 4];
+                edge_crossing(p1, v1, p2, v2)
+            }).collect();
+            let points: Vec<(f64, f64)> = crossings.iter().flatten().copied().collect();
+
+            match points.len() {
+                2 => segments.push((points[0], points[1])),
+                4 => {
+                    //鞍点，用网格中心的值判断哪两个对角相连
+                    let center = corners.iter().map(|(_, value)| value).sum::<f64>() / 4.0;
+                    let bottom_left_positive = corners[0].1 > 0.0;
+                    if (center > 0.0) == bottom_left_positive {
+                        //左下与右上相连，切开右下角和左上角
+                        segments.push((points[0], points[1]));
+                        segments.push((points[2], points[3]));
+                    } else {
+                        //左下角和右上角各自被切开
+                        segments.push((points[3], points[0]));
+                        segments.push((points[1], points[2]));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_marching_squares() {
+        //x*x+y*y-1在[-2,2]*[-2,2]上
+        let resolution = 8;
+        let grid_x = |i: usize| -2.0 + 4.0 * i as f64 / resolution as f64;
+        let mut values = Vec::new();
+        for j in 0..=resolution {
+            for i in 0..=resolution {
+                values.push(grid_x(i) * grid_x(i) + grid_x(j) * grid_x(j) - 1.0);
+            }
+        }
+        let segments = marching_squares(&Grid::new((-2.0, 2.0), (-2.0, 2.0), resolution, &values));
+        assert!(!segments.is_empty());
+        for (p1, p2) in segments {
+            for (x, y) in [p1, p2] {
+                let r = (x * x + y * y).sqrt();
+                assert!((r - 1.0).abs() < 0.1, "({}, {}) is not on the circle", x, y);
+            }
+        }
+
+        //没有零点
+        let values = vec![1.0; 9];
+        assert!(marching_squares(&Grid::new((0.0, 1.0), (0.0, 1.0), 2, &values)).is_empty());
+    }
+
+    #[test]
+    fn test_marching_squares_saddle() {
+        //左下和右上为正，中心不为正，两个正角各自被切开
+        let values = [1.0, -1.0, -1.0, 1.0];
+        let segments = marching_squares(&Grid::new((0.0, 1.0), (0.0, 1.0), 1, &values));
+        assert_eq!(segments, vec![((0.0, 0.5), (0.5, 0.0)), ((1.0, 0.5), (0.5, 1.0))]);
+    }
+}
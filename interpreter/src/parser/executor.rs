@@ -0,0 +1,578 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Instant;
+
+use crate::lexer::TokenTypeEnum;
+use crate::exception;
+use crate::options;
+use super::{adaptive, contour, expression, parallel, point_manager, statement};
+
+//计算迭代次数时容忍的相对浮点误差，使0到1步长0.1这样的循环包含终点。
+//(to - from) / step的舍入误差随迭代次数增大，容差也按迭代次数放大
+const LOOP_COUNT_EPSILON: f64 = 1e-9;
+
+///执行器：按顺序执行分析得到的语句，生成点序列。
+///不依赖词法、语法分析器，同一个程序可以在不同的设置下执行
+pub struct Executor {
+    //点生成与管理器
+    point_manager: point_manager::PointManager,
+    //ADAPTIVE采样的选项
+    adaptive_options: options::AdaptiveOptions,
+    //每条FOR语句最多迭代的次数
+    max_loop_iterations: usize,
+    //资源限制
+    resource_limits: options::ResourceLimits,
+    //开始执行的时间，用于判断超时
+    started_at: Option<Instant>,
+    //已经执行的语句数
+    executed_statements: usize,
+    //是否把循环中反复计算的表达式编译为字节码
+    compile_expressions: bool,
+    //是否在执行循环前化简表达式
+    optimize_expressions: bool,
+    //计算FOR DRAW的点时使用的线程数，为1时不创建线程
+    worker_threads: usize,
+    //宿主程序设置的外部参数，参数名->值
+    parameter_values: HashMap<String, f64>,
+    //负数的小数次方、PIECEWISE没有分支匹配的次数，与语法树共用
+    negative_base_counter: Rc<Cell<usize>>,
+    unmatched_piecewise_counter: Rc<Cell<usize>>,
+}
+
+impl Executor {
+    pub fn new(tracer: &Rc<RefCell<options::Tracer>>, negative_base_counter: &Rc<Cell<usize>>,
+               unmatched_piecewise_counter: &Rc<Cell<usize>>) -> Self {
+        Executor {
+            point_manager: point_manager::PointManager::new(tracer),
+            adaptive_options: options::AdaptiveOptions::new(),
+            max_loop_iterations: super::DEFAULT_MAX_LOOP_ITERATIONS,
+            resource_limits: options::ResourceLimits::new(),
+            started_at: None,
+            executed_statements: 0,
+            compile_expressions: true,
+            optimize_expressions: true,
+            worker_threads: 1,
+            parameter_values: HashMap::new(),
+            negative_base_counter: negative_base_counter.clone(),
+            unmatched_piecewise_counter: unmatched_piecewise_counter.clone(),
+        }
+    }
+
+    ///开始执行一个程序：清空上次执行的点序列与坐标变换，把循环变量重置为0，放入宿主程序设置的外部参数，记录开始时间
+    pub fn start(&mut self, program: &statement::Program) {
+        self.point_manager.reset();
+        for loop_variable in program.loop_variables() {
+            *loop_variable.borrow_mut() = 0.0;
+        }
+        //没有设置的参数在执行PARAM语句时计算默认值
+        for (name, value) in program.external_parameters() {
+            *value.borrow_mut() = self.parameter_values.get(name).copied().unwrap_or(f64::NAN);
+        }
+        self.started_at = Some(Instant::now());
+        self.executed_statements = 0;
+    }
+
+    ///执行一条顶层语句，返回执行时产生的警告，其中还没有附上源码
+    pub fn execute(&mut self, program_statement: &statement::ProgramStatement)
+                   -> exception::Result<Vec<exception::InterpretWarning>> {
+        self.negative_base_counter.set(0);
+        self.unmatched_piecewise_counter.set(0);
+        let mut discard_count = point_manager::DiscardCount::default();
+        match program_statement.statement() {
+            //定义语句不计入已执行的语句数
+            statement::Statement::Def(assignment) | statement::Statement::Let(assignment) => assignment.assign(),
+            statement::Statement::Fn(_) => {}
+            statement::Statement::Param(parameter) => self.assign_parameter(parameter)?,
+            statement => self.execute_statement(statement, None, &mut discard_count)?,
+        }
+
+        //被丢弃的点合并为警告
+        let mut warnings = Vec::new();
+        let statement_span = program_statement.span();
+        if discard_count.nan() > 0 {
+            warnings.push(exception::InterpretWarning::new(
+                exception::WarningKind::DiscardedNanPoints,
+                &format!("{} point(s) discarded because a coordinate is NaN", discard_count.nan()),
+                statement_span,
+            ));
+        }
+        if discard_count.out_of_range() > 0 {
+            warnings.push(exception::InterpretWarning::new(
+                exception::WarningKind::OutOfRangePoints,
+                &format!("{} point(s) discarded because they are out of the coordinate range", discard_count.out_of_range()),
+                statement_span,
+            ));
+        }
+        //求值中产生NaN的警告的区间包括分号
+        let statement_span = statement_span.merge(&program_statement.end_span());
+        let negative_base_count = self.negative_base_counter.take();
+        if negative_base_count > 0 {
+            warnings.push(exception::InterpretWarning::new(
+                exception::WarningKind::NegativeBaseFractionalPower,
+                &format!("a negative number was raised to a fractional power {} time(s), producing NaN", negative_base_count),
+                statement_span,
+            ));
+        }
+        let unmatched_piecewise_count = self.unmatched_piecewise_counter.take();
+        if unmatched_piecewise_count > 0 {
+            warnings.push(exception::InterpretWarning::new(
+                exception::WarningKind::UnmatchedPiecewise,
+                &format!("no branch of PIECEWISE matched {} time(s), producing NaN", unmatched_piecewise_count),
+                statement_span,
+            ));
+        }
+        Ok(warnings)
+    }
+
+    ///执行语句。series_index为直接包含该语句的循环的点序列
+    fn execute_statement(&mut self, statement: &statement::Statement, series_index: Option<usize>,
+                         discard_count: &mut point_manager::DiscardCount) -> exception::Result<()> {
+        self.count_executed_statement()?;
+        match statement {
+            statement::Statement::Origin(x, y) => {
+                let origin = (x.node().calculate()?, y.node().calculate()?);
+                self.point_manager().set_var_origin(origin);
+            }
+            statement::Statement::Scale(x, y) => {
+                let scale = (x.node().calculate()?, y.node().calculate()?);
+                self.point_manager().set_var_scale(scale);
+            }
+            statement::Statement::Rot(r) => {
+                let rot = r.node().calculate()?;
+                self.point_manager().set_var_rot(rot);
+            }
+            statement::Statement::For(for_statement) => self.execute_for_statement(for_statement, discard_count)?,
+            statement::Statement::Draw(coordinate_system, x, y) => {
+                //DRAW只会出现在循环体中，此时直接包含它的循环一定有点序列
+                if let Some(series_index) = series_index {
+                    let mut point_evaluator = self.point_evaluator(*coordinate_system, x.node(), y.node(), false);
+                    self.draw_point(series_index, &mut point_evaluator, discard_count)?;
+                }
+            }
+            statement::Statement::If(condition, then_branch, else_branch) => {
+                let branch = if expression::is_truthy(condition.node().calculate()?) { then_branch } else { else_branch };
+                for statement in branch {
+                    self.execute_statement(statement, series_index, discard_count)?;
+                }
+            }
+            statement::Statement::Implicit(implicit_statement) =>
+                self.execute_implicit_statement(implicit_statement, discard_count)?,
+            statement::Statement::Def(assignment) | statement::Statement::Let(assignment) => assignment.assign(),
+            statement::Statement::Fn(_) => {}
+            statement::Statement::Param(parameter) => self.assign_parameter(parameter)?,
+        }
+        Ok(())
+    }
+
+    ///执行PARAM，宿主程序设置的值优先于默认值
+    fn assign_parameter(&self, parameter: &statement::ParameterStatement) -> exception::Result<()> {
+        parameter.assign(self.parameter_values.get(parameter.name()).copied())
+    }
+
+    ///执行循环，生成所有点
+    fn execute_for_statement(&mut self, for_statement: &statement::ForStatement,
+                             discard_count: &mut point_manager::DiscardCount) -> exception::Result<()> {
+        let from = for_statement.from().node().calculate()?;
+        let to = for_statement.to().node().calculate()?;
+        let step = for_statement.step().node().calculate()?;
+        let iteration_count = self.loop_iteration_count(for_statement.loop_variable_name(), from, to, step)?;
+
+        let series_index = if for_statement.draws_directly() {
+            Some(self.point_manager().begin_series(
+                point_manager::SeriesKind::Curve, for_statement.span(), for_statement.loop_variable_name(), from, to, step,
+            ))
+        } else {
+            None
+        };
+
+        //循环体只有DRAW时，坐标表达式只化简、编译一次
+        let loop_variable = for_statement.loop_variable();
+        let (optimized_x, optimized_y);
+        let mut point_evaluator = match for_statement.body() {
+            statement::ForBody::Draw(coordinate_system, x, y) | statement::ForBody::AdaptiveDraw(coordinate_system, x, y) => {
+                let varying = [loop_variable.clone()];
+                optimized_x = self.optimize_expression(x.node(), &varying);
+                optimized_y = self.optimize_expression(y.node(), &varying);
+                Some(self.point_evaluator(
+                    *coordinate_system,
+                    optimized_x.as_deref().unwrap_or(x.node()),
+                    optimized_y.as_deref().unwrap_or(y.node()),
+                    self.compile_expressions,
+                ))
+            }
+            statement::ForBody::Block(_) => None,
+        };
+
+        let saved_value = *loop_variable.borrow();
+        let result = self.execute_for_iterations(
+            for_statement, from, step, iteration_count, series_index, point_evaluator.as_mut(), discard_count,
+        );
+        //嵌套的循环结束（包括出错）后恢复循环变量的值，使外层同名的循环变量不受影响
+        if for_statement.is_nested() {
+            *loop_variable.borrow_mut() = saved_value;
+        }
+        result
+    }
+
+    ///执行循环的各次迭代。point_evaluator为循环体只有DRAW时化简、编译好的坐标表达式
+    #[allow(clippy::too_many_arguments)]
+    fn execute_for_iterations(&mut self, for_statement: &statement::ForStatement, from: f64, step: f64,
+                              iteration_count: usize, series_index: Option<usize>,
+                              mut point_evaluator: Option<&mut statement::PointEvaluator>,
+                              discard_count: &mut point_manager::DiscardCount) -> exception::Result<()> {
+        let loop_variable = for_statement.loop_variable();
+        //迭代次数足够多时先由工作线程计算，出错时从出错的迭代开始按顺序执行
+        let mut first_index = 0;
+        if let (statement::ForBody::Draw(..), Some(series_index), Some(point_evaluator))
+            = (for_statement.body(), series_index, point_evaluator.as_deref()) {
+            if parallel::worker_count(self.worker_threads, iteration_count) > 1 {
+                first_index = self.execute_parallel_draw(
+                    series_index, loop_variable, from, step, iteration_count, point_evaluator, discard_count,
+                )?;
+            }
+        }
+        //ADAPTIVE的初始点
+        let mut initial_ts = Vec::new();
+        for i in first_index..iteration_count {
+            //由下标计算循环变量，避免累加误差
+            let curr_t = from + i as f64 * step;
+            *loop_variable.borrow_mut() = curr_t;
+            match for_statement.body() {
+                statement::ForBody::Draw(..) => {
+                    self.count_executed_statement()?;
+                    if let (Some(series_index), Some(point_evaluator)) = (series_index, point_evaluator.as_deref_mut()) {
+                        self.draw_point(series_index, point_evaluator, discard_count)?;
+                    }
+                }
+                statement::ForBody::Block(statements) => {
+                    self.check_execution_limits()?;
+                    for statement in statements {
+                        self.execute_statement(statement, series_index, discard_count)?;
+                    }
+                }
+                statement::ForBody::AdaptiveDraw(..) => initial_ts.push(curr_t),
+            }
+        }
+        if let (statement::ForBody::AdaptiveDraw(..), Some(series_index), Some(point_evaluator))
+            = (for_statement.body(), series_index, point_evaluator) {
+            self.execute_adaptive_draw(series_index, loop_variable, initial_ts, point_evaluator, discard_count)?;
+        }
+        Ok(())
+    }
+
+    ///检查循环的范围和步长，返回迭代次数。STEP为负时从FROM递减到TO
+    fn loop_iteration_count(&self, loop_variable_name: &str, from: f64, to: f64, step: f64) -> exception::Result<usize> {
+        for (bound, value) in [(TokenTypeEnum::From, from), (TokenTypeEnum::To, to), (TokenTypeEnum::Step, step)] {
+            if !value.is_finite() {
+                return Err(exception::NonFiniteLoopRangeError::new(loop_variable_name, bound, value));
+            }
+        }
+        if step == 0.0 {
+            return Err(exception::ZeroStepError::new(loop_variable_name));
+        }
+        let intervals = (to - from) / step;
+        let tolerance = intervals.abs().max(1.0) * LOOP_COUNT_EPSILON;
+        if intervals < -tolerance {
+            return Err(exception::StepDirectionError::new(loop_variable_name, from, to, step));
+        }
+        let iteration_count = (intervals + tolerance).floor() + 1.0;
+        if iteration_count > self.max_loop_iterations as f64 {
+            return Err(exception::IterationLimitExceededError::new(
+                loop_variable_name, iteration_count, self.max_loop_iterations,
+            ));
+        }
+        Ok(iteration_count as usize)
+    }
+
+    ///由工作线程计算各次迭代的点，再按迭代顺序添加，资源限制与警告的计数与顺序执行相同。
+    ///返回需要继续按顺序执行的第一个迭代下标，没有编译为字节码时从头开始
+    #[allow(clippy::too_many_arguments)]
+    fn execute_parallel_draw(&mut self, series_index: usize, loop_variable: &Rc<RefCell<f64>>,
+                             from: f64, step: f64, iteration_count: usize,
+                             point_evaluator: &statement::PointEvaluator,
+                             discard_count: &mut point_manager::DiscardCount) -> exception::Result<usize> {
+        let (Some(parallel_draw), Some((counter, _))) =
+            (parallel::ParallelDraw::new(point_evaluator, loop_variable), point_evaluator.compiled()) else {
+            return Ok(0);
+        };
+        let (resource_limits, started_at) = (&self.resource_limits, self.started_at);
+        let chunks = parallel_draw.run(
+            from, step, iteration_count, self.worker_threads,
+            &|| check_execution_limits(resource_limits, started_at).is_err(),
+        );
+
+        for chunk in chunks {
+            for (i, drawn_point) in chunk.points() {
+                *loop_variable.borrow_mut() = from + i as f64 * step;
+                self.count_executed_statement()?;
+                counter.add_counts(drawn_point.negative_base_count, drawn_point.unmatched_piecewise_count);
+                self.check_point_limit(1)?;
+                let mut coordinate = drawn_point.point;
+                if let Err(reason) = self.point_manager().add_point(series_index, &mut coordinate) {
+                    discard_count.record(reason);
+                }
+            }
+            if let Some(stopped_at) = chunk.stopped_at() {
+                return Ok(stopped_at);
+            }
+        }
+        Ok(iteration_count)
+    }
+
+    ///以固定步长的点为初始点，在屏幕上距离过大或弯曲的地方细分，不连续处会被标记
+    fn execute_adaptive_draw(&mut self, series_index: usize, loop_variable: &Rc<RefCell<f64>>, initial_ts: Vec<f64>,
+                             point_evaluator: &mut statement::PointEvaluator,
+                             discard_count: &mut point_manager::DiscardCount) -> exception::Result<()> {
+        let point_manager = &self.point_manager;
+        let (resource_limits, started_at) = (&self.resource_limits, self.started_at);
+        let executed_statements = &mut self.executed_statements;
+        //每个采样点（包括初始点与细分出的点）与普通DRAW的一次迭代一样计为执行了一条语句
+        let sample = |t: f64| {
+            count_executed_statement(executed_statements, resource_limits, started_at)?;
+            *loop_variable.borrow_mut() = t;
+            let point = point_evaluator.evaluate()?;
+            Ok(adaptive::Sample { t, point, screen: point_manager.transform(point) })
+        };
+        let events = adaptive::AdaptiveSampler::new(sample, &self.adaptive_options, point_manager.coordinate_diagonal())
+            .run(initial_ts.into_iter())?;
+
+        for event in events {
+            match event {
+                adaptive::AdaptiveEvent::Point(mut point) => {
+                    self.check_point_limit(1)?;
+                    if let Err(reason) = self.point_manager().add_point(series_index, &mut point) {
+                        discard_count.record(reason);
+                    }
+                }
+                adaptive::AdaptiveEvent::Break => self.point_manager().mark_discontinuity(series_index),
+            }
+        }
+        Ok(())
+    }
+
+    ///在网格上采样，用marching squares生成等值线
+    fn execute_implicit_statement(&mut self, implicit_statement: &statement::ImplicitStatement,
+                                  discard_count: &mut point_manager::DiscardCount) -> exception::Result<()> {
+        let [x_min, x_max, y_min, y_max] = implicit_statement.bounds();
+        let x_range = (x_min.node().calculate()?, x_max.node().calculate()?);
+        let y_range = (y_min.node().calculate()?, y_max.node().calculate()?);
+        let resolution = implicit_statement.resolution().node().calculate()?;
+        if !(resolution >= 1.0 && resolution.fract() == 0.0 && resolution.is_finite()) {
+            return Err(exception::InvalidResolutionError::new(resolution));
+        }
+        let resolution = resolution as usize;
+
+        let series_index = self.point_manager().begin_series(
+            point_manager::SeriesKind::Contour, implicit_statement.span(), super::IMPLICIT_X_VARIABLE,
+            x_range.0, x_range.1, (x_range.1 - x_range.0) / resolution as f64,
+        );
+
+        //X、Y可能同时是外层循环的循环变量，采样后恢复
+        let (x_variable, y_variable) = (implicit_statement.x_variable(), implicit_statement.y_variable());
+        let saved_values = (*x_variable.borrow(), *y_variable.borrow());
+        let optimized = self.optimize_expression(implicit_statement.expression().node(), &[x_variable.clone(), y_variable.clone()]);
+        let expression = optimized.as_deref().unwrap_or(implicit_statement.expression().node());
+        let mut evaluator = self.evaluator(expression, self.compile_expressions);
+        let mut values = Vec::with_capacity((resolution + 1) * (resolution + 1));
+        let sample_result = (|| {
+            for j in 0..=resolution {
+                self.check_execution_limits()?;
+                *y_variable.borrow_mut() = y_range.0 + (y_range.1 - y_range.0) * j as f64 / resolution as f64;
+                for i in 0..=resolution {
+                    *x_variable.borrow_mut() = x_range.0 + (x_range.1 - x_range.0) * i as f64 / resolution as f64;
+                    values.push(evaluator.evaluate()?);
+                }
+            }
+            Ok(())
+        })();
+        *x_variable.borrow_mut() = saved_values.0;
+        *y_variable.borrow_mut() = saved_values.1;
+        sample_result?;
+
+        let grid = contour::Grid::new(x_range, y_range, resolution, &values);
+        for mut segment in contour::marching_squares(&grid) {
+            self.check_point_limit(2)?;
+            if let Err(reason) = self.point_manager().add_segment(series_index, &mut segment) {
+                discard_count.record(reason);
+            }
+        }
+        Ok(())
+    }
+
+    ///计算并添加一个点，记录被丢弃的点。极坐标会先转换为直角坐标，再进行坐标变换
+    fn draw_point(&mut self, series_index: usize, point_evaluator: &mut statement::PointEvaluator,
+                  discard_count: &mut point_manager::DiscardCount) -> exception::Result<()> {
+        let mut coordinate = point_evaluator.evaluate()?;
+        self.check_point_limit(1)?;
+        if let Err(reason) = self.point_manager().add_point(series_index, &mut coordinate) {
+            discard_count.record(reason);
+        }
+        Ok(())
+    }
+
+    ///检查是否被取消或超时。在每次迭代中调用
+    fn check_execution_limits(&self) -> exception::Result<()> {
+        check_execution_limits(&self.resource_limits, self.started_at)
+    }
+
+    ///记录执行了一条语句，并检查资源限制
+    fn count_executed_statement(&mut self) -> exception::Result<()> {
+        count_executed_statement(&mut self.executed_statements, &self.resource_limits, self.started_at)
+    }
+
+    ///检查再添加new_points个点是否会超过点数上限
+    fn check_point_limit(&self, new_points: usize) -> exception::Result<()> {
+        if let Some(max_points) = self.resource_limits.get_max_points() {
+            if self.point_manager.total_points() + new_points > max_points {
+                return Err(exception::PointLimitExceededError::new(max_points));
+            }
+        }
+        Ok(())
+    }
+
+    ///化简循环中反复计算的表达式，varying为会变化的循环变量，不依赖它们的子树会被提前求值。关闭化简时返回None
+    fn optimize_expression(&self, expression: &dyn expression::ASTNode,
+                           varying: &[Rc<RefCell<f64>>]) -> Option<Box<dyn expression::ASTNode>> {
+        if !self.optimize_expressions {
+            return None;
+        }
+        let optimizer = expression::Optimizer::new(varying, &self.negative_base_counter, &self.unmatched_piecewise_counter);
+        Some(optimizer.optimize(expression))
+    }
+
+    ///创建点坐标的求值器，compile为true时把两个表达式编译为字节码
+    fn point_evaluator<'a>(&self, coordinate_system: statement::CoordinateSystem,
+                           x: &'a dyn expression::ASTNode, y: &'a dyn expression::ASTNode,
+                           compile: bool) -> statement::PointEvaluator<'a> {
+        statement::PointEvaluator::new(
+            coordinate_system,
+            self.evaluator(x, compile),
+            self.evaluator(y, compile),
+        )
+    }
+
+    ///创建表达式的求值器，compile为true时编译为字节码
+    fn evaluator<'a>(&self, expression: &'a dyn expression::ASTNode, compile: bool) -> expression::Evaluator<'a> {
+        if !compile {
+            return expression::Evaluator::Tree(expression);
+        }
+        expression::Evaluator::Compiled(expression::CompiledEvaluator::new(
+            expression::Compiler::compile(expression), &self.negative_base_counter, &self.unmatched_piecewise_counter,
+        ))
+    }
+
+    fn point_manager(&mut self) -> &mut point_manager::PointManager {
+        &mut self.point_manager
+    }
+
+    ///取出已经生成的点序列
+    pub fn take_series(&mut self) -> Vec<point_manager::PointSeries> {
+        self.point_manager.move_series_storage()
+    }
+
+    pub fn set_coordinate_range(&mut self, min_x: f64, max_x: f64, min_y: f64, max_y: f64) {
+        self.point_manager.set_coordinate_range(min_x, max_x, min_y, max_y);
+    }
+
+    pub fn set_resource_limits(&mut self, resource_limits: options::ResourceLimits) {
+        self.resource_limits = resource_limits;
+    }
+
+    pub fn resource_limits(&self) -> &options::ResourceLimits {
+        &self.resource_limits
+    }
+
+    pub fn set_max_loop_iterations(&mut self, max_loop_iterations: usize) {
+        self.max_loop_iterations = max_loop_iterations;
+    }
+
+    pub fn set_adaptive_options(&mut self, adaptive_options: options::AdaptiveOptions) {
+        self.adaptive_options = adaptive_options;
+    }
+
+    pub fn set_compile_expressions(&mut self, compile_expressions: bool) {
+        self.compile_expressions = compile_expressions;
+    }
+
+    pub fn set_optimize_expressions(&mut self, optimize_expressions: bool) {
+        self.optimize_expressions = optimize_expressions;
+    }
+
+    pub fn set_parameter(&mut self, name: &str, value: f64) {
+        self.parameter_values.insert(String::from(name), value);
+    }
+
+    pub fn set_worker_threads(&mut self, worker_threads: usize) {
+        self.worker_threads = worker_threads;
+    }
+}
+
+///检查是否被取消或超时。工作线程中也会调用
+fn check_execution_limits(resource_limits: &options::ResourceLimits,
+                          started_at: Option<Instant>) -> exception::Result<()> {
+    if let Some(cancellation_token) = resource_limits.get_cancellation_token() {
+        if cancellation_token.is_cancelled() {
+            return Err(exception::CancelledError::new());
+        }
+    }
+    if let (Some(timeout), Some(started_at)) = (resource_limits.get_timeout(), started_at) {
+        if started_at.elapsed() > timeout {
+            return Err(exception::TimeoutError::new(timeout));
+        }
+    }
+    Ok(())
+}
+
+///记录执行了一条语句，并检查资源限制。ADAPTIVE采样时也会调用
+fn count_executed_statement(executed_statements: &mut usize, resource_limits: &options::ResourceLimits,
+                            started_at: Option<Instant>) -> exception::Result<()> {
+    *executed_statements += 1;
+    if let Some(max_statements) = resource_limits.get_max_statements() {
+        if *executed_statements > max_statements {
+            return Err(exception::StatementLimitExceededError::new(max_statements));
+        }
+    }
+    check_execution_limits(resource_limits, started_at)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_executor() -> Executor {
+        let counter = Rc::new(Cell::new(0));
+        Executor::new(&Rc::new(RefCell::new(options::Tracer::new())), &counter, &counter)
+    }
+
+    #[test]
+    fn test_loop_iteration_count_large() {
+        let mut executor = new_executor();
+        executor.set_max_loop_iterations(usize::MAX);
+        //迭代次数很大时(to - from) / step的舍入误差超过1e-9，终点仍应被包含
+        let count = executor.loop_iteration_count("T", 0.0, 1400000.0, 0.07).unwrap();
+        assert_eq!(count, 20_000_001);
+        assert!(((count - 1) as f64 * 0.07 - 1400000.0).abs() < 1e-6);
+        assert_eq!(executor.loop_iteration_count("T", 0.0, 1.0, 0.1).unwrap(), 11);
+        assert_eq!(executor.loop_iteration_count("T", 0.0, 0.95, 0.1).unwrap(), 10);
+        assert!(executor.loop_iteration_count("T", 1.0, 0.0, 0.1).is_err());
+    }
+
+    #[test]
+    fn test_nested_loop_variable_restored_on_error() {
+        let mut interpreter_obj = crate::Interpreter::from_str("\
+For T from 1 to 1 step 1 do begin
+    For T from 5 to 6 step 1 do begin
+        For i from 1 to 0 step 1 draw(i, i);
+    end;
+end;");
+        let program = interpreter_obj.parse().unwrap();
+        let errors = interpreter_obj.execute(&program).unwrap_err();
+        assert_eq!(errors[0].kind(), crate::ErrorKind::StepDirectionError);
+        //内层循环出错后，外层同名循环变量仍为出错时外层的值
+        let statement::Statement::For(for_statement) = program.statements()[0].statement() else {
+            panic!("expected a FOR statement");
+        };
+        assert_eq!(*for_statement.loop_variable().borrow(), 1.0);
+    }
+}
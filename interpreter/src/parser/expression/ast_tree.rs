@@ -1,44 +1,55 @@
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::cell::{Cell, Ref, RefCell};
+use std::rc::{Rc, Weak};
 use crate::lexer;
 use crate::exception;
+use super::bytecode::{Compiler, Instruction};
+use super::optimizer::Optimizer;
 
 pub trait ASTNode {
     fn calculate(&self) -> exception::Result<f64>;
-    fn print_tree(&self, level: i32);
+    ///把子树编译为后缀字节码，求值结果留在栈顶
+    fn compile(&self, compiler: &mut Compiler);
+    ///返回化简后的新子树，原子树不变
+    fn optimize(&self, optimizer: &Optimizer) -> Box<dyn ASTNode>;
+    ///常数节点的值
+    fn constant_value(&self) -> Option<f64> {
+        None
+    }
+    ///把语法树以文本形式写入out
+    fn write_tree(&self, level: i32, out: &mut String);
 }
 
-//用于辅助print语法树的三个函数
-fn print_tree_prefix_tab(level: i32) {
-    print!("  |");
+//用于辅助输出语法树的三个函数
+fn write_tree_prefix_tab(level: i32, out: &mut String) {
+    out.push_str("  |");
     for _ in 0..level {
-        print!("       |");
+        out.push_str("       |");
     }
 }
 
-fn print_tree_prefix_begin(level: i32) {
+fn write_tree_prefix_begin(level: i32, out: &mut String) {
     if level == 0 {
-        print!("->/");
+        out.push_str("->/");
     } else {
-        print_tree_prefix_tab(level - 1);
-        print!("----->/");
+        write_tree_prefix_tab(level - 1, out);
+        out.push_str("----->/");
     }
 }
 
-fn print_tree_prefix_end(level: i32) {
+fn write_tree_prefix_end(level: i32, out: &mut String) {
     if level == 0 {
-        print!("  `");
+        out.push_str("  `");
     } else {
-        print_tree_prefix_tab(level - 1);
-        print!("       `");
+        write_tree_prefix_tab(level - 1, out);
+        out.push_str("       `");
     }
-    println!();
+    out.push('\n');
 }
 
 ///二元运算
 pub struct BinaryNode {
     token_type: lexer::TokenTypeEnum,
-    func: Rc<dyn Fn(&[f64]) -> exception::Result<f64>>,
+    func: lexer::TokenFunc,
     left: Box<dyn ASTNode>,
     right: Box<dyn ASTNode>,
 }
@@ -61,18 +72,413 @@ impl ASTNode for BinaryNode {
         (self.func)(&[left_result, right_result])
     }
 
-    fn print_tree(&self, level: i32) {
-        print_tree_prefix_begin(level);
-        println!("$ {:?}", self.token_type);
+    fn write_tree(&self, level: i32, out: &mut String) {
+        write_tree_prefix_begin(level, out);
+        out.push_str(&format!("$ {:?}\n", self.token_type));
+
+        write_tree_prefix_tab(level, out);
+        out.push('\n');
+        self.left.write_tree(level + 1, out);
+        // write_tree_prefix_tab(level, out);
+        // out.push('\n');
+        self.right.write_tree(level + 1, out);
+
+        write_tree_prefix_end(level, out);
+    }
+
+    fn optimize(&self, optimizer: &Optimizer) -> Box<dyn ASTNode> {
+        let left = self.left.optimize(optimizer);
+        let right = self.right.optimize(optimizer);
+        //恒等运算：x-0、x*1、1*x、x/1。x+0、0+x与0-x在x为±0时结果的符号不同，不做化简
+        let is = |node: &dyn ASTNode, value: f64| node.constant_value() == Some(value);
+        match self.token_type {
+            lexer::TokenTypeEnum::Minus if is(right.as_ref(), 0.0) => return left,
+            lexer::TokenTypeEnum::Mul if is(right.as_ref(), 1.0) => return left,
+            lexer::TokenTypeEnum::Mul if is(left.as_ref(), 1.0) => return right,
+            lexer::TokenTypeEnum::Div if is(right.as_ref(), 1.0) => return left,
+            _ => {}
+        }
+        let constant = left.constant_value().is_some() && right.constant_value().is_some();
+        let node = Box::new(BinaryNode {
+            token_type: self.token_type,
+            func: self.func.clone(),
+            left,
+            right,
+        });
+        if constant { optimizer.fold(node) } else { node }
+    }
+
+    fn compile(&self, compiler: &mut Compiler) {
+        self.left.compile(compiler);
+        self.right.compile(compiler);
+        //四则运算与比较直接由虚拟机完成，其他运算调用运算符的函数
+        let instruction = match self.token_type {
+            lexer::TokenTypeEnum::Plus => Instruction::Add,
+            lexer::TokenTypeEnum::Minus => Instruction::Sub,
+            lexer::TokenTypeEnum::Mul => Instruction::Mul,
+            lexer::TokenTypeEnum::Div => Instruction::Div,
+            lexer::TokenTypeEnum::Less => Instruction::Less,
+            lexer::TokenTypeEnum::LessEqual => Instruction::LessEqual,
+            lexer::TokenTypeEnum::Greater => Instruction::Greater,
+            lexer::TokenTypeEnum::GreaterEqual => Instruction::GreaterEqual,
+            lexer::TokenTypeEnum::Equal => Instruction::Equal,
+            lexer::TokenTypeEnum::NotEqual => Instruction::NotEqual,
+            _ => Instruction::Binary(self.func.clone()),
+        };
+        compiler.emit(instruction);
+    }
+}
+
+///条件是否成立：非0且非NaN即为真
+pub fn is_truthy(value: f64) -> bool {
+    value != 0.0 && !value.is_nan()
+}
+
+///逻辑与、逻辑或，短路求值。结果为1（真）或0（假）
+pub struct LogicNode {
+    token_type: lexer::TokenTypeEnum,
+    left: Box<dyn ASTNode>,
+    right: Box<dyn ASTNode>,
+}
+
+impl LogicNode {
+    pub fn new(token: &lexer::Token, left: Box<dyn ASTNode>, right: Box<dyn ASTNode>) -> Self {
+        LogicNode {
+            token_type: token.token_type(),
+            left,
+            right,
+        }
+    }
+}
+
+impl ASTNode for LogicNode {
+    fn calculate(&self) -> exception::Result<f64> {
+        let left_result = is_truthy(self.left.calculate()?);
+        let ans = match self.token_type {
+            lexer::TokenTypeEnum::And => left_result && is_truthy(self.right.calculate()?),
+            _ => left_result || is_truthy(self.right.calculate()?),
+        };
+        Ok(f64::from(ans))
+    }
+
+    fn optimize(&self, optimizer: &Optimizer) -> Box<dyn ASTNode> {
+        let left = self.left.optimize(optimizer);
+        //左侧为常数且短路时，右侧不会被计算
+        if let Some(left_value) = left.constant_value() {
+            match (self.token_type, is_truthy(left_value)) {
+                (lexer::TokenTypeEnum::And, false) => return Box::new(ConstNode::new(0.0)),
+                (lexer::TokenTypeEnum::Or, true) => return Box::new(ConstNode::new(1.0)),
+                _ => {}
+            }
+        }
+        let right = self.right.optimize(optimizer);
+        let constant = left.constant_value().is_some() && right.constant_value().is_some();
+        let node = Box::new(LogicNode {
+            token_type: self.token_type,
+            left,
+            right,
+        });
+        if constant { optimizer.fold(node) } else { node }
+    }
+
+    fn compile(&self, compiler: &mut Compiler) {
+        //短路时跳过右侧，直接压入结果
+        self.left.compile(compiler);
+        let short_circuit = match self.token_type {
+            lexer::TokenTypeEnum::And => compiler.emit(Instruction::JumpIfFalse(0)),
+            _ => compiler.emit(Instruction::JumpIfTrue(0)),
+        };
+        self.right.compile(compiler);
+        compiler.emit(Instruction::Truthy);
+        let end = compiler.emit(Instruction::Jump(0));
+        compiler.patch_jump(short_circuit);
+        let short_circuit_value = match self.token_type {
+            lexer::TokenTypeEnum::And => 0.0,
+            _ => 1.0,
+        };
+        compiler.emit(Instruction::Const(short_circuit_value));
+        compiler.patch_jump(end);
+    }
+
+    fn write_tree(&self, level: i32, out: &mut String) {
+        write_tree_prefix_begin(level, out);
+        out.push_str(&format!("$ {:?}\n", self.token_type));
+
+        write_tree_prefix_tab(level, out);
+        out.push('\n');
+        self.left.write_tree(level + 1, out);
+        self.right.write_tree(level + 1, out);
+
+        write_tree_prefix_end(level, out);
+    }
+}
+
+///逻辑非
+pub struct NotNode {
+    child: Box<dyn ASTNode>,
+}
+
+impl NotNode {
+    pub fn new(child: Box<dyn ASTNode>) -> Self {
+        NotNode {
+            child,
+        }
+    }
+}
+
+impl ASTNode for NotNode {
+    fn calculate(&self) -> exception::Result<f64> {
+        Ok(f64::from(!is_truthy(self.child.calculate()?)))
+    }
+
+    fn optimize(&self, optimizer: &Optimizer) -> Box<dyn ASTNode> {
+        let child = self.child.optimize(optimizer);
+        let constant = child.constant_value().is_some();
+        let node = Box::new(NotNode::new(child));
+        if constant { optimizer.fold(node) } else { node }
+    }
+
+    fn compile(&self, compiler: &mut Compiler) {
+        self.child.compile(compiler);
+        compiler.emit(Instruction::Not);
+    }
+
+    fn write_tree(&self, level: i32, out: &mut String) {
+        write_tree_prefix_begin(level, out);
+        out.push_str(&format!("$ {:?}\n", lexer::TokenTypeEnum::Not));
+
+        write_tree_prefix_tab(level, out);
+        out.push('\n');
+        self.child.write_tree(level + 1, out);
+
+        write_tree_prefix_end(level, out);
+    }
+}
+
+///条件表达式IF(cond,a,b)，只计算被选中的分支
+pub struct IfNode {
+    condition: Box<dyn ASTNode>,
+    then_node: Box<dyn ASTNode>,
+    else_node: Box<dyn ASTNode>,
+}
+
+impl IfNode {
+    pub fn new(condition: Box<dyn ASTNode>, then_node: Box<dyn ASTNode>, else_node: Box<dyn ASTNode>) -> Self {
+        IfNode {
+            condition,
+            then_node,
+            else_node,
+        }
+    }
+}
+
+impl ASTNode for IfNode {
+    fn calculate(&self) -> exception::Result<f64> {
+        if is_truthy(self.condition.calculate()?) {
+            self.then_node.calculate()
+        } else {
+            self.else_node.calculate()
+        }
+    }
 
-        print_tree_prefix_tab(level);
-        println!();
-        self.left.print_tree(level + 1);
-        // print_tree_prefix_tab(level);
-        // println!();
-        self.right.print_tree(level + 1);
+    fn optimize(&self, optimizer: &Optimizer) -> Box<dyn ASTNode> {
+        let condition = self.condition.optimize(optimizer);
+        //条件为常数时只保留被选中的分支
+        if let Some(condition_value) = condition.constant_value() {
+            return if is_truthy(condition_value) {
+                self.then_node.optimize(optimizer)
+            } else {
+                self.else_node.optimize(optimizer)
+            };
+        }
+        Box::new(IfNode::new(condition, self.then_node.optimize(optimizer), self.else_node.optimize(optimizer)))
+    }
 
-        print_tree_prefix_end(level);
+    fn compile(&self, compiler: &mut Compiler) {
+        self.condition.compile(compiler);
+        let else_jump = compiler.emit(Instruction::JumpIfFalse(0));
+        self.then_node.compile(compiler);
+        let end = compiler.emit(Instruction::Jump(0));
+        compiler.patch_jump(else_jump);
+        self.else_node.compile(compiler);
+        compiler.patch_jump(end);
+    }
+
+    fn write_tree(&self, level: i32, out: &mut String) {
+        write_tree_prefix_begin(level, out);
+        out.push_str(&format!("$ {:?}\n", lexer::TokenTypeEnum::If));
+
+        write_tree_prefix_tab(level, out);
+        out.push('\n');
+        self.condition.write_tree(level + 1, out);
+        self.then_node.write_tree(level + 1, out);
+        self.else_node.write_tree(level + 1, out);
+
+        write_tree_prefix_end(level, out);
+    }
+}
+
+///分段函数PIECEWISE((cond1,ex1),(cond2,ex2),ELSE ex3)
+///按顺序选中第一个条件成立的分支，只计算该分支。没有分支匹配且没有ELSE时结果为NaN，并统计次数以便给出警告
+pub struct PiecewiseNode {
+    branches: Vec<(Box<dyn ASTNode>, Box<dyn ASTNode>)>,
+    else_node: Option<Box<dyn ASTNode>>,
+    unmatched_counter: Rc<Cell<usize>>,
+}
+
+impl PiecewiseNode {
+    pub fn new(branches: Vec<(Box<dyn ASTNode>, Box<dyn ASTNode>)>, else_node: Option<Box<dyn ASTNode>>,
+               unmatched_counter: &Rc<Cell<usize>>) -> Self {
+        PiecewiseNode {
+            branches,
+            else_node,
+            unmatched_counter: unmatched_counter.clone(),
+        }
+    }
+}
+
+impl ASTNode for PiecewiseNode {
+    fn calculate(&self) -> exception::Result<f64> {
+        for (condition, branch) in &self.branches {
+            if is_truthy(condition.calculate()?) {
+                return branch.calculate();
+            }
+        }
+        match &self.else_node {
+            Some(else_node) => else_node.calculate(),
+            None => {
+                self.unmatched_counter.set(self.unmatched_counter.get() + 1);
+                Ok(f64::NAN)
+            }
+        }
+    }
+
+    fn optimize(&self, optimizer: &Optimizer) -> Box<dyn ASTNode> {
+        //去掉条件恒为假的分支，条件恒为真的分支之后的分支不会被选中
+        let mut branches = Vec::new();
+        for (condition, branch) in &self.branches {
+            let condition = condition.optimize(optimizer);
+            match condition.constant_value() {
+                Some(condition_value) if !is_truthy(condition_value) => continue,
+                Some(_) if branches.is_empty() => return branch.optimize(optimizer),
+                Some(_) => {
+                    return Box::new(PiecewiseNode::new(
+                        branches, Some(branch.optimize(optimizer)), &self.unmatched_counter,
+                    ));
+                }
+                None => branches.push((condition, branch.optimize(optimizer))),
+            }
+        }
+        let else_node = self.else_node.as_ref().map(|else_node| else_node.optimize(optimizer));
+        match else_node {
+            Some(else_node) if branches.is_empty() => else_node,
+            else_node => Box::new(PiecewiseNode::new(branches, else_node, &self.unmatched_counter)),
+        }
+    }
+
+    fn compile(&self, compiler: &mut Compiler) {
+        let mut end_jumps = Vec::new();
+        for (condition, branch) in &self.branches {
+            condition.compile(compiler);
+            let next_branch = compiler.emit(Instruction::JumpIfFalse(0));
+            branch.compile(compiler);
+            end_jumps.push(compiler.emit(Instruction::Jump(0)));
+            compiler.patch_jump(next_branch);
+        }
+        match &self.else_node {
+            Some(else_node) => else_node.compile(compiler),
+            None => {
+                compiler.emit(Instruction::Unmatched);
+            }
+        }
+        for end_jump in end_jumps {
+            compiler.patch_jump(end_jump);
+        }
+    }
+
+    fn write_tree(&self, level: i32, out: &mut String) {
+        write_tree_prefix_begin(level, out);
+        out.push_str(&format!("$ {:?}\n", lexer::TokenTypeEnum::Piecewise));
+
+        write_tree_prefix_tab(level, out);
+        out.push('\n');
+        for (condition, branch) in &self.branches {
+            condition.write_tree(level + 1, out);
+            branch.write_tree(level + 1, out);
+        }
+        if let Some(else_node) = &self.else_node {
+            write_tree_prefix_tab(level, out);
+            out.push_str(&format!(": {:?}\n", lexer::TokenTypeEnum::Else));
+            else_node.write_tree(level + 1, out);
+        }
+
+        write_tree_prefix_end(level, out);
+    }
+}
+
+///乘方。会统计负数的小数次方（结果为NaN）出现的次数，以便给出警告
+pub struct PowerNode {
+    func: lexer::TokenFunc,
+    left: Box<dyn ASTNode>,
+    right: Box<dyn ASTNode>,
+    negative_base_counter: Rc<Cell<usize>>,
+}
+
+impl PowerNode {
+    pub fn new(token: &lexer::Token, left: Box<dyn ASTNode>, right: Box<dyn ASTNode>,
+               negative_base_counter: &Rc<Cell<usize>>) -> Self {
+        PowerNode {
+            func: token.func().clone(),
+            left,
+            right,
+            negative_base_counter: negative_base_counter.clone(),
+        }
+    }
+}
+
+impl ASTNode for PowerNode {
+    fn calculate(&self) -> exception::Result<f64> {
+        let left_result = self.left.calculate()?;
+        let right_result = self.right.calculate()?;
+        if left_result < 0.0 && right_result.is_finite() && right_result.fract() != 0.0 {
+            self.negative_base_counter.set(self.negative_base_counter.get() + 1);
+        }
+        (self.func)(&[left_result, right_result])
+    }
+
+    fn optimize(&self, optimizer: &Optimizer) -> Box<dyn ASTNode> {
+        let left = self.left.optimize(optimizer);
+        let right = self.right.optimize(optimizer);
+        //x**1
+        if right.constant_value() == Some(1.0) {
+            return left;
+        }
+        let constant = left.constant_value().is_some() && right.constant_value().is_some();
+        let node = Box::new(PowerNode {
+            func: self.func.clone(),
+            left,
+            right,
+            negative_base_counter: self.negative_base_counter.clone(),
+        });
+        if constant { optimizer.fold(node) } else { node }
+    }
+
+    fn compile(&self, compiler: &mut Compiler) {
+        self.left.compile(compiler);
+        self.right.compile(compiler);
+        compiler.emit(Instruction::Power(self.func.clone()));
+    }
+
+    fn write_tree(&self, level: i32, out: &mut String) {
+        write_tree_prefix_begin(level, out);
+        out.push_str(&format!("$ {:?}\n", lexer::TokenTypeEnum::Power));
+
+        write_tree_prefix_tab(level, out);
+        out.push('\n');
+        self.left.write_tree(level + 1, out);
+        self.right.write_tree(level + 1, out);
+
+        write_tree_prefix_end(level, out);
     }
 }
 
@@ -94,11 +500,23 @@ impl ASTNode for ConstNode {
         Ok(self.value)
     }
 
-    fn print_tree(&self, level: i32) {
-        print_tree_prefix_begin(level);
-        println!("$ {:?}", self.value);
+    fn optimize(&self, _optimizer: &Optimizer) -> Box<dyn ASTNode> {
+        Box::new(ConstNode::new(self.value))
+    }
 
-        print_tree_prefix_end(level);
+    fn constant_value(&self) -> Option<f64> {
+        Some(self.value)
+    }
+
+    fn compile(&self, compiler: &mut Compiler) {
+        compiler.emit(Instruction::Const(self.value));
+    }
+
+    fn write_tree(&self, level: i32, out: &mut String) {
+        write_tree_prefix_begin(level, out);
+        out.push_str(&format!("$ {:?}\n", self.value));
+
+        write_tree_prefix_end(level, out);
     }
 }
 
@@ -106,7 +524,7 @@ impl ASTNode for ConstNode {
 pub struct FuncNode {
     token_type: lexer::TokenTypeEnum,
     func_name: String,
-    func: Rc<dyn Fn(&[f64]) -> exception::Result<f64>>,
+    func: lexer::TokenFunc,
     arg_nodes: Vec<Box<dyn ASTNode>>,
 }
 
@@ -130,56 +548,298 @@ impl ASTNode for FuncNode {
         (self.func)(&args)
     }
 
-    fn print_tree(&self, level: i32) {
-        print_tree_prefix_begin(level);
-        println!("$ {:?}", self.token_type);
-        print_tree_prefix_tab(level);
-        println!(": {}", self.func_name);
+    fn optimize(&self, optimizer: &Optimizer) -> Box<dyn ASTNode> {
+        let arg_nodes: Vec<Box<dyn ASTNode>> = self.arg_nodes.iter().map(|node| node.optimize(optimizer)).collect();
+        let constant = arg_nodes.iter().all(|node| node.constant_value().is_some());
+        let node = Box::new(FuncNode {
+            token_type: self.token_type,
+            func_name: self.func_name.clone(),
+            func: self.func.clone(),
+            arg_nodes,
+        });
+        if constant { optimizer.fold(node) } else { node }
+    }
 
-        print_tree_prefix_tab(level);
-        println!();
+    fn compile(&self, compiler: &mut Compiler) {
         for arg_node in &self.arg_nodes {
-            arg_node.print_tree(level + 1)
+            arg_node.compile(compiler);
         }
+        compiler.emit(Instruction::Call(self.func.clone(), self.arg_nodes.len()));
+    }
 
-        print_tree_prefix_end(level);
+    fn write_tree(&self, level: i32, out: &mut String) {
+        write_tree_prefix_begin(level, out);
+        out.push_str(&format!("$ {:?}\n", self.token_type));
+        write_tree_prefix_tab(level, out);
+        out.push_str(&format!(": {}\n", self.func_name));
+
+        write_tree_prefix_tab(level, out);
+        out.push('\n');
+        for arg_node in &self.arg_nodes {
+            arg_node.write_tree(level + 1, out)
+        }
+
+        write_tree_prefix_end(level, out);
     }
 }
 
-///参数T
-pub struct TNode {
+///用户定义的函数。调用时把实参绑定到形参上，并保存外层调用的值，以支持递归
+pub struct UserFunction {
+    name: String,
+    parameters: Vec<Rc<RefCell<f64>>>,
+    //函数体，定义失败时保持为NaN
+    body: RefCell<Box<dyn ASTNode>>,
+    //当前的函数调用深度与上限，所有用户函数共享
+    call_depth: Rc<Cell<usize>>,
+    max_recursion_depth: Rc<Cell<usize>>,
+}
+
+impl UserFunction {
+    pub fn new(name: &str, parameters: Vec<Rc<RefCell<f64>>>,
+               call_depth: &Rc<Cell<usize>>, max_recursion_depth: &Rc<Cell<usize>>) -> Self {
+        UserFunction {
+            name: String::from(name),
+            parameters,
+            body: RefCell::new(Box::new(ConstNode::new(f64::NAN))),
+            call_depth: call_depth.clone(),
+            max_recursion_depth: max_recursion_depth.clone(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn parameters(&self) -> &[Rc<RefCell<f64>>] {
+        &self.parameters
+    }
+
+    pub fn body(&self) -> Ref<'_, Box<dyn ASTNode>> {
+        self.body.borrow()
+    }
+
+    pub fn set_body(&self, body: Box<dyn ASTNode>) {
+        *self.body.borrow_mut() = body;
+    }
+
+    pub fn max_recursion_depth(&self) -> usize {
+        self.max_recursion_depth.get()
+    }
+
+    pub fn call(&self, args: &[f64]) -> exception::Result<f64> {
+        lexer::Token::judge_arg_num_equal(&self.name, args, self.parameters.len())?;
+        if self.call_depth.get() >= self.max_recursion_depth.get() {
+            return Err(exception::RecursionLimitExceededError::new(&self.name, self.max_recursion_depth.get()));
+        }
+
+        let saved_values: Vec<f64> = self.parameters.iter().zip(args)
+            .map(|(parameter, arg)| parameter.replace(*arg))
+            .collect();
+        self.call_depth.set(self.call_depth.get() + 1);
+        let result = self.body.borrow().calculate();
+        self.call_depth.set(self.call_depth.get() - 1);
+        for (parameter, value) in self.parameters.iter().zip(saved_values) {
+            *parameter.borrow_mut() = value;
+        }
+        result
+    }
+}
+
+///调用用户定义的函数。函数体可能递归调用该函数，用弱引用避免循环引用
+pub struct UserFuncNode {
+    function: Weak<UserFunction>,
+    arg_nodes: Vec<Box<dyn ASTNode>>,
+}
+
+impl UserFuncNode {
+    pub fn new(function: &Rc<UserFunction>, arg_nodes: Vec<Box<dyn ASTNode>>) -> Self {
+        UserFuncNode {
+            function: Rc::downgrade(function),
+            arg_nodes,
+        }
+    }
+}
+
+impl ASTNode for UserFuncNode {
+    fn calculate(&self) -> exception::Result<f64> {
+        let mut args: Vec<f64> = Vec::new();
+        for node in &self.arg_nodes {
+            args.push(node.calculate()?);
+        }
+        match self.function.upgrade() {
+            Some(function) => function.call(&args),
+            None => Ok(f64::NAN),
+        }
+    }
+
+    fn optimize(&self, optimizer: &Optimizer) -> Box<dyn ASTNode> {
+        //函数体不化简。实参都是常数、且函数体中没有读取会变化的循环变量时，整个调用可以提前求值
+        let arg_nodes: Vec<Box<dyn ASTNode>> = self.arg_nodes.iter().map(|node| node.optimize(optimizer)).collect();
+        let constant = arg_nodes.iter().all(|node| node.constant_value().is_some());
+        let node = Box::new(UserFuncNode {
+            function: self.function.clone(),
+            arg_nodes,
+        });
+        if constant && !optimizer.reads_varying(node.as_ref()) { optimizer.fold(node) } else { node }
+    }
+
+    fn compile(&self, compiler: &mut Compiler) {
+        match self.function.upgrade() {
+            Some(function) => {
+                for arg_node in &self.arg_nodes {
+                    arg_node.compile(compiler);
+                }
+                compiler.call_user_function(&function, self.arg_nodes.len());
+            }
+            None => {
+                compiler.emit(Instruction::Const(f64::NAN));
+            }
+        }
+    }
+
+    fn write_tree(&self, level: i32, out: &mut String) {
+        write_tree_prefix_begin(level, out);
+        out.push_str(&format!("$ {:?}\n", lexer::TokenTypeEnum::Func));
+        if let Some(function) = self.function.upgrade() {
+            write_tree_prefix_tab(level, out);
+            out.push_str(&format!(": {}\n", function.name()));
+        }
+
+        write_tree_prefix_tab(level, out);
+        out.push('\n');
+        for arg_node in &self.arg_nodes {
+            arg_node.write_tree(level + 1, out)
+        }
+
+        write_tree_prefix_end(level, out);
+    }
+}
+
+///循环变量
+pub struct LoopVariableNode {
+    variable_name: String,
+    value_reference: Rc<RefCell<f64>>,
+}
+
+impl LoopVariableNode {
+    pub fn new(variable_name: &str, value_reference: &Rc<RefCell<f64>>) -> Self {
+        LoopVariableNode {
+            variable_name: String::from(variable_name),
+            value_reference: value_reference.clone(),
+        }
+    }
+}
+
+impl ASTNode for LoopVariableNode {
+    fn calculate(&self) -> exception::Result<f64> {
+        Ok(*(*self.value_reference).borrow())
+    }
+
+    fn optimize(&self, optimizer: &Optimizer) -> Box<dyn ASTNode> {
+        //不会变化的循环变量（如外层循环的循环变量）视为常数
+        if optimizer.is_varying(&self.value_reference) {
+            return Box::new(LoopVariableNode::new(&self.variable_name, &self.value_reference));
+        }
+        Box::new(ConstNode::new(*(*self.value_reference).borrow()))
+    }
+
+    fn compile(&self, compiler: &mut Compiler) {
+        compiler.load_variable(&self.value_reference);
+    }
+
+    fn write_tree(&self, level: i32, out: &mut String) {
+        write_tree_prefix_begin(level, out);
+        out.push_str(&format!("$ {}\n", self.variable_name));
+
+        write_tree_prefix_end(level, out);
+    }
+}
+
+///函数形参
+pub struct ParameterNode {
+    parameter_name: String,
     value_reference: Rc<RefCell<f64>>,
 }
 
-impl TNode {
-    pub fn new(value_reference: &Rc<RefCell<f64>>) -> Self {
-        TNode {
+impl ParameterNode {
+    pub fn new(parameter_name: &str, value_reference: &Rc<RefCell<f64>>) -> Self {
+        ParameterNode {
+            parameter_name: String::from(parameter_name),
             value_reference: value_reference.clone(),
         }
     }
 }
 
-impl ASTNode for TNode {
+impl ASTNode for ParameterNode {
     fn calculate(&self) -> exception::Result<f64> {
         Ok(*(*self.value_reference).borrow())
     }
 
-    fn print_tree(&self, level: i32) {
-        print_tree_prefix_begin(level);
-        println!("$ {:?}", lexer::TokenTypeEnum::T);
+    fn optimize(&self, _optimizer: &Optimizer) -> Box<dyn ASTNode> {
+        Box::new(ParameterNode::new(&self.parameter_name, &self.value_reference))
+    }
+
+    fn compile(&self, compiler: &mut Compiler) {
+        compiler.load_variable(&self.value_reference);
+    }
+
+    fn write_tree(&self, level: i32, out: &mut String) {
+        write_tree_prefix_begin(level, out);
+        out.push_str("$ Parameter\n");
+        write_tree_prefix_tab(level, out);
+        out.push_str(&format!(": {}\n", self.parameter_name));
+
+        write_tree_prefix_end(level, out);
+    }
+}
+
+///外部参数，由PARAM声明或由宿主程序设置
+pub struct ExternalParameterNode {
+    parameter_name: String,
+    value_reference: Rc<RefCell<f64>>,
+}
+
+impl ExternalParameterNode {
+    pub fn new(parameter_name: &str, value_reference: &Rc<RefCell<f64>>) -> Self {
+        ExternalParameterNode {
+            parameter_name: String::from(parameter_name),
+            value_reference: value_reference.clone(),
+        }
+    }
+}
+
+impl ASTNode for ExternalParameterNode {
+    fn calculate(&self) -> exception::Result<f64> {
+        Ok(*(*self.value_reference).borrow())
+    }
+
+    fn optimize(&self, _optimizer: &Optimizer) -> Box<dyn ASTNode> {
+        //外部参数在执行PARAM语句后就不会再变化，视为常数
+        Box::new(ConstNode::new(*(*self.value_reference).borrow()))
+    }
+
+    fn compile(&self, compiler: &mut Compiler) {
+        compiler.load_variable(&self.value_reference);
+    }
+
+    fn write_tree(&self, level: i32, out: &mut String) {
+        write_tree_prefix_begin(level, out);
+        out.push_str(&format!("$ {:?}\n", lexer::TokenTypeEnum::Param));
+        write_tree_prefix_tab(level, out);
+        out.push_str(&format!(": {}\n", self.parameter_name));
 
-        print_tree_prefix_end(level);
+        write_tree_prefix_end(level, out);
     }
 }
 
 ///变量
 pub struct VariableNode {
     variable_name: String,
-    expression_reference: Rc<RefCell<Box<dyn ASTNode>>>,
+    expression_reference: Rc<RefCell<Rc<dyn ASTNode>>>,
 }
 
 impl VariableNode {
-    pub fn new(variable_name: &str, expression_reference: &Rc<RefCell<Box<dyn ASTNode>>>) -> Self {
+    pub fn new(variable_name: &str, expression_reference: &Rc<RefCell<Rc<dyn ASTNode>>>) -> Self {
         VariableNode {
             variable_name: String::from(variable_name),
             expression_reference: expression_reference.clone(),
@@ -192,21 +852,33 @@ impl ASTNode for VariableNode {
         (*self.expression_reference).borrow().calculate()
     }
 
-    fn print_tree(&self, level: i32) {
-        print_tree_prefix_begin(level);
-        println!("$ {:?}", lexer::TokenTypeEnum::Variable);
-        print_tree_prefix_tab(level);
-        println!(": {}", self.variable_name);
+    fn optimize(&self, optimizer: &Optimizer) -> Box<dyn ASTNode> {
+        //变量的表达式直接内联
+        (*self.expression_reference).borrow().optimize(optimizer)
+    }
+
+    fn compile(&self, compiler: &mut Compiler) {
+        //变量的表达式直接内联
+        (*self.expression_reference).borrow().compile(compiler);
+    }
+
+    fn write_tree(&self, level: i32, out: &mut String) {
+        write_tree_prefix_begin(level, out);
+        out.push_str(&format!("$ {:?}\n", lexer::TokenTypeEnum::Variable));
+        write_tree_prefix_tab(level, out);
+        out.push_str(&format!(": {}\n", self.variable_name));
 
         //打印其所属语法树
-        self.expression_reference.borrow_mut().print_tree(level + 1);
+        self.expression_reference.borrow_mut().write_tree(level + 1, out);
 
-        print_tree_prefix_end(level);
+        write_tree_prefix_end(level, out);
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
     use crate::lexer::{TokenBuilder, TokenTypeEnum};
 
     use super::*;
@@ -214,7 +886,7 @@ mod tests {
     #[test]
     fn test_binary_node() {
         let token1 = TokenBuilder::new().token_type(TokenTypeEnum::Plus)
-            .lexeme("+").func(Rc::new(|args| {
+            .lexeme("+").func(Arc::new(|args| {
             let ans = args[0] + args[1];
             println!("binary_node {} + {} ans = {}", args[0], args[1], ans);
             Ok(ans)
@@ -226,13 +898,15 @@ mod tests {
 
         let ans = binary_node.calculate().unwrap();
         assert_eq!(ans, 17.8);
-        binary_node.print_tree(0);
+        let mut tree = String::new();
+        binary_node.write_tree(0, &mut tree);
+        println!("{}", tree);
     }
 
     #[test]
     fn test_func_node() {
         let token1 = TokenBuilder::new().token_type(TokenTypeEnum::Func)
-            .lexeme("mutiall").func(Rc::new(|args| {
+            .lexeme("mutiall").func(Arc::new(|args| {
             let mut ans = 1.0;
             for arg in args {
                 ans *= arg;
@@ -241,7 +915,7 @@ mod tests {
             Ok(ans)
         })).build();
         let token2 = TokenBuilder::new().token_type(TokenTypeEnum::Plus)
-            .lexeme("+").func(Rc::new(|args| {
+            .lexeme("+").func(Arc::new(|args| {
             let ans = args[0] + args[1];
             println!("binary_node {} + {} ans = {}", args[0], args[1], ans);
             Ok(ans)
@@ -253,10 +927,11 @@ mod tests {
         let const_node4 = ConstNode::new(5.0);
         let binary_node = BinaryNode::new(&token2, Box::new(const_node1), Box::new(const_node2));
 
-        let mut args: Vec<Box<dyn ASTNode>> = Vec::new();
-        args.push(Box::new(binary_node));
-        args.push(Box::new(const_node3));
-        args.push(Box::new(const_node4));
+        let args: Vec<Box<dyn ASTNode>> = vec![
+            Box::new(binary_node),
+            Box::new(const_node3),
+            Box::new(const_node4),
+        ];
         let func_node = FuncNode::new(&token1, args);
 
         let mut ans = func_node.calculate().unwrap();
@@ -265,13 +940,30 @@ mod tests {
         ans = func_node.calculate().unwrap();
         assert_eq!(ans, 125.0);
 
-        func_node.print_tree(0);
+        let mut tree = String::new();
+        func_node.write_tree(0, &mut tree);
+        println!("{}", tree);
+    }
+
+    #[test]
+    fn test_power_node() {
+        let token = TokenBuilder::new().token_type(TokenTypeEnum::Power)
+            .lexeme("**").func(Arc::new(|args| Ok(args[0].powf(args[1])))).build();
+        let counter = Rc::new(Cell::new(0));
+
+        let power_node = PowerNode::new(&token, Box::new(ConstNode::new(-8.0)), Box::new(ConstNode::new(2.0)), &counter);
+        assert_eq!(power_node.calculate().unwrap(), 64.0);
+        assert_eq!(counter.get(), 0);
+
+        let power_node = PowerNode::new(&token, Box::new(ConstNode::new(-8.0)), Box::new(ConstNode::new(0.5)), &counter);
+        assert!(power_node.calculate().unwrap().is_nan());
+        assert_eq!(counter.get(), 1);
     }
 
     #[test]
     fn test_variable_node() {
         let token1 = TokenBuilder::new().token_type(TokenTypeEnum::Plus)
-            .lexeme("+").func(Rc::new(|args| {
+            .lexeme("+").func(Arc::new(|args| {
             let ans = args[0] + args[1];
             println!("binary_node {} + {} ans = {}", args[0], args[1], ans);
             Ok(ans)
@@ -280,7 +972,7 @@ mod tests {
             .lexeme("val").build();
 
         let val = ConstNode::new(8.5);
-        let val_refer: Rc<RefCell<Box<dyn ASTNode>>> = Rc::new(RefCell::new(Box::new(val)));
+        let val_refer: Rc<RefCell<Rc<dyn ASTNode>>> = Rc::new(RefCell::new(Rc::new(val)));
         let val_node = VariableNode::new(token2.lexeme(), &val_refer);
         let const_node = ConstNode::new(5.0);
         let binary_node = BinaryNode::new(&token1, Box::new(val_node), Box::new(const_node));
@@ -288,7 +980,9 @@ mod tests {
         let ans = binary_node.calculate().unwrap();
         assert_eq!(ans, 13.5);
 
-        binary_node.print_tree(0);
+        let mut tree = String::new();
+        binary_node.write_tree(0, &mut tree);
+        println!("{}", tree);
     }
 }
 
@@ -0,0 +1,652 @@
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::rc::Rc;
+
+use crate::exception;
+use crate::lexer;
+use super::ast_tree::{ASTNode, UserFunction, is_truthy};
+
+///后缀字节码指令，由栈式虚拟机执行
+#[derive(Clone)]
+pub enum Instruction {
+    //压入常数
+    Const(f64),
+    //压入变量槽中的当前值（循环变量）
+    Load(usize),
+    //压入当前函数调用的第i个实参
+    Parameter(usize),
+    Add,
+    Sub,
+    Mul,
+    Div,
+    //比较，结果为1或0
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+    //其他二元运算
+    Binary(lexer::TokenFunc),
+    //乘方，会统计负数的小数次方
+    Power(lexer::TokenFunc),
+    //逻辑非
+    Not,
+    //把栈顶转换为1或0
+    Truthy,
+    //调用内置函数：函数与参数个数
+    Call(lexer::TokenFunc, usize),
+    //调用用户函数：函数编号与参数个数
+    CallUser(usize, usize),
+    //无条件跳转
+    Jump(usize),
+    //弹出栈顶，为假时跳转
+    JumpIfFalse(usize),
+    //弹出栈顶，为真时跳转
+    JumpIfTrue(usize),
+    //比较栈顶的两个值，不成立时跳转。由比较与JumpIfFalse合并而成
+    CompareJumpIfFalse(Comparison, usize),
+    //PIECEWISE没有分支匹配，压入NaN
+    Unmatched,
+    //返回栈顶的值
+    Return,
+}
+
+impl fmt::Debug for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::Const(value) => write!(f, "Const({:?})", value),
+            Instruction::Load(slot) => write!(f, "Load({})", slot),
+            Instruction::Parameter(index) => write!(f, "Parameter({})", index),
+            Instruction::Add => write!(f, "Add"),
+            Instruction::Sub => write!(f, "Sub"),
+            Instruction::Mul => write!(f, "Mul"),
+            Instruction::Div => write!(f, "Div"),
+            Instruction::Less => write!(f, "Less"),
+            Instruction::LessEqual => write!(f, "LessEqual"),
+            Instruction::Greater => write!(f, "Greater"),
+            Instruction::GreaterEqual => write!(f, "GreaterEqual"),
+            Instruction::Equal => write!(f, "Equal"),
+            Instruction::NotEqual => write!(f, "NotEqual"),
+            Instruction::Binary(_) => write!(f, "Binary"),
+            Instruction::Power(_) => write!(f, "Power"),
+            Instruction::Not => write!(f, "Not"),
+            Instruction::Truthy => write!(f, "Truthy"),
+            Instruction::Call(_, argc) => write!(f, "Call({})", argc),
+            Instruction::CallUser(function, argc) => write!(f, "CallUser({}, {})", function, argc),
+            Instruction::Jump(target) => write!(f, "Jump({})", target),
+            Instruction::JumpIfFalse(target) => write!(f, "JumpIfFalse({})", target),
+            Instruction::JumpIfTrue(target) => write!(f, "JumpIfTrue({})", target),
+            Instruction::CompareJumpIfFalse(comparison, target) => write!(f, "CompareJumpIfFalse({:?}, {})", comparison, target),
+            Instruction::Unmatched => write!(f, "Unmatched"),
+            Instruction::Return => write!(f, "Return"),
+        }
+    }
+}
+
+impl Instruction {
+    ///比较指令对应的比较运算
+    fn comparison(&self) -> Option<Comparison> {
+        match self {
+            Instruction::Less => Some(Comparison::Less),
+            Instruction::LessEqual => Some(Comparison::LessEqual),
+            Instruction::Greater => Some(Comparison::Greater),
+            Instruction::GreaterEqual => Some(Comparison::GreaterEqual),
+            Instruction::Equal => Some(Comparison::Equal),
+            Instruction::NotEqual => Some(Comparison::NotEqual),
+            _ => None,
+        }
+    }
+}
+
+///比较运算，与NaN比较时都不成立（NotEqual除外）
+#[derive(Debug, Clone, Copy)]
+pub enum Comparison {
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+}
+
+impl Comparison {
+    #[inline(always)]
+    fn holds(self, left: f64, right: f64) -> bool {
+        match self {
+            Comparison::Less => left < right,
+            Comparison::LessEqual => left <= right,
+            Comparison::Greater => left > right,
+            Comparison::GreaterEqual => left >= right,
+            Comparison::Equal => left == right,
+            Comparison::NotEqual => left != right,
+        }
+    }
+}
+
+///编译后的用户函数
+struct CompiledFunction {
+    name: String,
+    arity: usize,
+    //函数体第一条指令的位置
+    entry: usize,
+    max_recursion_depth: usize,
+}
+
+///字节码。表达式本身的代码从0开始，用到的用户函数的函数体依次排在后面
+///变量的值由求值时传入，因此可以在多个线程中共用
+pub struct Code {
+    instructions: Vec<Instruction>,
+    functions: Vec<CompiledFunction>,
+}
+
+///编译后的表达式
+pub struct CompiledExpression {
+    code: Code,
+    //变量槽，求值前读取其当前值
+    slots: Vec<Rc<RefCell<f64>>>,
+}
+
+impl CompiledExpression {
+    pub fn code(&self) -> &Code {
+        &self.code
+    }
+
+    ///求值时读取的变量，包括调用的用户函数中读取的
+    pub fn slots(&self) -> &[Rc<RefCell<f64>>] {
+        &self.slots
+    }
+
+    ///某个变量在变量槽中的位置，没有读取该变量时为None
+    pub fn slot_index(&self, value_reference: &Rc<RefCell<f64>>) -> Option<usize> {
+        self.slots.iter().position(|slot| Rc::ptr_eq(slot, value_reference))
+    }
+
+    ///读取所有变量槽的当前值
+    pub fn read_slots(&self, slot_values: &mut Vec<f64>) {
+        slot_values.clear();
+        slot_values.extend(self.slots.iter().map(|slot| *slot.borrow()));
+    }
+}
+
+///把语法树编译为字节码
+pub struct Compiler {
+    code: Vec<Instruction>,
+    slots: Vec<Rc<RefCell<f64>>>,
+    functions: Vec<CompiledFunction>,
+    //与functions一一对应的用户函数
+    function_references: Vec<Rc<UserFunction>>,
+    //还没有编译函数体的函数编号
+    pending_functions: Vec<usize>,
+    //正在编译的函数体的形参
+    parameters: Vec<Rc<RefCell<f64>>>,
+    //最近一次patch_jump的跳转目标
+    last_jump_target: Option<usize>,
+}
+
+impl Compiler {
+    pub fn compile(root: &dyn ASTNode) -> CompiledExpression {
+        let mut compiler = Compiler {
+            code: Vec::new(),
+            slots: Vec::new(),
+            functions: Vec::new(),
+            function_references: Vec::new(),
+            pending_functions: Vec::new(),
+            parameters: Vec::new(),
+            last_jump_target: None,
+        };
+        root.compile(&mut compiler);
+        compiler.emit(Instruction::Return);
+
+        //函数体中可能调用其他函数，编译时会继续加入待编译的函数
+        while let Some(function_index) = compiler.pending_functions.pop() {
+            let function = compiler.function_references[function_index].clone();
+            compiler.functions[function_index].entry = compiler.code.len();
+            compiler.parameters = function.parameters().to_vec();
+            function.body().compile(&mut compiler);
+            compiler.emit(Instruction::Return);
+        }
+
+        CompiledExpression {
+            code: Code {
+                instructions: compiler.code,
+                functions: compiler.functions,
+            },
+            slots: compiler.slots,
+        }
+    }
+
+    ///追加一条指令，返回其位置。
+    ///比较后紧跟JumpIfFalse时合并为一条指令，省去压入与弹出比较结果；有跳转以JumpIfFalse为目标时不能合并
+    pub fn emit(&mut self, instruction: Instruction) -> usize {
+        if let Instruction::JumpIfFalse(target) = instruction {
+            let comparison = self.code.last().and_then(Instruction::comparison);
+            if let (Some(comparison), false) = (comparison, self.last_jump_target == Some(self.code.len())) {
+                let at = self.code.len() - 1;
+                self.code[at] = Instruction::CompareJumpIfFalse(comparison, target);
+                return at;
+            }
+        }
+        self.code.push(instruction);
+        self.code.len() - 1
+    }
+
+    ///让位于at的跳转指令跳到下一条将要追加的指令
+    pub fn patch_jump(&mut self, at: usize) {
+        let next = self.code.len();
+        match &mut self.code[at] {
+            Instruction::Jump(target) | Instruction::JumpIfFalse(target) | Instruction::JumpIfTrue(target)
+            | Instruction::CompareJumpIfFalse(_, target) => *target = next,
+            _ => panic!("Compiler: instruction at {} is not a jump", at),
+        }
+        self.last_jump_target = Some(next);
+    }
+
+    ///读取变量：正在编译的函数的形参，或是变量槽
+    pub fn load_variable(&mut self, value_reference: &Rc<RefCell<f64>>) {
+        if let Some(index) = self.parameters.iter().position(|p| Rc::ptr_eq(p, value_reference)) {
+            self.emit(Instruction::Parameter(index));
+            return;
+        }
+        let slot = match self.slots.iter().position(|s| Rc::ptr_eq(s, value_reference)) {
+            Some(slot) => slot,
+            None => {
+                self.slots.push(value_reference.clone());
+                self.slots.len() - 1
+            }
+        };
+        self.emit(Instruction::Load(slot));
+    }
+
+    ///调用用户函数，实参已经在栈上。每个函数只编译一次
+    pub fn call_user_function(&mut self, function: &Rc<UserFunction>, argc: usize) {
+        let function_index = match self.function_references.iter().position(|f| Rc::ptr_eq(f, function)) {
+            Some(function_index) => function_index,
+            None => {
+                self.function_references.push(function.clone());
+                self.functions.push(CompiledFunction {
+                    name: String::from(function.name()),
+                    arity: function.parameters().len(),
+                    entry: 0,
+                    max_recursion_depth: function.max_recursion_depth(),
+                });
+                self.pending_functions.push(self.functions.len() - 1);
+                self.functions.len() - 1
+            }
+        };
+        self.emit(Instruction::CallUser(function_index, argc));
+    }
+}
+
+//用户函数的调用帧
+struct Frame {
+    return_pc: usize,
+    //第一个实参在栈中的位置
+    base: usize,
+}
+
+///栈式虚拟机。栈等缓冲区在多次求值之间复用，求值时不再分配内存
+#[derive(Default)]
+pub struct Vm {
+    stack: Vec<f64>,
+    frames: Vec<Frame>,
+    //负数的小数次方、PIECEWISE没有分支匹配出现的次数
+    negative_base_count: usize,
+    unmatched_piecewise_count: usize,
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    ///slot_values为各变量槽的值
+    pub fn evaluate(&mut self, code: &Code, slot_values: &[f64]) -> exception::Result<f64> {
+        let instructions = &code.instructions;
+        //只读取一个变量或常数的表达式（如DRAW(T, T)）不进入解释循环
+        match instructions.as_slice() {
+            [Instruction::Load(slot), Instruction::Return] => return Ok(slot_values[*slot]),
+            [Instruction::Const(value), Instruction::Return] => return Ok(*value),
+            _ => {}
+        }
+        self.stack.clear();
+        self.frames.clear();
+
+        let mut pc = 0;
+        let mut base = 0;
+        loop {
+            let instruction = &instructions[pc];
+            pc += 1;
+            match instruction {
+                Instruction::Const(value) => self.stack.push(*value),
+                Instruction::Load(slot) => self.stack.push(slot_values[*slot]),
+                Instruction::Parameter(index) => self.stack.push(self.stack[base + index]),
+                Instruction::Add => self.binary(|a, b| a + b),
+                Instruction::Sub => self.binary(|a, b| a - b),
+                Instruction::Mul => self.binary(|a, b| a * b),
+                Instruction::Div => self.binary(|a, b| a / b),
+                Instruction::Less => self.binary(|a, b| f64::from(a < b)),
+                Instruction::LessEqual => self.binary(|a, b| f64::from(a <= b)),
+                Instruction::Greater => self.binary(|a, b| f64::from(a > b)),
+                Instruction::GreaterEqual => self.binary(|a, b| f64::from(a >= b)),
+                Instruction::Equal => self.binary(|a, b| f64::from(a == b)),
+                Instruction::NotEqual => self.binary(|a, b| f64::from(a != b)),
+                Instruction::Binary(func) => self.call(func, 2)?,
+                Instruction::Power(func) => {
+                    let len = self.stack.len();
+                    let (left, right) = (self.stack[len - 2], self.stack[len - 1]);
+                    if left < 0.0 && right.is_finite() && right.fract() != 0.0 {
+                        self.negative_base_count += 1;
+                    }
+                    self.call(func, 2)?;
+                }
+                Instruction::Not => {
+                    let value = self.stack.last_mut().unwrap();
+                    *value = f64::from(!is_truthy(*value));
+                }
+                Instruction::Truthy => {
+                    let value = self.stack.last_mut().unwrap();
+                    *value = f64::from(is_truthy(*value));
+                }
+                Instruction::Call(func, argc) => self.call(func, *argc)?,
+                Instruction::CallUser(function_index, argc) => {
+                    let function = &code.functions[*function_index];
+                    if *argc != function.arity {
+                        return Err(exception::ArgumentNumberNotMatchError::new(&function.name, *argc, function.arity, false));
+                    }
+                    if self.frames.len() >= function.max_recursion_depth {
+                        return Err(exception::RecursionLimitExceededError::new(&function.name, function.max_recursion_depth));
+                    }
+                    self.frames.push(Frame { return_pc: pc, base });
+                    base = self.stack.len() - argc;
+                    pc = function.entry;
+                }
+                Instruction::Jump(target) => pc = *target,
+                Instruction::JumpIfFalse(target) => {
+                    if !is_truthy(self.pop()) {
+                        pc = *target;
+                    }
+                }
+                Instruction::JumpIfTrue(target) => {
+                    if is_truthy(self.pop()) {
+                        pc = *target;
+                    }
+                }
+                Instruction::CompareJumpIfFalse(comparison, target) => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    if !comparison.holds(left, right) {
+                        pc = *target;
+                    }
+                }
+                Instruction::Unmatched => {
+                    self.unmatched_piecewise_count += 1;
+                    self.stack.push(f64::NAN);
+                }
+                Instruction::Return => {
+                    let result = self.pop();
+                    match self.frames.pop() {
+                        None => return Ok(result),
+                        Some(frame) => {
+                            //弹出实参
+                            self.stack.truncate(base);
+                            self.stack.push(result);
+                            pc = frame.return_pc;
+                            base = frame.base;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    ///取出并清零负数的小数次方出现的次数
+    pub fn take_negative_base_count(&mut self) -> usize {
+        std::mem::take(&mut self.negative_base_count)
+    }
+
+    ///取出并清零PIECEWISE没有分支匹配的次数
+    pub fn take_unmatched_piecewise_count(&mut self) -> usize {
+        std::mem::take(&mut self.unmatched_piecewise_count)
+    }
+
+    fn pop(&mut self) -> f64 {
+        self.stack.pop().unwrap()
+    }
+
+    ///结果直接写回左操作数的位置
+    #[inline(always)]
+    fn binary(&mut self, op: fn(f64, f64) -> f64) {
+        let right = self.pop();
+        let left = self.stack.last_mut().unwrap();
+        *left = op(*left, right);
+    }
+
+    ///以栈顶的argc个值为参数调用函数，不需要额外的参数数组
+    fn call(&mut self, func: &lexer::TokenFunc, argc: usize) -> exception::Result<()> {
+        let args_begin = self.stack.len() - argc;
+        let result = func(&self.stack[args_begin..])?;
+        self.stack.truncate(args_begin);
+        self.stack.push(result);
+        Ok(())
+    }
+}
+
+///编译后的表达式与它的虚拟机。求值后把警告的计数累加到与语法树共用的计数器中
+pub struct CompiledEvaluator {
+    expression: CompiledExpression,
+    vm: Vm,
+    slot_values: Vec<f64>,
+    negative_base_counter: Rc<Cell<usize>>,
+    unmatched_piecewise_counter: Rc<Cell<usize>>,
+}
+
+impl CompiledEvaluator {
+    pub fn new(expression: CompiledExpression, negative_base_counter: &Rc<Cell<usize>>,
+               unmatched_piecewise_counter: &Rc<Cell<usize>>) -> Self {
+        let slot_values = vec![0.0; expression.slots.len()];
+        CompiledEvaluator {
+            expression,
+            vm: Vm::new(),
+            slot_values,
+            negative_base_counter: negative_base_counter.clone(),
+            unmatched_piecewise_counter: unmatched_piecewise_counter.clone(),
+        }
+    }
+
+    pub fn expression(&self) -> &CompiledExpression {
+        &self.expression
+    }
+
+    pub fn evaluate(&mut self) -> exception::Result<f64> {
+        //只读取一个变量的表达式（如DRAW(T, T)）直接返回变量的值，不经过虚拟机
+        if let [Instruction::Load(slot), Instruction::Return] = self.expression.code.instructions.as_slice() {
+            return Ok(*self.expression.slots[*slot].borrow());
+        }
+        //变量槽的数量不变，原地更新它们的值
+        for (value, slot) in self.slot_values.iter_mut().zip(&self.expression.slots) {
+            *value = *slot.borrow();
+        }
+        let result = self.vm.evaluate(&self.expression.code, &self.slot_values);
+        let counts = (self.vm.take_negative_base_count(), self.vm.take_unmatched_piecewise_count());
+        if counts != (0, 0) {
+            self.add_counts(counts.0, counts.1);
+        }
+        result
+    }
+
+    ///累加警告的计数，包括在其他虚拟机（如工作线程）中统计的
+    pub fn add_counts(&self, negative_base_count: usize, unmatched_piecewise_count: usize) {
+        self.negative_base_counter.set(self.negative_base_counter.get() + negative_base_count);
+        self.unmatched_piecewise_counter.set(self.unmatched_piecewise_counter.get() + unmatched_piecewise_count);
+    }
+}
+
+///对同一个表达式反复求值：直接遍历语法树，或先编译为字节码再由虚拟机执行
+pub enum Evaluator<'a> {
+    Tree(&'a dyn ASTNode),
+    Compiled(CompiledEvaluator),
+}
+
+impl Evaluator<'_> {
+    pub fn evaluate(&mut self) -> exception::Result<f64> {
+        match self {
+            Evaluator::Tree(expression) => expression.calculate(),
+            Evaluator::Compiled(evaluator) => evaluator.evaluate(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::exception::{ErrorKind, ExceptionTrait};
+    use crate::lexer::{Token, TokenBuilder, TokenTypeEnum};
+    use super::super::ast_tree::*;
+    use super::*;
+
+    fn operator(token_type: TokenTypeEnum, func: fn(f64, f64) -> f64) -> Token {
+        TokenBuilder::new().token_type(token_type).lexeme(token_type.describe()).func(Arc::new(move |args| Ok(func(args[0], args[1])))).build()
+    }
+
+    fn evaluate(vm: &mut Vm, expression: &CompiledExpression) -> exception::Result<f64> {
+        let mut slot_values = Vec::new();
+        expression.read_slots(&mut slot_values);
+        vm.evaluate(expression.code(), &slot_values)
+    }
+
+    #[test]
+    fn test_compile_arithmetic() {
+        //(T + 2) * T ** 0.5
+        let t = Rc::new(RefCell::new(0.0));
+        let sum = BinaryNode::new(
+            &operator(TokenTypeEnum::Plus, |a, b| a + b),
+            Box::new(LoopVariableNode::new("T", &t)), Box::new(ConstNode::new(2.0)),
+        );
+        let power = PowerNode::new(
+            &operator(TokenTypeEnum::Power, f64::powf),
+            Box::new(LoopVariableNode::new("T", &t)), Box::new(ConstNode::new(0.5)), &Rc::new(Cell::new(0)),
+        );
+        let root = BinaryNode::new(&operator(TokenTypeEnum::Mul, |a, b| a * b), Box::new(sum), Box::new(power));
+
+        let compiled = Compiler::compile(&root);
+        assert_eq!(format!("{:?}", compiled.code.instructions),
+                   "[Load(0), Const(2.0), Add, Load(0), Const(0.5), Power, Mul, Return]");
+        let mut vm = Vm::new();
+        for value in [0.0, 1.5, 4.0] {
+            *t.borrow_mut() = value;
+            assert_eq!(evaluate(&mut vm, &compiled).unwrap(), root.calculate().unwrap());
+        }
+        *t.borrow_mut() = -1.0;
+        assert!(evaluate(&mut vm, &compiled).unwrap().is_nan());
+        assert_eq!(vm.take_negative_base_count(), 1);
+    }
+
+    #[test]
+    fn test_compile_short_circuit() {
+        //右侧被计算时会出错
+        let failing = || -> Box<dyn ASTNode> {
+            let token = TokenBuilder::new().token_type(TokenTypeEnum::Func).lexeme("FAIL")
+                .func(Arc::new(|args| Token::judge_arg_num_equal("FAIL", args, 1))).build();
+            Box::new(FuncNode::new(&token, Vec::new()))
+        };
+        let and_token = TokenBuilder::new().token_type(TokenTypeEnum::And).lexeme("AND").build();
+        let or_token = TokenBuilder::new().token_type(TokenTypeEnum::Or).lexeme("OR").build();
+        let mut vm = Vm::new();
+
+        let and_node = LogicNode::new(&and_token, Box::new(ConstNode::new(0.0)), failing());
+        assert_eq!(evaluate(&mut vm, &Compiler::compile(&and_node)).unwrap(), 0.0);
+        let or_node = LogicNode::new(&or_token, Box::new(ConstNode::new(2.0)), failing());
+        assert_eq!(evaluate(&mut vm, &Compiler::compile(&or_node)).unwrap(), 1.0);
+        let or_node = LogicNode::new(&or_token, Box::new(ConstNode::new(0.0)), failing());
+        assert!(evaluate(&mut vm, &Compiler::compile(&or_node)).is_err());
+
+        let piecewise_node = PiecewiseNode::new(
+            vec![(Box::new(ConstNode::new(0.0)), failing()), (Box::new(ConstNode::new(1.0)), Box::new(ConstNode::new(7.0)))],
+            None, &Rc::new(Cell::new(0)),
+        );
+        assert_eq!(evaluate(&mut vm, &Compiler::compile(&piecewise_node)).unwrap(), 7.0);
+        let piecewise_node = PiecewiseNode::new(
+            vec![(Box::new(ConstNode::new(0.0)), failing())], None, &Rc::new(Cell::new(0)),
+        );
+        assert!(evaluate(&mut vm, &Compiler::compile(&piecewise_node)).unwrap().is_nan());
+        assert_eq!(vm.take_unmatched_piecewise_count(), 1);
+    }
+
+    #[test]
+    fn test_compile_compare_jump() {
+        let t = Rc::new(RefCell::new(0.0));
+        let less = || -> Box<dyn ASTNode> {
+            Box::new(BinaryNode::new(
+                &operator(TokenTypeEnum::Less, |a, b| f64::from(a < b)),
+                Box::new(LoopVariableNode::new("T", &t)), Box::new(ConstNode::new(1.0)),
+            ))
+        };
+        let mut vm = Vm::new();
+
+        //IF(T < 1, 2, 3)：比较与条件跳转合并
+        let if_node = IfNode::new(less(), Box::new(ConstNode::new(2.0)), Box::new(ConstNode::new(3.0)));
+        let compiled = Compiler::compile(&if_node);
+        assert_eq!(format!("{:?}", compiled.code.instructions),
+                   "[Load(0), Const(1.0), CompareJumpIfFalse(Less, 5), Const(2.0), Jump(6), Const(3.0), Return]");
+
+        //IF(IF(T > 2, 0, T < 1), 2, 3)：内层IF的跳转以外层的条件跳转为目标，不能合并
+        let inner = IfNode::new(
+            Box::new(BinaryNode::new(
+                &operator(TokenTypeEnum::Greater, |a, b| f64::from(a > b)),
+                Box::new(LoopVariableNode::new("T", &t)), Box::new(ConstNode::new(2.0)),
+            )),
+            Box::new(ConstNode::new(0.0)), less(),
+        );
+        let outer = IfNode::new(Box::new(inner), Box::new(ConstNode::new(2.0)), Box::new(ConstNode::new(3.0)));
+        let compiled = Compiler::compile(&outer);
+        assert!(format!("{:?}", compiled.code.instructions).contains("Less, JumpIfFalse"));
+        for value in [0.0, 1.5, 3.0, f64::NAN] {
+            *t.borrow_mut() = value;
+            assert_eq!(evaluate(&mut vm, &compiled).unwrap(), outer.calculate().unwrap());
+            assert_eq!(evaluate(&mut vm, &Compiler::compile(&if_node)).unwrap(), if_node.calculate().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_compile_user_function() {
+        //FN FACT(N) => IF(N <= 1, 1, N * FACT(N - 1))
+        let max_recursion_depth = Rc::new(Cell::new(16));
+        let n = Rc::new(RefCell::new(0.0));
+        let fact = Rc::new(UserFunction::new("FACT", vec![n.clone()], &Rc::new(Cell::new(0)), &max_recursion_depth));
+        let recursive_call = UserFuncNode::new(&fact, vec![Box::new(BinaryNode::new(
+            &operator(TokenTypeEnum::Minus, |a, b| a - b),
+            Box::new(ParameterNode::new("N", &n)), Box::new(ConstNode::new(1.0)),
+        ))]);
+        fact.set_body(Box::new(IfNode::new(
+            Box::new(BinaryNode::new(
+                &operator(TokenTypeEnum::LessEqual, |a, b| f64::from(a <= b)),
+                Box::new(ParameterNode::new("N", &n)), Box::new(ConstNode::new(1.0)),
+            )),
+            Box::new(ConstNode::new(1.0)),
+            Box::new(BinaryNode::new(
+                &operator(TokenTypeEnum::Mul, |a, b| a * b),
+                Box::new(ParameterNode::new("N", &n)), Box::new(recursive_call),
+            )),
+        )));
+
+        let mut vm = Vm::new();
+        let call = UserFuncNode::new(&fact, vec![Box::new(ConstNode::new(5.0))]);
+        let compiled = Compiler::compile(&call);
+        assert_eq!(evaluate(&mut vm, &compiled).unwrap(), 120.0);
+        assert_eq!(call.calculate().unwrap(), 120.0);
+
+        //递归深度与语法树求值的上限一致
+        let call = UserFuncNode::new(&fact, vec![Box::new(ConstNode::new(20.0))]);
+        let error = evaluate(&mut vm, &Compiler::compile(&call)).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::RecursionLimitExceededError);
+        assert_eq!(error.message(), call.calculate().unwrap_err().message());
+        max_recursion_depth.set(32);
+        assert_eq!(evaluate(&mut vm, &Compiler::compile(&call)).unwrap(), call.calculate().unwrap());
+
+        //参数个数不符
+        let call = UserFuncNode::new(&fact, Vec::new());
+        assert!(evaluate(&mut vm, &Compiler::compile(&call)).is_err());
+    }
+}
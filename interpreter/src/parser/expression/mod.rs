@@ -1,36 +1,166 @@
-use std::cell::{Ref, RefCell, RefMut};
+use std::cell::{Cell, Ref, RefCell, RefMut};
 use std::collections::HashMap;
 use std::rc::Rc;
-use crate::{exception, parser, lexer};
+use crate::{exception, parser, lexer, options};
 
-pub use ast_tree::ASTNode;
+pub use ast_tree::{ASTNode, UserFunction, is_truthy};
+pub use bytecode::{Code, CompiledEvaluator, Compiler, Evaluator, Vm};
+pub use optimizer::Optimizer;
 
 mod ast_tree;
+mod bytecode;
+mod optimizer;
+
+///表达式变量符号
+pub struct VariableSymbol {
+    //变量对应的语法树，执行DEF、LET时放入
+    expression: Rc<RefCell<Rc<dyn ASTNode>>>,
+    //定义该变量的token
+    define_token: lexer::Token,
+    //是否被引用过
+    used: Cell<bool>,
+}
+
+impl VariableSymbol {
+    pub fn new(define_token: &lexer::Token, expression: Rc<dyn ASTNode>) -> Self {
+        VariableSymbol {
+            expression: Rc::new(RefCell::new(expression)),
+            define_token: define_token.clone(),
+            used: Cell::new(false),
+        }
+    }
+
+    pub fn expression(&self) -> &Rc<RefCell<Rc<dyn ASTNode>>> {
+        &self.expression
+    }
+    pub fn define_token(&self) -> &lexer::Token {
+        &self.define_token
+    }
+    pub fn is_used(&self) -> bool {
+        self.used.get()
+    }
+}
+
+///用户定义的函数符号
+pub struct FunctionSymbol {
+    function: Rc<ast_tree::UserFunction>,
+    //定义该函数的token
+    define_token: lexer::Token,
+}
+
+impl FunctionSymbol {
+    pub fn define_token(&self) -> &lexer::Token {
+        &self.define_token
+    }
+}
 
 pub struct ExpressionParser {
     parser_kernel: Rc<RefCell<parser::ParserKernel>>,
-    //表达式变量符号表，符号名->表达式
-    variable_symbol_table: HashMap<String, Rc<RefCell<Box<dyn ast_tree::ASTNode>>>>,
-    argument_t: Rc<RefCell<f64>>, //参数T
+    //表达式变量符号表，符号名->变量符号
+    variable_symbol_table: HashMap<String, VariableSymbol>,
+    //循环变量的值，循环变量名->值。同名的循环变量共享同一个值
+    loop_variable_table: HashMap<String, Rc<RefCell<f64>>>,
+    //正在分析的循环的循环变量名，只有其中的循环变量（以及默认循环变量T）在表达式中可见
+    loop_variable_scope: Vec<String>,
+    //负数的小数次方出现的次数
+    negative_base_counter: Rc<Cell<usize>>,
+    //PIECEWISE没有分支匹配的次数
+    unmatched_piecewise_counter: Rc<Cell<usize>>,
+    //用户定义的函数，按定义顺序排列。被覆盖的旧定义也要保留，因为之前的调用仍在使用
+    function_symbols: Vec<FunctionSymbol>,
+    //正在分析的函数体的形参，形参名->形参的值
+    parameter_scope: HashMap<String, Rc<RefCell<f64>>>,
+    //外部参数的名字与值，按声明顺序排列。重新声明时旧的值也要保留，因为之前的引用仍在使用
+    external_parameters: Vec<(String, Rc<RefCell<f64>>)>,
+    //当前的函数调用深度与上限
+    call_depth: Rc<Cell<usize>>,
+    max_recursion_depth: Rc<Cell<usize>>,
+    tracer: Rc<RefCell<options::Tracer>>,
 }
 
 impl ExpressionParser {
-    pub fn new(parser_kernel: &Rc<RefCell<parser::ParserKernel>>) -> Self {
+    pub fn new(parser_kernel: &Rc<RefCell<parser::ParserKernel>>, tracer: &Rc<RefCell<options::Tracer>>) -> Self {
         ExpressionParser {
             parser_kernel: parser_kernel.clone(),
+            tracer: tracer.clone(),
             variable_symbol_table: HashMap::new(),
-            argument_t: Rc::new(RefCell::new(0.0)),
+            loop_variable_table: HashMap::from([
+                (String::from(parser::DEFAULT_LOOP_VARIABLE), Rc::new(RefCell::new(0.0))),
+            ]),
+            loop_variable_scope: Vec::new(),
+            negative_base_counter: Rc::new(Cell::new(0)),
+            unmatched_piecewise_counter: Rc::new(Cell::new(0)),
+            function_symbols: Vec::new(),
+            parameter_scope: HashMap::new(),
+            external_parameters: Vec::new(),
+            call_depth: Rc::new(Cell::new(0)),
+            max_recursion_depth: Rc::new(Cell::new(parser::DEFAULT_MAX_RECURSION_DEPTH)),
         }
     }
 
-    //TODO 控制语法树的打印开关
-    ///分析表达式，返回语法树
+    ///分析表达式，返回语法树。开启dump_ast选项时会输出语法树
     pub fn parse_expression_entrance(&mut self) -> exception::Result<Box<dyn ASTNode>> {
-        let ans_tree = self.parse_expression()?;
-        ans_tree.print_tree(0);
+        let ans_tree = self.parse_condition()?;
+        let mut tracer = self.tracer.borrow_mut();
+        if tracer.is_dump_ast_enabled() {
+            let mut tree = String::new();
+            ans_tree.write_tree(0, &mut tree);
+            tracer.write_text(&tree);
+        }
         Ok(ans_tree)
     }
 
+    ///逻辑或，左结合
+    fn parse_condition(&mut self) -> exception::Result<Box<dyn ASTNode>> {
+        let mut left_node_ref = self.parse_and_condition()?;
+        while self.get_mut_parser_kernel().get_curr_token_type() == lexer::TokenTypeEnum::Or {
+            let or_token = self.get_mut_parser_kernel().get_curr_token().clone();
+            self.get_mut_parser_kernel().match_and_eat_token(lexer::TokenTypeEnum::Or)?;
+            let right_node_ref = self.parse_and_condition()?;
+            left_node_ref = Box::new(ast_tree::LogicNode::new(&or_token, left_node_ref, right_node_ref));
+        }
+        Ok(left_node_ref)
+    }
+
+    ///逻辑与，左结合
+    fn parse_and_condition(&mut self) -> exception::Result<Box<dyn ASTNode>> {
+        let mut left_node_ref = self.parse_not_condition()?;
+        while self.get_mut_parser_kernel().get_curr_token_type() == lexer::TokenTypeEnum::And {
+            let and_token = self.get_mut_parser_kernel().get_curr_token().clone();
+            self.get_mut_parser_kernel().match_and_eat_token(lexer::TokenTypeEnum::And)?;
+            let right_node_ref = self.parse_not_condition()?;
+            left_node_ref = Box::new(ast_tree::LogicNode::new(&and_token, left_node_ref, right_node_ref));
+        }
+        Ok(left_node_ref)
+    }
+
+    ///逻辑非
+    fn parse_not_condition(&mut self) -> exception::Result<Box<dyn ASTNode>> {
+        if self.get_mut_parser_kernel().get_curr_token_type() == lexer::TokenTypeEnum::Not {
+            self.get_mut_parser_kernel().match_and_eat_token(lexer::TokenTypeEnum::Not)?;
+            let child_node_ref = self.parse_not_condition()?;
+            return Ok(Box::new(ast_tree::NotNode::new(child_node_ref)));
+        }
+        self.parse_comparison()
+    }
+
+    ///比较，不可连续比较
+    fn parse_comparison(&mut self) -> exception::Result<Box<dyn ASTNode>> {
+        let left_node_ref = self.parse_expression()?;
+        let token_type = self.get_mut_parser_kernel().get_curr_token_type();
+        match token_type {
+            lexer::TokenTypeEnum::Less | lexer::TokenTypeEnum::LessEqual
+            | lexer::TokenTypeEnum::Greater | lexer::TokenTypeEnum::GreaterEqual
+            | lexer::TokenTypeEnum::Equal | lexer::TokenTypeEnum::NotEqual => {
+                let comparison_token = self.get_mut_parser_kernel().get_curr_token().clone();
+                self.get_mut_parser_kernel().match_and_eat_token(token_type)?;
+                let right_node_ref = self.parse_expression()?;
+                Ok(Box::new(ast_tree::BinaryNode::new(&comparison_token, left_node_ref, right_node_ref)))
+            }
+            _ => Ok(left_node_ref),
+        }
+    }
+
     ///加减，左结合
     fn parse_expression(&mut self) -> exception::Result<Box<dyn ASTNode>> {
         let mut left_node_ref = self.parse_term()?;
@@ -93,7 +223,7 @@ impl ExpressionParser {
         }
 
         //没有正负号，直接视为Atom
-        return self.parse_component();
+        self.parse_component()
     }
 
     ///乘方，右结合
@@ -104,7 +234,9 @@ impl ExpressionParser {
             let power_token = self.get_mut_parser_kernel().get_curr_token().clone();
             self.get_mut_parser_kernel().match_and_eat_token(token_type)?;
             let right_node_ref = self.parse_component()?;
-            let ans_node = ast_tree::BinaryNode::new(&power_token, left_node_ref, right_node_ref);
+            let ans_node = ast_tree::PowerNode::new(
+                &power_token, left_node_ref, right_node_ref, &self.negative_base_counter,
+            );
             return Ok(Box::new(ans_node));
         }
 
@@ -112,10 +244,10 @@ impl ExpressionParser {
         Ok(left_node_ref)
     }
 
-    ///常量、参数、括号（子表达式）、函数
+    ///常量、参数、括号（子表达式）、函数、条件表达式、分段函数
     fn parse_atom(&mut self) -> exception::Result<Box<dyn ASTNode>> {
         let token_type = self.get_mut_parser_kernel().get_curr_token_type();
-        return match token_type {
+        match token_type {
             //常量
             lexer::TokenTypeEnum::ConstId => {
                 let ans_node = ast_tree::ConstNode::new(
@@ -124,23 +256,40 @@ impl ExpressionParser {
                 self.get_mut_parser_kernel().match_and_eat_token(token_type)?;
                 Ok(Box::new(ans_node))
             }
-            //参数T
-            lexer::TokenTypeEnum::T => {
-                let ans_node = ast_tree::TNode::new(&self.argument_t);
-                self.get_mut_parser_kernel().match_and_eat_token(token_type)?;
-                Ok(Box::new(ans_node))
-            }
             //变量
             lexer::TokenTypeEnum::Variable => {
-                //获取对应的语法树
-                let var_name = self.get_parser_kernel().get_curr_token().lexeme().clone();
-                let expression_reference = self.variable_symbol_table().get(&var_name);
+                let var_token = self.get_parser_kernel().get_curr_token().clone();
+                //函数体中优先视为形参
+                if let Some(value_reference) = self.parameter_scope.get(var_token.lexeme()) {
+                    let ans_node = ast_tree::ParameterNode::new(var_token.lexeme(), value_reference);
+                    self.get_mut_parser_kernel().match_and_eat_token(token_type)?;
+                    return Ok(Box::new(ans_node));
+                }
+                //其次是作用域中的循环变量
+                if self.loop_variable_scope.contains(var_token.lexeme()) {
+                    return self.parse_loop_variable(&var_token);
+                }
 
-                if let None = expression_reference {
+                //获取对应的语法树
+                let variable_symbol = self.variable_symbol_table().get(var_token.lexeme());
+
+                if variable_symbol.is_none() {
+                    //然后是外部参数
+                    if let Some(value_reference) = self.get_external_parameter(var_token.lexeme()) {
+                        let ans_node = ast_tree::ExternalParameterNode::new(var_token.lexeme(), value_reference);
+                        self.get_mut_parser_kernel().match_and_eat_token(token_type)?;
+                        return Ok(Box::new(ans_node));
+                    }
+                    //最后是默认循环变量
+                    if var_token.lexeme() == parser::DEFAULT_LOOP_VARIABLE {
+                        return self.parse_loop_variable(&var_token);
+                    }
                     //变量未定义
-                    return Err(exception::UndefinedVariableError::new(&var_name));
+                    return Err(exception::UndefinedVariableError::new(&var_token));
                 }
-                let expression_reference = expression_reference.unwrap().clone();
+                let variable_symbol = variable_symbol.unwrap();
+                variable_symbol.used.set(true);
+                let expression_reference = variable_symbol.expression().clone();
                 let ans_node = ast_tree::VariableNode::new(
                     self.get_parser_kernel().get_curr_token().lexeme(), &expression_reference,
                 );
@@ -150,10 +299,24 @@ impl ExpressionParser {
             //括号（子表达式）
             lexer::TokenTypeEnum::LBracket => {
                 self.get_mut_parser_kernel().match_and_eat_token(token_type)?;
-                let ans_node_ref = self.parse_expression()?;
+                let ans_node_ref = self.parse_condition()?;
                 self.get_mut_parser_kernel().match_and_eat_token(lexer::TokenTypeEnum::RBracket)?;
                 Ok(ans_node_ref)
             }
+            //条件表达式
+            lexer::TokenTypeEnum::If => {
+                self.get_mut_parser_kernel().match_and_eat_token(token_type)?;
+                self.get_mut_parser_kernel().match_and_eat_token(lexer::TokenTypeEnum::LBracket)?;
+                let condition = self.parse_condition()?;
+                self.get_mut_parser_kernel().match_and_eat_token(lexer::TokenTypeEnum::Comma)?;
+                let then_node = self.parse_condition()?;
+                self.get_mut_parser_kernel().match_and_eat_token(lexer::TokenTypeEnum::Comma)?;
+                let else_node = self.parse_condition()?;
+                self.get_mut_parser_kernel().match_and_eat_token(lexer::TokenTypeEnum::RBracket)?;
+                Ok(Box::new(ast_tree::IfNode::new(condition, then_node, else_node)))
+            }
+            //分段函数
+            lexer::TokenTypeEnum::Piecewise => self.parse_piecewise(),
             //函数
             lexer::TokenTypeEnum::Func => {
                 let func_token = self.get_mut_parser_kernel().get_curr_token().clone();
@@ -174,38 +337,177 @@ impl ExpressionParser {
                             }
                             first_arg_flag = false;
                             //获取参数表达式
-                            let new_arg_node = self.parse_expression()?;
+                            let new_arg_node = self.parse_condition()?;
                             arg_nodes.push(new_arg_node);
                         }
                     }
                 }
 
+                //用户定义的函数直接调用，以便编译为字节码
+                if let Some(symbol) = self.get_function_symbol(func_token.lexeme()) {
+                    return Ok(Box::new(ast_tree::UserFuncNode::new(&symbol.function, arg_nodes)));
+                }
                 let ans_node = ast_tree::FuncNode::new(&func_token, arg_nodes);
                 Ok(Box::new(ans_node))
             }
             _ => {
                 self.get_mut_parser_kernel().generate_syntax_error(&[
-                    lexer::TokenTypeEnum::ConstId, lexer::TokenTypeEnum::Variable, lexer::TokenTypeEnum::LBracket, lexer::TokenTypeEnum::Func
+                    lexer::TokenTypeEnum::ConstId, lexer::TokenTypeEnum::Variable, lexer::TokenTypeEnum::LBracket,
+                    lexer::TokenTypeEnum::Func, lexer::TokenTypeEnum::If, lexer::TokenTypeEnum::Piecewise
                 ])
             }
-        };
+        }
+    }
+
+    ///定义函数：把函数注册为词法分析器中的函数名，并进入其形参作用域
+    ///分析完函数体后再用set_function_body填入。先注册再分析函数体，因此函数体中可以递归调用自身
+    pub fn define_function(&mut self, func_token: &lexer::Token, param_tokens: &[lexer::Token]) {
+        let parameters: Vec<Rc<RefCell<f64>>> = param_tokens.iter()
+            .map(|_| Rc::new(RefCell::new(0.0)))
+            .collect();
+        self.parameter_scope = param_tokens.iter()
+            .map(|t| t.lexeme().clone())
+            .zip(parameters.iter().cloned())
+            .collect();
+
+        let function = Rc::new(ast_tree::UserFunction::new(
+            func_token.lexeme(), parameters, &self.call_depth, &self.max_recursion_depth,
+        ));
+        //词法分析器中的token只用于识别函数名，调用由UserFuncNode直接完成
+        let mut registered_token = lexer::TokenBuilder::new().token_type(lexer::TokenTypeEnum::Func)
+            .lexeme(func_token.lexeme()).build();
+        registered_token.set_span(func_token.span());
+        self.get_mut_parser_kernel().lexer.register_token(registered_token);
+
+        self.function_symbols.push(FunctionSymbol {
+            function,
+            define_token: func_token.clone(),
+        });
+    }
+
+    ///填入最新定义的函数的函数体，返回该函数
+    pub fn set_function_body(&mut self, body: Box<dyn ASTNode>) -> Rc<UserFunction> {
+        //在define_function之后调用，一定存在
+        let symbol = self.function_symbols.last().unwrap();
+        symbol.function.set_body(body);
+        symbol.function.clone()
+    }
+
+    ///离开函数体，清空形参作用域
+    pub fn exit_parameter_scope(&mut self) {
+        self.parameter_scope.clear();
+    }
+
+    ///获取某个名字最新的用户函数定义
+    pub fn get_function_symbol(&self, func_name: &str) -> Option<&FunctionSymbol> {
+        self.function_symbols.iter().rev().find(|symbol| symbol.define_token().lexeme() == func_name)
+    }
+
+    pub fn set_max_recursion_depth(&mut self, max_recursion_depth: usize) {
+        self.max_recursion_depth.set(max_recursion_depth);
+    }
+
+    ///分段函数，ELSE分支可以省略
+    ///PIECEWISE((cond1,ex1),(cond2,ex2),...,ELSE ex3)
+    fn parse_piecewise(&mut self) -> exception::Result<Box<dyn ASTNode>> {
+        self.get_mut_parser_kernel().match_and_eat_token(lexer::TokenTypeEnum::Piecewise)?;
+        self.get_mut_parser_kernel().match_and_eat_token(lexer::TokenTypeEnum::LBracket)?;
+        let mut branches = Vec::new();
+        let mut else_node = None;
+        loop {
+            if self.get_mut_parser_kernel().get_curr_token_type() == lexer::TokenTypeEnum::Else {
+                //ELSE必须是最后一个分支
+                self.get_mut_parser_kernel().match_and_eat_token(lexer::TokenTypeEnum::Else)?;
+                else_node = Some(self.parse_condition()?);
+                break;
+            }
+            self.get_mut_parser_kernel().match_and_eat_token(lexer::TokenTypeEnum::LBracket)?;
+            let condition = self.parse_condition()?;
+            self.get_mut_parser_kernel().match_and_eat_token(lexer::TokenTypeEnum::Comma)?;
+            let branch = self.parse_condition()?;
+            self.get_mut_parser_kernel().match_and_eat_token(lexer::TokenTypeEnum::RBracket)?;
+            branches.push((condition, branch));
+
+            if self.get_mut_parser_kernel().get_curr_token_type() != lexer::TokenTypeEnum::Comma {
+                break;
+            }
+            self.get_mut_parser_kernel().match_and_eat_token(lexer::TokenTypeEnum::Comma)?;
+        }
+        self.get_mut_parser_kernel().match_and_eat_token(lexer::TokenTypeEnum::RBracket)?;
+        Ok(Box::new(ast_tree::PiecewiseNode::new(branches, else_node, &self.unmatched_piecewise_counter)))
+    }
+
+    ///循环变量
+    fn parse_loop_variable(&mut self, var_token: &lexer::Token) -> exception::Result<Box<dyn ASTNode>> {
+        let value_reference = &self.loop_variable_table[var_token.lexeme()];
+        let ans_node = ast_tree::LoopVariableNode::new(var_token.lexeme(), value_reference);
+        self.get_mut_parser_kernel().match_and_eat_token(lexer::TokenTypeEnum::Variable)?;
+        Ok(Box::new(ans_node))
+    }
+
+    ///进入循环变量的作用域，返回循环变量的值
+    pub fn enter_loop_scope(&mut self, loop_variable_name: &str) -> Rc<RefCell<f64>> {
+        self.loop_variable_scope.push(String::from(loop_variable_name));
+        self.loop_variable_table.entry(String::from(loop_variable_name))
+            .or_insert_with(|| Rc::new(RefCell::new(0.0)))
+            .clone()
+    }
+
+    ///声明外部参数，返回它的值。之后引用该名字的表达式都使用这个值
+    pub fn declare_external_parameter(&mut self, parameter_name: &str) -> Rc<RefCell<f64>> {
+        let value_reference = Rc::new(RefCell::new(f64::NAN));
+        self.external_parameters.push((String::from(parameter_name), value_reference.clone()));
+        value_reference
+    }
+
+    ///获取某个名字最新声明的外部参数的值
+    pub fn get_external_parameter(&self, parameter_name: &str) -> Option<&Rc<RefCell<f64>>> {
+        self.external_parameters.iter().rev()
+            .find(|(name, _)| name == parameter_name)
+            .map(|(_, value_reference)| value_reference)
     }
 
-    pub fn set_t(&mut self, value: f64) {
-        *self.argument_t.borrow_mut() = value;
+    ///所有外部参数的名字与值，包括被重新声明的
+    pub fn external_parameters(&self) -> &[(String, Rc<RefCell<f64>>)] {
+        &self.external_parameters
     }
 
-    pub fn get_mut_parser_kernel(&self) -> RefMut<parser::ParserKernel> {
+    ///所有循环变量的值，包括默认循环变量T
+    pub fn loop_variables(&self) -> Vec<Rc<RefCell<f64>>> {
+        self.loop_variable_table.values().cloned().collect()
+    }
+
+    ///是否正在分析某个循环的循环体
+    pub fn is_in_loop_scope(&self) -> bool {
+        !self.loop_variable_scope.is_empty()
+    }
+
+    ///离开最内层循环变量的作用域
+    pub fn exit_loop_scope(&mut self) {
+        self.loop_variable_scope.pop();
+    }
+
+    pub fn get_mut_parser_kernel(&self) -> RefMut<'_, parser::ParserKernel> {
         self.parser_kernel.borrow_mut()
     }
 
-    pub fn get_parser_kernel(&self) -> Ref<parser::ParserKernel> {
+    pub fn get_parser_kernel(&self) -> Ref<'_, parser::ParserKernel> {
         self.parser_kernel.borrow()
     }
 
-    pub fn variable_symbol_table(&mut self) -> &mut HashMap<String, Rc<RefCell<Box<dyn ASTNode>>>> {
+    pub fn variable_symbol_table(&mut self) -> &mut HashMap<String, VariableSymbol> {
         &mut self.variable_symbol_table
     }
+
+    ///负数的小数次方出现的次数，由执行器取出
+    pub fn negative_base_counter(&self) -> &Rc<Cell<usize>> {
+        &self.negative_base_counter
+    }
+
+    ///PIECEWISE没有分支匹配的次数，由执行器取出
+    pub fn unmatched_piecewise_counter(&self) -> &Rc<Cell<usize>> {
+        &self.unmatched_piecewise_counter
+    }
 }
 
 
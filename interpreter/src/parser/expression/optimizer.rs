@@ -0,0 +1,190 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use super::ast_tree::{ASTNode, ConstNode};
+use super::bytecode::Compiler;
+
+///化简表达式：折叠常数子树、消去恒等运算。
+///在执行语句之前进行，此时除了varying中的循环变量，其他循环变量与DEF变量的值都不会再变化，
+///因此不依赖varying的子树会被提前求值，相当于把它们移出了循环
+pub struct Optimizer {
+    //求值过程中会变化的循环变量
+    varying: Vec<Rc<RefCell<f64>>>,
+    //负数的小数次方、PIECEWISE没有分支匹配的计数。提前求值时产生了计数就不能折叠，否则警告的次数会变少
+    negative_base_counter: Rc<Cell<usize>>,
+    unmatched_piecewise_counter: Rc<Cell<usize>>,
+}
+
+impl Optimizer {
+    pub fn new(varying: &[Rc<RefCell<f64>>], negative_base_counter: &Rc<Cell<usize>>,
+               unmatched_piecewise_counter: &Rc<Cell<usize>>) -> Self {
+        Optimizer {
+            varying: varying.to_vec(),
+            negative_base_counter: negative_base_counter.clone(),
+            unmatched_piecewise_counter: unmatched_piecewise_counter.clone(),
+        }
+    }
+
+    pub fn optimize(&self, expression: &dyn ASTNode) -> Box<dyn ASTNode> {
+        expression.optimize(self)
+    }
+
+    pub fn is_varying(&self, value_reference: &Rc<RefCell<f64>>) -> bool {
+        self.varying.iter().any(|v| Rc::ptr_eq(v, value_reference))
+    }
+
+    ///子树是否读取了会变化的循环变量。用户函数的函数体也可能读取循环变量，借助编译器找出所有被读取的变量
+    pub fn reads_varying(&self, node: &dyn ASTNode) -> bool {
+        Compiler::compile(node).slots().iter().any(|slot| self.is_varying(slot))
+    }
+
+    ///子节点都是常数的节点直接求值。求值出错或产生了警告的计数时保持原样，留到执行时再处理
+    pub fn fold(&self, node: Box<dyn ASTNode>) -> Box<dyn ASTNode> {
+        let counts = (self.negative_base_counter.get(), self.unmatched_piecewise_counter.get());
+        match node.calculate() {
+            Ok(value) if counts == (self.negative_base_counter.get(), self.unmatched_piecewise_counter.get()) =>
+                Box::new(ConstNode::new(value)),
+            _ => {
+                self.negative_base_counter.set(counts.0);
+                self.unmatched_piecewise_counter.set(counts.1);
+                node
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::lexer::{Token, TokenBuilder, TokenTypeEnum};
+    use super::super::ast_tree::*;
+    use super::*;
+
+    fn operator(token_type: TokenTypeEnum, func: fn(f64, f64) -> f64) -> Token {
+        TokenBuilder::new().token_type(token_type).lexeme(token_type.describe())
+            .func(Arc::new(move |args| Ok(func(args[0], args[1])))).build()
+    }
+
+    fn binary(token_type: TokenTypeEnum, func: fn(f64, f64) -> f64,
+              left: Box<dyn ASTNode>, right: Box<dyn ASTNode>) -> Box<dyn ASTNode> {
+        Box::new(BinaryNode::new(&operator(token_type, func), left, right))
+    }
+
+    fn tree(node: &dyn ASTNode) -> String {
+        let mut out = String::new();
+        node.write_tree(0, &mut out);
+        out
+    }
+
+    #[test]
+    fn test_optimize_constant() {
+        //S * (PI / 4) + (0 - T) * 1，S不会变化
+        let (s, t) = (Rc::new(RefCell::new(2.0)), Rc::new(RefCell::new(0.0)));
+        let quarter_pi = binary(TokenTypeEnum::Div, |a, b| a / b,
+                                Box::new(ConstNode::new(std::f64::consts::PI)), Box::new(ConstNode::new(4.0)));
+        let scaled = binary(TokenTypeEnum::Mul, |a, b| a * b, Box::new(LoopVariableNode::new("S", &s)), quarter_pi);
+        let negated = binary(TokenTypeEnum::Minus, |a, b| a - b,
+                             Box::new(ConstNode::new(0.0)), Box::new(LoopVariableNode::new("T", &t)));
+        let root = binary(TokenTypeEnum::Plus, |a, b| a + b, scaled,
+                          binary(TokenTypeEnum::Mul, |a, b| a * b, negated, Box::new(ConstNode::new(1.0))));
+
+        let counter = Rc::new(Cell::new(0));
+        let optimized = Optimizer::new(std::slice::from_ref(&t), &counter, &counter).optimize(root.as_ref());
+        //0 - T在T为0时得到+0，取负得到-0，因此不化为取负
+        let expected = binary(TokenTypeEnum::Plus, |a, b| a + b,
+                              Box::new(ConstNode::new(std::f64::consts::PI / 2.0)),
+                              binary(TokenTypeEnum::Minus, |a, b| a - b,
+                                     Box::new(ConstNode::new(0.0)), Box::new(LoopVariableNode::new("T", &t))));
+        assert_eq!(tree(optimized.as_ref()), tree(expected.as_ref()));
+        for value in [-1.0, 0.5, 3.0] {
+            *t.borrow_mut() = value;
+            assert_eq!(optimized.calculate().unwrap(), root.calculate().unwrap());
+        }
+
+        //全部是常数
+        let optimized = Optimizer::new(&[], &counter, &counter).optimize(root.as_ref());
+        assert_eq!(optimized.constant_value(), Some(std::f64::consts::PI / 2.0 - 3.0));
+    }
+
+    #[test]
+    fn test_optimize_signed_zero() {
+        //T + 0、0 + T、0 - T在T为±0时与T、-T的符号不同，保持原样；T - 0、T * 1、T / 1可以化简
+        let t = Rc::new(RefCell::new(0.0));
+        let counter = Rc::new(Cell::new(0));
+        let optimizer = Optimizer::new(std::slice::from_ref(&t), &counter, &counter);
+        let variable = || -> Box<dyn ASTNode> { Box::new(LoopVariableNode::new("T", &t)) };
+        let zero = || -> Box<dyn ASTNode> { Box::new(ConstNode::new(0.0)) };
+        let one = || -> Box<dyn ASTNode> { Box::new(ConstNode::new(1.0)) };
+        let kept = [
+            binary(TokenTypeEnum::Plus, |a, b| a + b, variable(), zero()),
+            binary(TokenTypeEnum::Plus, |a, b| a + b, zero(), variable()),
+            binary(TokenTypeEnum::Minus, |a, b| a - b, zero(), variable()),
+        ];
+        let simplified = [
+            binary(TokenTypeEnum::Minus, |a, b| a - b, variable(), zero()),
+            binary(TokenTypeEnum::Mul, |a, b| a * b, variable(), one()),
+            binary(TokenTypeEnum::Mul, |a, b| a * b, one(), variable()),
+            binary(TokenTypeEnum::Div, |a, b| a / b, variable(), one()),
+        ];
+        for node in &kept {
+            assert_eq!(tree(optimizer.optimize(node.as_ref()).as_ref()), tree(node.as_ref()));
+        }
+        for node in &simplified {
+            assert_eq!(tree(optimizer.optimize(node.as_ref()).as_ref()), tree(variable().as_ref()));
+        }
+        for value in [0.0, -0.0] {
+            *t.borrow_mut() = value;
+            for node in kept.iter().chain(&simplified) {
+                let (optimized, expected) = (optimizer.optimize(node.as_ref()).calculate().unwrap(), node.calculate().unwrap());
+                assert_eq!(optimized.to_bits(), expected.to_bits());
+            }
+        }
+    }
+
+    #[test]
+    fn test_optimize_branch() {
+        let t = Rc::new(RefCell::new(0.0));
+        let counter = Rc::new(Cell::new(0));
+        let optimizer = Optimizer::new(std::slice::from_ref(&t), &counter, &counter);
+
+        //IF(1 < 2, T, 1 / 0)只保留被选中的分支
+        let if_node = IfNode::new(
+            binary(TokenTypeEnum::Less, |a, b| f64::from(a < b), Box::new(ConstNode::new(1.0)), Box::new(ConstNode::new(2.0))),
+            Box::new(LoopVariableNode::new("T", &t)),
+            binary(TokenTypeEnum::Div, |a, b| a / b, Box::new(ConstNode::new(1.0)), Box::new(ConstNode::new(0.0))),
+        );
+        let expected = LoopVariableNode::new("T", &t);
+        assert_eq!(tree(optimizer.optimize(&if_node).as_ref()), tree(&expected));
+
+        //条件恒为假的分支被去掉，恒为真的分支成为ELSE
+        let piecewise_node = PiecewiseNode::new(vec![
+            (Box::new(ConstNode::new(0.0)), Box::new(ConstNode::new(1.0))),
+            (Box::new(LoopVariableNode::new("T", &t)), Box::new(ConstNode::new(2.0))),
+            (Box::new(ConstNode::new(1.0)), Box::new(ConstNode::new(3.0))),
+            (Box::new(LoopVariableNode::new("T", &t)), Box::new(ConstNode::new(4.0))),
+        ], None, &counter);
+        let expected = PiecewiseNode::new(vec![
+            (Box::new(LoopVariableNode::new("T", &t)), Box::new(ConstNode::new(2.0))),
+        ], Some(Box::new(ConstNode::new(3.0))), &counter);
+        assert_eq!(tree(optimizer.optimize(&piecewise_node).as_ref()), tree(&expected));
+    }
+
+    #[test]
+    fn test_optimize_keep_warning() {
+        //(-8) ** 0.5会产生警告，提前求值会让警告的次数变少，因此保持原样
+        let counter = Rc::new(Cell::new(0));
+        let power_token = operator(TokenTypeEnum::Power, f64::powf);
+        let power_node = PowerNode::new(
+            &power_token, Box::new(ConstNode::new(-8.0)), Box::new(ConstNode::new(0.5)), &counter,
+        );
+        let optimized = Optimizer::new(&[], &counter, &counter).optimize(&power_node);
+        assert_eq!(optimized.constant_value(), None);
+        assert_eq!(counter.get(), 0);
+
+        let power_node = PowerNode::new(
+            &power_token, Box::new(ConstNode::new(-8.0)), Box::new(ConstNode::new(2.0)), &counter,
+        );
+        assert_eq!(Optimizer::new(&[], &counter, &counter).optimize(&power_node).constant_value(), Some(64.0));
+    }
+}
@@ -1,252 +1,886 @@
-use std::cell::{RefCell, RefMut};
-use std::fs::File;
+use std::cell::{Ref, RefCell, RefMut};
+use std::collections::HashMap;
 use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use crate::lexer::*;
 use crate::exception;
 use crate::exception::ExceptionTrait;
+use crate::options;
 
+mod adaptive;
+mod contour;
+mod executor;
 mod expression;
+mod parallel;
 mod point_manager;
+mod statement;
+
+pub use point_manager::{PointSeries, SeriesKind, Transform};
+pub use statement::{
+    Assignment, CoordinateSystem, Expression, ForBody, ForStatement, FunctionDefinition, ImplicitStatement,
+    ParameterDeclaration, ParameterStatement, Program, ProgramStatement, Statement,
+};
+
+///默认最多收集的错误数量
+pub const DEFAULT_MAX_ERROR_COUNT: usize = 20;
+///默认的循环变量。它在循环外也可以使用，以兼容在DEF中直接使用T的程序
+pub const DEFAULT_LOOP_VARIABLE: &str = "T";
+///默认的用户函数最大递归深度
+pub const DEFAULT_MAX_RECURSION_DEPTH: usize = 256;
+///默认的每条FOR语句最大迭代次数
+pub const DEFAULT_MAX_LOOP_ITERATIONS: usize = 1_000_000;
+//IMPLICIT表达式中的两个变量
+const IMPLICIT_X_VARIABLE: &str = "X";
+const IMPLICIT_Y_VARIABLE: &str = "Y";
+//下一个解释器的编号，用于识别程序由哪个解释器分析得到
+static NEXT_PARSER_ID: AtomicUsize = AtomicUsize::new(0);
+
+///解释结果：每条FOR或IMPLICIT语句的点序列与解释过程中产生的警告
+#[derive(Debug)]
+pub struct InterpretOutput {
+    series: Vec<PointSeries>,
+    warnings: Vec<exception::InterpretWarning>,
+}
+
+impl InterpretOutput {
+    ///按语句顺序排列的点序列
+    pub fn series(&self) -> &[PointSeries] {
+        &self.series
+    }
+    pub fn warnings(&self) -> &[exception::InterpretWarning] {
+        &self.warnings
+    }
+    pub fn into_series(self) -> Vec<PointSeries> {
+        self.series
+    }
+    ///把所有点序列按顺序展平为一个点集
+    pub fn points(&self) -> Vec<(f64, f64)> {
+        self.series.iter()
+            .flat_map(|series| series.points().iter().copied())
+            .collect()
+    }
+    pub fn into_points(self) -> Vec<(f64, f64)> {
+        self.series.into_iter()
+            .flat_map(|series| series.into_points())
+            .collect()
+    }
+}
 
 pub struct ParserManager {
+    //解释器的编号，分析得到的程序带有它
+    id: usize,
     parser_kernel: Rc<RefCell<ParserKernel>>,
+    //调试信息输出
+    tracer: Rc<RefCell<options::Tracer>>,
     //expression语法分析器
     expression_parser: expression::ExpressionParser,
-    //点生成与管理器
-    point_manager: point_manager::PointManager,
+    //执行分析得到的语句
+    executor: executor::Executor,
+    //正在分析的语句的首个token，用于报错时提示
+    curr_statement_token: Option<Token>,
+    //分析时收集的错误，附带出错时已经分析完的顶层语句数
+    errors: Vec<(usize, exception::InterpretError)>,
+    //收集到这么多错误后停止分析
+    max_error_count: usize,
+    //分析时收集的警告，附带产生时已经分析完的顶层语句数
+    warnings: Vec<(usize, exception::InterpretWarning)>,
+    //是否把警告提升为错误
+    warnings_as_errors: bool,
+    //正在分析的块的嵌套层数，用于出错后跳过整个块
+    block_depth: usize,
+    //已经分析完的顶层语句
+    statements: Vec<statement::ProgramStatement>,
+    //PARAM声明过的参数名及其区间，用于提示重复声明
+    parameter_declarations: HashMap<String, Span>,
+    //出错时已经生成的点序列与警告
+    partial_output: Option<InterpretOutput>,
+    //分析得到的程序与分析时的错误。源程序只能读取一次，之后的解释都重复执行它
+    parsed: Option<(Program, Vec<(usize, exception::InterpretError)>)>,
 }
 
 impl ParserManager {
-    pub fn new(file: File) -> Self {
-        let parser_kernel = Rc::new(RefCell::new(ParserKernel::new(file)));
+    pub fn new(source: Source) -> Self {
+        let tracer = Rc::new(RefCell::new(options::Tracer::new()));
+        let parser_kernel = Rc::new(RefCell::new(ParserKernel::new(source, &tracer)));
+        let expression_parser = expression::ExpressionParser::new(&parser_kernel, &tracer);
+        let executor = executor::Executor::new(
+            &tracer, expression_parser.negative_base_counter(), expression_parser.unmatched_piecewise_counter(),
+        );
         ParserManager {
-            expression_parser: expression::ExpressionParser::new(&parser_kernel),
-            parser_kernel, //一定要放在后面，否则会过早夺取所有权
-            point_manager: point_manager::PointManager::new(),
+            id: NEXT_PARSER_ID.fetch_add(1, Ordering::Relaxed),
+            expression_parser,
+            executor,
+            parser_kernel,
+            tracer,
+            curr_statement_token: None,
+            errors: Vec::new(),
+            max_error_count: DEFAULT_MAX_ERROR_COUNT,
+            warnings: Vec::new(),
+            warnings_as_errors: false,
+            block_depth: 0,
+            statements: Vec::new(),
+            parameter_declarations: HashMap::new(),
+            partial_output: None,
+            parsed: None,
         }
     }
 
-    pub fn parse(&mut self) -> Result<Vec<(f64, f64)>, ()> {
-        let parse_result = self.parse_program();
-        if let Err(e) = parse_result {
-            println!();
-            let err_position = self.get_mut_parser_kernel().lexer.get_char_position();
-            println!("Interpret Terminated at {}:{}", err_position.0, err_position.1);
-            e.print_exception();
-            return Err(());
+    ///分析并执行整个程序，返回点集与警告。出错时返回所有附带位置的异常
+    pub fn parse(&mut self) -> Result<InterpretOutput, Vec<exception::InterpretError>> {
+        let (program, parse_errors) = self.parse_program();
+        self.execute_program(&program, parse_errors)
+    }
+
+    ///分析整个程序，只生成语句，不执行。返回程序与分析时的错误，错误附带出错时已经分析完的顶层语句数。
+    ///只在第一次调用时读取源程序，之后返回同一个程序
+    pub fn parse_program(&mut self) -> (Program, Vec<(usize, exception::InterpretError)>) {
+        if self.parsed.is_none() {
+            self.parsed = Some(self.parse_source());
         }
-        //返回结果点集
-        Ok(self.point_manager().move_point_storage())
+        //上面保证已经分析过
+        self.parsed.clone().unwrap()
     }
 
-    ///分析程序
-    fn parse_program(&mut self) -> exception::Result<()> {
+    ///读取并分析源程序。某句出错时记录异常，跳到下一个分号后继续分析（panic mode），以便一次报告所有错误
+    fn parse_source(&mut self) -> (Program, Vec<(usize, exception::InterpretError)>) {
         //EOF前一直读取
         while self.get_mut_parser_kernel().get_curr_token_type() != TokenTypeEnum::NonToken {
             //匹配一句
-            self.parse_statement()?;
-            self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Semico)?;
+            let statement_result = self.parse_statement().and_then(|statement| {
+                //缺少分号时语句仍然有效，之后报告语法错误
+                let begin_token = self.curr_statement_token.clone().unwrap();
+                let end_span = self.get_parser_kernel().get_curr_token().span();
+                let span = self.curr_statement_span();
+                self.statements.push(statement::ProgramStatement::new(statement, begin_token, span, end_span));
+                self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Semico)
+            });
+            if let Err(e) = statement_result {
+                let span = self.get_parser_kernel().get_curr_token().span();
+                let interpret_error = self.generate_interpret_error(e, span, self.curr_statement_token.as_ref());
+                self.errors.push((self.statements.len(), interpret_error));
+                if self.errors.len() >= self.max_error_count {
+                    break;
+                }
+                let block_depth = self.block_depth;
+                self.get_mut_parser_kernel().skip_to_token(TokenTypeEnum::Semico, block_depth);
+            }
         }
-        Ok(())
+        self.check_unused_variables();
+
+        let program = Program::new(
+            self.id, std::mem::take(&mut self.statements), std::mem::take(&mut self.warnings), self.expression_parser.loop_variables(),
+            self.expression_parser.external_parameters().to_vec(),
+        );
+        (program, std::mem::take(&mut self.errors))
+    }
+
+    ///执行分析得到的程序，返回点集与警告。分析与执行时的错误、警告按产生的先后排列，
+    ///与边分析边执行时相同：某条语句之前分析出的错误与警告排在执行它时产生的之前
+    pub fn execute_program(&mut self, program: &Program, parse_errors: Vec<(usize, exception::InterpretError)>)
+                           -> Result<InterpretOutput, Vec<exception::InterpretError>> {
+        //其他解释器的程序引用的是那个解释器的变量，不能在这里执行
+        if program.parser_id() != self.id {
+            return Err(vec![exception::InterpretError::new(exception::ForeignProgramError::new(), Span::default())]);
+        }
+        self.executor.start(program);
+        self.partial_output = None;
+        let (mut errors, mut warnings) = (Vec::new(), Vec::new());
+        let mut parse_errors = parse_errors.into_iter().peekable();
+        let mut parse_warnings = program.indexed_warnings().iter().peekable();
+        let mut stopped = false;
+        for (i, program_statement) in program.statements().iter().enumerate() {
+            errors.extend(std::iter::from_fn(|| parse_errors.next_if(|(index, _)| *index <= i)).map(|(_, e)| e));
+            warnings.extend(std::iter::from_fn(|| parse_warnings.next_if(|(index, _)| *index <= i)).map(|(_, w)| w.clone()));
+            if errors.len() >= self.max_error_count {
+                stopped = true;
+                break;
+            }
+            match self.executor.execute(program_statement) {
+                Ok(statement_warnings) => warnings.extend(
+                    statement_warnings.into_iter().map(|w| self.attach_source_line(w))
+                ),
+                Err(e) => {
+                    let is_resource_limit_error = e.kind().is_resource_limit_error();
+                    errors.push(self.generate_interpret_error(e, program_statement.end_span(), Some(program_statement.begin_token())));
+                    //超过资源限制时不再继续解释
+                    if is_resource_limit_error {
+                        stopped = true;
+                        break;
+                    }
+                }
+            }
+        }
+        if !stopped {
+            errors.extend(parse_errors.map(|(_, e)| e));
+            warnings.extend(parse_warnings.map(|(_, w)| w.clone()));
+        }
+        errors.truncate(self.max_error_count);
+
+        let series = self.executor.take_series();
+        if self.warnings_as_errors {
+            errors.extend(warnings.into_iter().map(|w| w.into_error()));
+            errors.sort_by_key(|e| e.position());
+            errors.truncate(self.max_error_count);
+            if !errors.is_empty() {
+                self.partial_output = Some(InterpretOutput { series, warnings: Vec::new() });
+                return Err(errors);
+            }
+            return Ok(InterpretOutput { series, warnings: Vec::new() });
+        }
+
+        if !errors.is_empty() {
+            self.partial_output = Some(InterpretOutput { series, warnings });
+            return Err(errors);
+        }
+        //返回结果点集
+        Ok(InterpretOutput { series, warnings })
+    }
+
+    ///为异常附上出错区间、源码与所在的语句，生成对外的InterpretError
+    ///异常自带token则定位到该token，否则定位到default_span
+    fn generate_interpret_error(&self, e: exception::Exception, default_span: Span,
+                                statement_token: Option<&Token>) -> exception::InterpretError {
+        let span = match e.token() {
+            Some(token) => token.span(),
+            None => default_span,
+        };
+        let mut interpret_error = exception::InterpretError::new(e, span);
+        if let Some(line_text) = self.get_parser_kernel().lexer.get_line_text(span.start().0) {
+            interpret_error = interpret_error.with_source_line(line_text);
+        }
+        if let Some(statement_token) = statement_token {
+            let (line, col) = statement_token.span().start();
+            interpret_error = interpret_error.with_note(&format!(
+                "while parsing {} statement starting at {}:{}",
+                statement_token.token_type().describe(), line, col
+            ));
+        }
+        interpret_error
+    }
+
+    ///为执行时产生的警告附上源码
+    fn attach_source_line(&self, warning: exception::InterpretWarning) -> exception::InterpretWarning {
+        match self.get_parser_kernel().lexer.get_line_text(warning.position().0) {
+            Some(line_text) => warning.with_source_line(line_text),
+            None => warning,
+        }
+    }
+
+    ///生成一条附上源码的警告
+    fn generate_interpret_warning(&self, kind: exception::WarningKind, message: &str, span: Span) -> exception::InterpretWarning {
+        let warning = exception::InterpretWarning::new(kind, message, span);
+        match self.get_parser_kernel().lexer.get_line_text(span.start().0) {
+            Some(line_text) => warning.with_source_line(line_text),
+            None => warning,
+        }
+    }
+
+    ///记录一条分析时产生的警告
+    fn add_warning(&mut self, warning: exception::InterpretWarning) {
+        self.warnings.push((self.statements.len(), warning));
+    }
+
+    ///当前语句从首个token到上一个被吃掉的token的区间
+    fn curr_statement_span(&self) -> Span {
+        let prev_token_span = self.get_parser_kernel().get_prev_token_span();
+        match &self.curr_statement_token {
+            Some(statement_token) => statement_token.span().merge(&prev_token_span),
+            None => prev_token_span,
+        }
+    }
+
+    ///程序结束后，为从未被使用的表达式变量生成警告
+    fn check_unused_variables(&mut self) {
+        let mut unused_tokens: Vec<Token> = self.expression_parser().variable_symbol_table().values()
+            .filter(|symbol| !symbol.is_used())
+            .map(|symbol| symbol.define_token().clone())
+            .collect();
+        unused_tokens.sort_by_key(|token| token.span().start());
+        for token in unused_tokens {
+            self.add_unused_variable_warning(&token);
+        }
+    }
+
+    fn add_unused_variable_warning(&mut self, define_token: &Token) {
+        let warning = self.generate_interpret_warning(
+            exception::WarningKind::UnusedVariable,
+            &format!("variable `{}` is defined but never used", define_token.lexeme()),
+            define_token.span(),
+        );
+        self.add_warning(warning);
     }
 
-    ///分析语句
-    fn parse_statement(&mut self) -> exception::Result<()> {
-        println!("Debug: parse a statement, begin token: {:?}", self.get_mut_parser_kernel().get_curr_token());
+    ///分析一条顶层语句
+    fn parse_statement(&mut self) -> exception::Result<statement::Statement> {
+        let statement_token = self.get_parser_kernel().get_curr_token().clone();
+        self.tracer.borrow_mut().trace_statement(&statement_token);
+        self.curr_statement_token = Some(statement_token);
+        self.block_depth = 0;
         let token_type = self.get_mut_parser_kernel().get_curr_token_type();
         match token_type {
-            TokenTypeEnum::Origin => self.parse_origin_statement()?,
-            TokenTypeEnum::Scale => self.parse_scale_statement()?,
-            TokenTypeEnum::Rot => self.parse_rot_statement()?,
-            TokenTypeEnum::For => self.parse_for_statement()?,
-            TokenTypeEnum::Def => self.parse_def_statement()?,
-            TokenTypeEnum::Let => self.parse_let_statement()?,
-            _ => return self.get_mut_parser_kernel().generate_syntax_error(&[
-                TokenTypeEnum::Origin, TokenTypeEnum::Scale, TokenTypeEnum::Rot,
-                TokenTypeEnum::For, TokenTypeEnum::Def, TokenTypeEnum::Let
+            TokenTypeEnum::Origin | TokenTypeEnum::Scale | TokenTypeEnum::Rot
+            | TokenTypeEnum::For | TokenTypeEnum::If | TokenTypeEnum::Implicit => self.parse_executable_statement(),
+            TokenTypeEnum::Def => self.parse_def_statement(),
+            TokenTypeEnum::Let => self.parse_let_statement(),
+            TokenTypeEnum::Fn => self.parse_fn_statement(),
+            TokenTypeEnum::Param => self.parse_param_statement(),
+            _ => self.get_mut_parser_kernel().generate_syntax_error(&[
+                TokenTypeEnum::Origin, TokenTypeEnum::Scale, TokenTypeEnum::Rot, TokenTypeEnum::For,
+                TokenTypeEnum::If, TokenTypeEnum::Implicit, TokenTypeEnum::Def, TokenTypeEnum::Let, TokenTypeEnum::Fn,
+                TokenTypeEnum::Param
             ]),
         }
-        Ok(())
+    }
+
+    ///分析可执行的语句，也就是可以出现在块中的语句。DRAW只能出现在循环体中
+    fn parse_executable_statement(&mut self) -> exception::Result<statement::Statement> {
+        let token_type = self.get_mut_parser_kernel().get_curr_token_type();
+        let in_loop = self.expression_parser().is_in_loop_scope();
+        match token_type {
+            TokenTypeEnum::Origin => self.parse_origin_statement(),
+            TokenTypeEnum::Scale => self.parse_scale_statement(),
+            TokenTypeEnum::Rot => self.parse_rot_statement(),
+            TokenTypeEnum::For => self.parse_for_statement(),
+            TokenTypeEnum::If => self.parse_if_statement(),
+            TokenTypeEnum::Implicit => self.parse_implicit_statement(),
+            TokenTypeEnum::Draw if in_loop => self.parse_draw_statement(),
+            _ => {
+                let mut expected_token_types = vec![
+                    TokenTypeEnum::Origin, TokenTypeEnum::Scale, TokenTypeEnum::Rot, TokenTypeEnum::For, TokenTypeEnum::If,
+                    TokenTypeEnum::Implicit,
+                ];
+                if in_loop {
+                    expected_token_types.push(TokenTypeEnum::Draw);
+                }
+                if self.block_depth > 0 {
+                    expected_token_types.push(TokenTypeEnum::End);
+                }
+                self.get_mut_parser_kernel().generate_syntax_error(&expected_token_types)
+            }
+        }
+    }
+
+    ///条件语句，ELSE与最近的IF匹配
+    ///IF ex THEN branch [ELSE branch]
+    fn parse_if_statement(&mut self) -> exception::Result<statement::Statement> {
+        self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::If)?;
+        let condition = self.parse_expression()?;
+        self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Then)?;
+        let then_branch = self.parse_if_branch()?;
+        let else_branch = if self.get_mut_parser_kernel().get_curr_token_type() == TokenTypeEnum::Else {
+            self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Else)?;
+            self.parse_if_branch()?
+        } else {
+            Vec::new()
+        };
+        Ok(statement::Statement::If(condition, then_branch, else_branch))
+    }
+
+    ///条件语句的分支，是一条可执行的语句或一个块
+    fn parse_if_branch(&mut self) -> exception::Result<Vec<statement::Statement>> {
+        if self.get_mut_parser_kernel().get_curr_token_type() == TokenTypeEnum::Begin {
+            return self.parse_block();
+        }
+        Ok(vec![self.parse_executable_statement()?])
     }
 
     ///平移
     ///ORIGIN IS (ex1,ex2)
-    fn parse_origin_statement(&mut self) -> exception::Result<()> {
+    fn parse_origin_statement(&mut self) -> exception::Result<statement::Statement> {
         self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Origin)?;
         self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Is)?;
-        self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::LBracket)?;
-        let x = self.expression_parser().parse_expression_entrance()?.calculate()?;
-        self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Comma)?;
-        let y = self.expression_parser().parse_expression_entrance()?.calculate()?;
-        self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::RBracket)?;
-
-        self.point_manager().set_var_origin((x, y));
-
-        Ok(())
+        let (x, y) = self.parse_point_expressions()?;
+        Ok(statement::Statement::Origin(x, y))
     }
 
     ///放大
     ///SCALE IS (ex1,ex2)
-    fn parse_scale_statement(&mut self) -> exception::Result<()> {
+    fn parse_scale_statement(&mut self) -> exception::Result<statement::Statement> {
         self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Scale)?;
         self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Is)?;
-        self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::LBracket)?;
-        let x = self.expression_parser().parse_expression_entrance()?.calculate()?;
-        self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Comma)?;
-        let y = self.expression_parser().parse_expression_entrance()?.calculate()?;
-        self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::RBracket)?;
-
-        self.point_manager().set_var_scale((x, y));
-
-        Ok(())
+        let (x, y) = self.parse_point_expressions()?;
+        Ok(statement::Statement::Scale(x, y))
     }
 
     ///旋转
     ///ROT IS ex
-    fn parse_rot_statement(&mut self) -> exception::Result<()> {
+    fn parse_rot_statement(&mut self) -> exception::Result<statement::Statement> {
         self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Rot)?;
         self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Is)?;
-        let r = self.expression_parser().parse_expression_entrance()?.calculate()?;
-
-        self.point_manager().set_var_rot(r);
-
-        Ok(())
+        let r = self.parse_expression()?;
+        Ok(statement::Statement::Rot(r))
     }
 
     ///绘制
-    ///FOR var FROM ex1 TO ex2 STEP ex3 DRAW(ex4,ex5)
-    fn parse_for_statement(&mut self) -> exception::Result<()> {
+    ///FOR var FROM ex1 TO ex2 STEP ex3 [ADAPTIVE] DRAW(ex4,ex5)
+    ///FOR var FROM ex1 TO ex2 STEP ex3 DO BEGIN statement; ... END
+    fn parse_for_statement(&mut self) -> exception::Result<statement::Statement> {
+        let for_token_span = self.get_parser_kernel().get_curr_token().span();
         self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::For)?;
-        //这里暂时只能是T。这也是为什么规定T和variable分开，如果功能拓展了就能一视同仁
-        self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::T)?;
+        //循环变量可以是任意变量名，只在循环体中可见
+        let loop_variable_token = self.get_mut_parser_kernel().get_curr_token().clone();
+        self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Variable)?;
         self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::From)?;
-        let from = self.expression_parser().parse_expression_entrance()?.calculate()?;
+        let from = self.parse_expression()?;
         self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::To)?;
-        let to = self.expression_parser().parse_expression_entrance()?.calculate()?;
+        let to = self.parse_expression()?;
         self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Step)?;
-        let step = self.expression_parser().parse_expression_entrance()?.calculate()?;
-        self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Draw)?;
+        let step = self.parse_expression()?;
+
+        //循环体，在循环变量的作用域中分析
+        let nested = self.expression_parser().is_in_loop_scope();
+        let loop_variable = self.expression_parser().enter_loop_scope(loop_variable_token.lexeme());
+        let body_result = self.parse_for_body();
+        self.expression_parser().exit_loop_scope();
+        let body = body_result?;
+
+        let span = for_token_span.merge(&self.get_parser_kernel().get_prev_token_span());
+        Ok(statement::Statement::For(statement::ForStatement::new(
+            span, loop_variable_token.lexeme(), &loop_variable, from, to, step, body, nested,
+        )))
+    }
 
-        //点生成函数
+    ///循环体，ADAPTIVE只能用于单个DRAW
+    ///[ADAPTIVE] DRAW [POLAR] (ex1,ex2) | DO BLOCK
+    fn parse_for_body(&mut self) -> exception::Result<statement::ForBody> {
+        let token_type = self.get_mut_parser_kernel().get_curr_token_type();
+        match token_type {
+            TokenTypeEnum::Adaptive => {
+                self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Adaptive)?;
+                let (coordinate_system, x, y) = self.parse_draw()?;
+                Ok(statement::ForBody::AdaptiveDraw(coordinate_system, x, y))
+            }
+            TokenTypeEnum::Draw => {
+                let (coordinate_system, x, y) = self.parse_draw()?;
+                Ok(statement::ForBody::Draw(coordinate_system, x, y))
+            }
+            TokenTypeEnum::Do => {
+                self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Do)?;
+                Ok(statement::ForBody::Block(self.parse_block()?))
+            }
+            _ => self.get_mut_parser_kernel().generate_syntax_error(&[TokenTypeEnum::Adaptive, TokenTypeEnum::Draw, TokenTypeEnum::Do]),
+        }
+    }
+
+    ///隐函数曲线，表达式中可以使用X、Y
+    ///IMPLICIT ex OVER (ex1,ex2,ex3,ex4) RESOLUTION ex5
+    fn parse_implicit_statement(&mut self) -> exception::Result<statement::Statement> {
+        let implicit_token_span = self.get_parser_kernel().get_curr_token().span();
+        self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Implicit)?;
+
+        let x_variable = self.expression_parser().enter_loop_scope(IMPLICIT_X_VARIABLE);
+        let y_variable = self.expression_parser().enter_loop_scope(IMPLICIT_Y_VARIABLE);
+        let expression_result = self.parse_expression();
+        self.expression_parser().exit_loop_scope();
+        self.expression_parser().exit_loop_scope();
+        let expression = expression_result?;
+
+        self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Over)?;
         self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::LBracket)?;
-        let x_expression = self.expression_parser().parse_expression_entrance()?;
+        let x_min = self.parse_expression()?;
+        self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Comma)?;
+        let x_max = self.parse_expression()?;
+        self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Comma)?;
+        let y_min = self.parse_expression()?;
         self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Comma)?;
-        let y_expression = self.expression_parser().parse_expression_entrance()?;
+        let y_max = self.parse_expression()?;
         self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::RBracket)?;
+        self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Resolution)?;
+        let resolution = self.parse_expression()?;
 
-        //生成所有点
-        let mut discarded_point = Vec::new(); //记录被丢弃的所有点
-        let mut curr_t = from;
-        while curr_t <= to {
-            self.expression_parser().set_t(curr_t);
-            let mut coordinate = (x_expression.calculate()?, y_expression.calculate()?);
-            let res = self.point_manager().add_point(&mut coordinate);
-            if let Err(()) = res {
-                discarded_point.push(coordinate);
-            }
-            curr_t += step;
-        }
-        if !discarded_point.is_empty() {
-            println!("Warning: Discard Points: {:?}", discarded_point);
+        let span = implicit_token_span.merge(&self.get_parser_kernel().get_prev_token_span());
+        Ok(statement::Statement::Implicit(statement::ImplicitStatement::new(
+            span, &x_variable, &y_variable, expression, [x_min, x_max, y_min, y_max], resolution,
+        )))
+    }
+
+    ///块，其中每条语句都以分号结尾
+    ///BEGIN statement; ... END
+    fn parse_block(&mut self) -> exception::Result<Vec<statement::Statement>> {
+        self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Begin)?;
+        self.block_depth += 1;
+        let mut statements = Vec::new();
+        while self.get_mut_parser_kernel().get_curr_token_type() != TokenTypeEnum::End {
+            statements.push(self.parse_executable_statement()?);
+            self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Semico)?;
         }
+        self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::End)?;
+        self.block_depth -= 1;
+        Ok(statements)
+    }
 
-        Ok(())
+    ///块中绘制一个点
+    ///DRAW [POLAR] (ex1,ex2)
+    fn parse_draw_statement(&mut self) -> exception::Result<statement::Statement> {
+        let (coordinate_system, x, y) = self.parse_draw()?;
+        Ok(statement::Statement::Draw(coordinate_system, x, y))
+    }
+
+    ///DRAW及其坐标系和坐标
+    ///DRAW [POLAR] (ex1,ex2)
+    fn parse_draw(&mut self) -> exception::Result<(statement::CoordinateSystem, statement::Expression, statement::Expression)> {
+        self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Draw)?;
+        let coordinate_system = if self.get_mut_parser_kernel().get_curr_token_type() == TokenTypeEnum::Polar {
+            self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Polar)?;
+            statement::CoordinateSystem::Polar
+        } else {
+            statement::CoordinateSystem::Cartesian
+        };
+        let (x, y) = self.parse_point_expressions()?;
+        Ok((coordinate_system, x, y))
     }
 
-    ///定义表达式变量
-    ///DEF var = ex TODO 禁止定义T
-    fn parse_def_statement(&mut self) -> exception::Result<()> {
+    ///一对坐标
+    ///(ex1,ex2)
+    fn parse_point_expressions(&mut self) -> exception::Result<(statement::Expression, statement::Expression)> {
+        self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::LBracket)?;
+        let x_expression = self.parse_expression()?;
+        self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Comma)?;
+        let y_expression = self.parse_expression()?;
+        self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::RBracket)?;
+        Ok((x_expression, y_expression))
+    }
+
+    ///定义表达式变量。之后引用该变量的表达式在分析时就与这个定义绑定
+    ///DEF var = ex
+    fn parse_def_statement(&mut self) -> exception::Result<statement::Statement> {
         self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Def)?;
 
-        let var_name = self.get_mut_parser_kernel().get_curr_token().lexeme().clone();
+        let var_token = self.get_mut_parser_kernel().get_curr_token().clone();
         self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Variable)?;
         self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Assign)?;
 
-        let ex = self.expression_parser().parse_expression_entrance()?;
-        self.expression_parser().variable_symbol_table().insert(
-            var_name, Rc::new(RefCell::new(ex)),
-        );
+        let ex = self.parse_expression()?;
+        let symbol = expression::VariableSymbol::new(&var_token, ex.shared_node().clone());
+        let assignment = statement::Assignment::new(var_token.lexeme(), symbol.expression(), ex);
+        let old_symbol = self.expression_parser().variable_symbol_table().insert(var_token.lexeme().clone(), symbol);
+
+        //覆盖了同名变量
+        if let Some(old_symbol) = old_symbol {
+            let (line, col) = old_symbol.define_token().span().start();
+            let message = format!("`{}` shadows the variable defined at {}:{}", var_token.lexeme(), line, col);
+            let warning = self.generate_interpret_warning(exception::WarningKind::ShadowedDefinition, &message, var_token.span())
+                .with_note("earlier references keep using the previous definition");
+            self.add_warning(warning);
+            if !old_symbol.is_used() {
+                self.add_unused_variable_warning(old_symbol.define_token());
+            }
+        }
 
-        Ok(())
+        Ok(statement::Statement::Def(assignment))
     }
 
-    ///重赋值表达式变量
+    ///重赋值表达式变量，执行时生效
     ///LET var = ex
-    fn parse_let_statement(&mut self) -> exception::Result<()> {
+    fn parse_let_statement(&mut self) -> exception::Result<statement::Statement> {
         self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Let)?;
 
-        let var_name = self.get_mut_parser_kernel().get_curr_token().lexeme().clone();
+        let var_token = self.get_mut_parser_kernel().get_curr_token().clone();
         self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Variable)?;
-        let var_ref = self.expression_parser().variable_symbol_table().get(&var_name);
+        let var_ref = self.expression_parser().variable_symbol_table().get(var_token.lexeme());
         //确保变量存在
-        if let None = var_ref {
-            return Err(exception::UndefinedVariableError::new(&var_name));
+        if var_ref.is_none() {
+            return Err(exception::UndefinedVariableError::new(&var_token));
         }
-        let var_ref = var_ref.unwrap().clone();
+        let var_ref = var_ref.unwrap().expression().clone();
         self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Assign)?;
 
-        let ex = self.expression_parser().parse_expression_entrance()?;
-        *var_ref.borrow_mut() = ex;
+        let ex = self.parse_expression()?;
+        Ok(statement::Statement::Let(statement::Assignment::new(var_token.lexeme(), &var_ref, ex)))
+    }
 
-        Ok(())
+    ///定义函数
+    ///FN name(var1,var2,...) => ex
+    fn parse_fn_statement(&mut self) -> exception::Result<statement::Statement> {
+        self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Fn)?;
+
+        //函数名。已定义的用户函数会被识别为函数名，允许重新定义
+        let func_token = self.get_mut_parser_kernel().get_curr_token().clone();
+        let old_define_token = self.expression_parser().get_function_symbol(func_token.lexeme())
+            .map(|symbol| symbol.define_token().clone());
+        match (func_token.token_type(), &old_define_token) {
+            (TokenTypeEnum::Variable, _) => self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Variable)?,
+            (TokenTypeEnum::Func, Some(_)) => self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Func)?,
+            _ => return self.get_mut_parser_kernel().generate_syntax_error(&[TokenTypeEnum::Variable]),
+        }
+
+        //形参列表
+        self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::LBracket)?;
+        let mut param_tokens: Vec<Token> = Vec::new();
+        if self.get_mut_parser_kernel().get_curr_token_type() != TokenTypeEnum::RBracket {
+            loop {
+                let param_token = self.get_mut_parser_kernel().get_curr_token().clone();
+                self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Variable)?;
+                //形参名不能重复，否则后面的形参会遮住前面的
+                if param_tokens.iter().any(|token| token.lexeme() == param_token.lexeme()) {
+                    let message = format!("parameter `{}` is declared more than once", param_token.lexeme());
+                    return Err(exception::SyntaxError::with_message(&param_token, &message));
+                }
+                param_tokens.push(param_token);
+                if self.get_mut_parser_kernel().get_curr_token_type() != TokenTypeEnum::Comma {
+                    break;
+                }
+                self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Comma)?;
+            }
+        }
+        self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::RBracket)?;
+
+        //要在读取函数体的首个token前注册函数，以支持递归
+        self.expression_parser().define_function(&func_token, &param_tokens);
+        let arrow_result = self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Arrow);
+        let body_result = arrow_result.and_then(|_| self.expression_parser().parse_expression_entrance());
+        self.expression_parser().exit_parameter_scope();
+        let function = self.expression_parser().set_function_body(body_result?);
+        let parameter_names = param_tokens.iter().map(|token| token.lexeme().clone()).collect();
+
+        //覆盖了同名函数
+        if let Some(old_define_token) = old_define_token {
+            let (line, col) = old_define_token.span().start();
+            let message = format!("`{}` shadows the function defined at {}:{}", func_token.lexeme(), line, col);
+            let warning = self.generate_interpret_warning(exception::WarningKind::ShadowedDefinition, &message, func_token.span())
+                .with_note("earlier calls keep using the previous definition");
+            self.add_warning(warning);
+        }
+
+        Ok(statement::Statement::Fn(statement::FunctionDefinition::new(&function, parameter_names)))
     }
 
-    fn point_manager(&mut self) -> &mut point_manager::PointManager {
-        &mut self.point_manager
+    ///声明外部参数，宿主程序没有设置它时使用默认值
+    ///PARAM var DEFAULT ex
+    fn parse_param_statement(&mut self) -> exception::Result<statement::Statement> {
+        self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Param)?;
+
+        let var_token = self.get_mut_parser_kernel().get_curr_token().clone();
+        self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Variable)?;
+        //参数名的限制与宿主程序注册的名字相同，T、X、Y不能作为参数
+        if let Err(error) = self.check_name(var_token.lexeme()) {
+            return Err(exception::SyntaxError::with_message(&var_token, &error.to_string()));
+        }
+        self.get_mut_parser_kernel().match_and_eat_token(TokenTypeEnum::Default)?;
+        //默认值中不能引用参数自身
+        let default = self.parse_expression()?;
+
+        let old_declaration = self.parameter_declarations.insert(var_token.lexeme().clone(), var_token.span());
+        let value = self.expression_parser().declare_external_parameter(var_token.lexeme());
+
+        //重复声明
+        if let Some(old_span) = old_declaration {
+            let (line, col) = old_span.start();
+            let message = format!("`{}` shadows the parameter declared at {}:{}", var_token.lexeme(), line, col);
+            let warning = self.generate_interpret_warning(exception::WarningKind::ShadowedDefinition, &message, var_token.span())
+                .with_note("earlier references keep using the previous declaration");
+            self.add_warning(warning);
+        }
+
+        Ok(statement::Statement::Param(statement::ParameterStatement::new(&var_token, &value, default)))
+    }
+
+    ///分析语句中的一个表达式，记录它在源码中的区间
+    fn parse_expression(&mut self) -> exception::Result<statement::Expression> {
+        let begin_span = self.get_parser_kernel().get_curr_token().span();
+        let node = self.expression_parser().parse_expression_entrance()?;
+        let span = begin_span.merge(&self.get_parser_kernel().get_prev_token_span());
+        Ok(statement::Expression::new(node, span))
     }
 
     fn expression_parser(&mut self) -> &mut expression::ExpressionParser {
         &mut self.expression_parser
     }
 
-    pub fn get_mut_parser_kernel(&self) -> RefMut<ParserKernel> {
+    pub fn get_mut_parser_kernel(&self) -> RefMut<'_, ParserKernel> {
         self.parser_kernel.borrow_mut()
     }
 
+    pub fn get_parser_kernel(&self) -> Ref<'_, ParserKernel> {
+        self.parser_kernel.borrow()
+    }
+
     pub fn set_coordinate_range(&mut self, min_x: f64, max_x: f64, min_y: f64, max_y: f64) {
-        self.point_manager().set_coordinate_range(min_x, max_x, min_y, max_y);
+        self.executor.set_coordinate_range(min_x, max_x, min_y, max_y);
+    }
+
+    ///设置外部参数，参数名不区分大小写。参数必须由PARAM声明，因此还没有分析源程序时会先分析
+    pub fn set_parameter(&mut self, name: &str, value: f64) -> Result<(), exception::RegistrationError> {
+        let name = name.to_ascii_uppercase();
+        self.check_name(&name)?;
+        self.parse_program();
+        if !self.parameter_declarations.contains_key(&name) {
+            return Err(exception::RegistrationError::UndeclaredParameter(name));
+        }
+        self.executor.set_parameter(&name, value);
+        Ok(())
+    }
+
+    ///注册宿主程序提供的函数，调用时检查参数数量
+    pub fn register_function<F>(&mut self, name: &str, arity: usize, func: F) -> Result<(), exception::RegistrationError>
+    where F: Fn(&[f64]) -> f64 + Send + Sync + 'static {
+        let name = name.to_ascii_uppercase();
+        let func_name = name.clone();
+        let token = TokenBuilder::new().token_type(TokenTypeEnum::Func).lexeme(&name)
+            .func(Arc::new(move |args| {
+                Token::judge_arg_num_equal(&func_name, args, arity)?;
+                Ok(func(args))
+            })).build();
+        self.register_token(token)
+    }
+
+    ///注册宿主程序提供的常数
+    pub fn register_constant(&mut self, name: &str, value: f64) -> Result<(), exception::RegistrationError> {
+        let name = name.to_ascii_uppercase();
+        let token = TokenBuilder::new().token_type(TokenTypeEnum::ConstId).lexeme(&name).value(value).build();
+        self.register_token(token)
+    }
+
+    ///把函数名或常数放入词法分析器的符号表。只能在分析源程序之前注册，名字不能与已有的词或特殊变量相同
+    fn register_token(&mut self, token: Token) -> Result<(), exception::RegistrationError> {
+        let name = token.lexeme().clone();
+        if self.parsed.is_some() {
+            return Err(exception::RegistrationError::AlreadyParsed(name));
+        }
+        self.check_name(&name)?;
+        self.get_mut_parser_kernel().lexer.register_token(token);
+        Ok(())
+    }
+
+    ///宿主程序给出的名字与参数名必须是字母开头、只包含字母和数字的词，且不能与保留字、函数、常数或特殊变量相同
+    fn check_name(&self, name: &str) -> Result<(), exception::RegistrationError> {
+        let mut chars = name.chars();
+        let valid = chars.next().is_some_and(|ch| ch.is_ascii_alphabetic()) && chars.all(|ch| ch.is_ascii_alphanumeric());
+        if !valid {
+            return Err(exception::RegistrationError::InvalidName(String::from(name)));
+        }
+        let special_variables = [DEFAULT_LOOP_VARIABLE, IMPLICIT_X_VARIABLE, IMPLICIT_Y_VARIABLE];
+        if special_variables.contains(&name) || self.get_parser_kernel().lexer.contains_word(name) {
+            return Err(exception::RegistrationError::NameCollision(String::from(name)));
+        }
+        Ok(())
+    }
+
+    ///设置最多收集的错误数量，至少为1
+    pub fn set_max_error_count(&mut self, max_error_count: usize) {
+        self.max_error_count = max_error_count.max(1);
+    }
+
+    ///设置用户函数的最大递归深度，只修改资源限制中的这一项
+    pub fn set_max_recursion_depth(&mut self, max_recursion_depth: usize) {
+        let resource_limits = self.executor.resource_limits().clone().max_recursion_depth(max_recursion_depth);
+        self.set_resource_limits(resource_limits);
+    }
+
+    ///递归深度以资源限制为准，资源限制中未设置时使用默认值
+    pub fn set_resource_limits(&mut self, resource_limits: options::ResourceLimits) {
+        let max_recursion_depth = resource_limits.get_max_recursion_depth().unwrap_or(DEFAULT_MAX_RECURSION_DEPTH);
+        self.expression_parser().set_max_recursion_depth(max_recursion_depth);
+        self.executor.set_resource_limits(resource_limits);
+    }
+
+    ///出错时已经生成的点序列与警告
+    pub fn take_partial_output(&mut self) -> Option<InterpretOutput> {
+        self.partial_output.take()
+    }
+
+    pub fn set_max_loop_iterations(&mut self, max_loop_iterations: usize) {
+        self.executor.set_max_loop_iterations(max_loop_iterations);
+    }
+
+    pub fn set_adaptive_options(&mut self, adaptive_options: options::AdaptiveOptions) {
+        self.executor.set_adaptive_options(adaptive_options);
+    }
+
+    pub fn set_options(&mut self, options: options::InterpreterOptions) {
+        self.tracer.borrow_mut().set_options(options);
+    }
+
+    ///是否把循环中反复计算的表达式编译为字节码，默认开启。关闭时直接遍历语法树求值
+    pub fn set_compile_expressions(&mut self, compile_expressions: bool) {
+        self.executor.set_compile_expressions(compile_expressions);
+    }
+
+    ///是否在执行循环前化简表达式，默认开启
+    pub fn set_optimize_expressions(&mut self, optimize_expressions: bool) {
+        self.executor.set_optimize_expressions(optimize_expressions);
+    }
+
+    ///计算FOR DRAW的点时最多使用的线程数，默认为1。点的顺序与单线程时相同
+    pub fn set_worker_threads(&mut self, worker_threads: usize) {
+        self.executor.set_worker_threads(worker_threads);
+    }
+
+    ///设置是否把警告提升为错误
+    pub fn set_warnings_as_errors(&mut self, warnings_as_errors: bool) {
+        self.warnings_as_errors = warnings_as_errors;
     }
 }
 
 ///对parser底层进行一次封装
 pub struct ParserKernel {
     curr_token: Token,
+    //上一个被吃掉的token的区间
+    prev_token_span: Span,
     lexer: Lexer,
+    tracer: Rc<RefCell<options::Tracer>>,
 }
 
 impl ParserKernel {
-    pub fn new(file: File) -> Self {
-        let mut lexer = Lexer::new(file);
+    pub fn new(source: Source, tracer: &Rc<RefCell<options::Tracer>>) -> Self {
+        let mut lexer = Lexer::new(source);
         Self {
             curr_token: lexer.fetch_token(), //刚开始读一个以保证逻辑一致性
+            prev_token_span: Span::default(),
             lexer,
+            tracer: tracer.clone(),
         }
     }
 
     ///检查当前token是否匹配目标，如果匹配则成功并读取一次token，否则会返回语法错误SyntaxError
     pub fn match_and_eat_token(&mut self, expected_token_type: TokenTypeEnum) -> exception::Result<()> {
         if self.curr_token.token_type() == TokenTypeEnum::ErrToken {
-            return Err(exception::IllegalTokenError::new(&self.curr_token.lexeme()));
+            return Err(exception::IllegalTokenError::new(self.curr_token.lexeme()));
         }
         if self.curr_token.token_type() != expected_token_type {
             return Err(exception::SyntaxError::new(&self.curr_token, &[expected_token_type]));
         }
+        self.tracer.borrow_mut().trace_token(&self.curr_token);
+        self.prev_token_span = self.curr_token.span();
         self.curr_token = self.lexer.fetch_token();
         Ok(())
     }
 
+    ///错误恢复：一直跳过token，直到在所有块之外吃掉目标token或遇到EOF
+    ///block_depth为出错时所在的块的嵌套层数
+    pub fn skip_to_token(&mut self, target_token_type: TokenTypeEnum, mut block_depth: usize) {
+        loop {
+            let token_type = self.curr_token.token_type();
+            if token_type == TokenTypeEnum::NonToken {
+                return;
+            }
+            self.curr_token = self.lexer.fetch_token();
+            match token_type {
+                TokenTypeEnum::Begin => block_depth += 1,
+                TokenTypeEnum::End => block_depth = block_depth.saturating_sub(1),
+                _ if token_type == target_token_type && block_depth == 0 => return,
+                _ => {}
+            }
+        }
+    }
+
     pub fn get_curr_token(&self) -> &Token {
         &self.curr_token
     }
 
+    pub fn get_prev_token_span(&self) -> Span {
+        self.prev_token_span
+    }
+
     pub fn get_curr_token_type(&self) -> TokenTypeEnum {
         self.curr_token.token_type()
     }
 
     ///帮助自动生成语法错误，附有期望的token type
     pub fn generate_syntax_error<T>(&self, expected_token_type: &[TokenTypeEnum]) -> exception::Result<T> {
-        Err(exception::SyntaxError::new(&self.get_curr_token(), expected_token_type))
+        Err(exception::SyntaxError::new(self.get_curr_token(), expected_token_type))
     }
 }
 
@@ -254,16 +888,37 @@ impl ParserKernel {
 #[cfg(test)]
 mod tests {
     use std::fs::File;
+    use std::io::{BufReader, Cursor};
 
     use super::*;
 
     #[test]
     fn test_parse() {
         let file = File::open("parse_test.txt").unwrap();
-        let mut parser = ParserManager::new(file);
+        let mut parser = ParserManager::new(Box::new(BufReader::new(file)));
         let res = parser.parse();
         println!("Res: {:?}", res);
     }
+
+    #[test]
+    fn test_parse_error_recovery() {
+        let source = "ROT is 8*(2;\nFor T from 0 to 1 step 1 draw(T, T);\n45 is 8;\nLet var2 = 1;\nScale is (2, 2);";
+        let mut parser = ParserManager::new(Box::new(Cursor::new(String::from(source))));
+        let errors = parser.parse().unwrap_err();
+        let error_lines: Vec<u32> = errors.iter().map(|e| e.position().0).collect();
+        assert_eq!(error_lines, vec![1, 3, 4]);
+        assert_eq!(errors[0].kind(), exception::ErrorKind::SyntaxError);
+        assert_eq!(errors[2].kind(), exception::ErrorKind::UndefinedVariableError);
+    }
+
+    #[test]
+    fn test_parse_max_error_count() {
+        let source = "1;\n2;\n3;\n4;";
+        let mut parser = ParserManager::new(Box::new(Cursor::new(String::from(source))));
+        parser.set_max_error_count(2);
+        let errors = parser.parse().unwrap_err();
+        assert_eq!(errors.len(), 2);
+    }
 }
 
 
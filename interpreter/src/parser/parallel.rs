@@ -0,0 +1,218 @@
+use std::cell::RefCell;
+use std::ops::Range;
+use std::rc::Rc;
+use std::thread;
+
+use crate::exception;
+use super::expression::{Code, CompiledEvaluator, Vm};
+use super::statement::{CoordinateSystem, PointEvaluator};
+
+///并行采样时每个工作线程至少分到的迭代次数，迭代次数较少时创建线程得不偿失
+pub const MIN_ITERATIONS_PER_WORKER: usize = 4096;
+//工作线程每隔多少次迭代检查一次是否被取消或超时
+const LIMIT_CHECK_INTERVAL: usize = 256;
+
+///实际使用的工作线程数
+pub fn worker_count(worker_threads: usize, iteration_count: usize) -> usize {
+    worker_threads.min(iteration_count / MIN_ITERATIONS_PER_WORKER).max(1)
+}
+
+///把0..iteration_count按顺序分成worker_count段，各段长度最多相差1
+fn split(iteration_count: usize, worker_count: usize) -> Vec<Range<usize>> {
+    let (size, remainder) = (iteration_count / worker_count, iteration_count % worker_count);
+    let mut begin = 0;
+    (0..worker_count).map(|i| {
+        let end = begin + size + usize::from(i < remainder);
+        let range = begin..end;
+        begin = end;
+        range
+    }).collect()
+}
+
+///一个坐标表达式：各线程共用的字节码、循环开始时读取的变量值以及循环变量所在的变量槽
+struct Coordinate<'a> {
+    code: &'a Code,
+    slot_values: Vec<f64>,
+    loop_slot: Option<usize>,
+}
+
+impl<'a> Coordinate<'a> {
+    fn new(evaluator: &'a CompiledEvaluator, loop_variable: &Rc<RefCell<f64>>) -> Self {
+        let expression = evaluator.expression();
+        let mut slot_values = Vec::new();
+        expression.read_slots(&mut slot_values);
+        Coordinate {
+            code: expression.code(),
+            slot_values,
+            loop_slot: expression.slot_index(loop_variable),
+        }
+    }
+
+    fn set_loop_value(&self, slot_values: &mut [f64], t: f64) {
+        if let Some(slot) = self.loop_slot {
+            slot_values[slot] = t;
+        }
+    }
+}
+
+///工作线程计算的一个点，以及计算它时产生的警告计数
+#[derive(Debug, Copy, Clone)]
+pub struct DrawnPoint {
+    //变换前的直角坐标
+    pub point: (f64, f64),
+    pub negative_base_count: usize,
+    pub unmatched_piecewise_count: usize,
+}
+
+///一个工作线程计算的一段连续迭代
+pub struct Chunk {
+    //第一个点的迭代下标
+    first_index: usize,
+    //按迭代顺序排列的点
+    points: Vec<DrawnPoint>,
+    //求值出错、被取消或超时的迭代下标。主线程从这里开始按顺序执行，以得到与顺序执行相同的错误
+    stopped_at: Option<usize>,
+}
+
+impl Chunk {
+    ///按迭代顺序返回迭代下标与点
+    pub fn points(&self) -> impl Iterator<Item = (usize, &DrawnPoint)> {
+        (self.first_index..).zip(self.points.iter())
+    }
+
+    pub fn stopped_at(&self) -> Option<usize> {
+        self.stopped_at
+    }
+}
+
+///把循环体只有DRAW的FOR语句的迭代分给多个线程计算。
+///循环中只有循环变量会变化，各线程使用各自的虚拟机和变量值，共用编译好的字节码
+pub struct ParallelDraw<'a> {
+    coordinate_system: CoordinateSystem,
+    x: Coordinate<'a>,
+    y: Coordinate<'a>,
+}
+
+impl<'a> ParallelDraw<'a> {
+    ///两个坐标都编译为字节码时才能并行计算
+    pub fn new(point_evaluator: &'a PointEvaluator, loop_variable: &Rc<RefCell<f64>>) -> Option<Self> {
+        let (x, y) = point_evaluator.compiled()?;
+        Some(ParallelDraw {
+            coordinate_system: point_evaluator.coordinate_system(),
+            x: Coordinate::new(x, loop_variable),
+            y: Coordinate::new(y, loop_variable),
+        })
+    }
+
+    ///计算循环变量为from + i * step的点，返回按迭代顺序排列的各段结果。
+    ///一段停止后，后面各段的结果仍然返回，由调用者决定是否使用
+    pub fn run(&self, from: f64, step: f64, iteration_count: usize, worker_threads: usize,
+               should_stop: &(dyn Fn() -> bool + Sync)) -> Vec<Chunk> {
+        let ranges = split(iteration_count, worker_count(worker_threads, iteration_count));
+        thread::scope(|scope| {
+            let handles: Vec<_> = ranges.into_iter()
+                .map(|range| scope.spawn(move || self.sample(range, from, step, should_stop)))
+                .collect();
+            handles.into_iter()
+                .map(|handle| handle.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+                .collect()
+        })
+    }
+
+    fn sample(&self, range: Range<usize>, from: f64, step: f64, should_stop: &(dyn Fn() -> bool + Sync)) -> Chunk {
+        let mut vm = Vm::new();
+        let (mut x_values, mut y_values) = (self.x.slot_values.clone(), self.y.slot_values.clone());
+        let mut chunk = Chunk {
+            first_index: range.start,
+            points: Vec::with_capacity(range.len()),
+            stopped_at: None,
+        };
+        for i in range.clone() {
+            if (i - range.start).is_multiple_of(LIMIT_CHECK_INTERVAL) && should_stop() {
+                chunk.stopped_at = Some(i);
+                break;
+            }
+            let t = from + i as f64 * step;
+            self.x.set_loop_value(&mut x_values, t);
+            self.y.set_loop_value(&mut y_values, t);
+            match self.evaluate(&mut vm, &x_values, &y_values) {
+                Ok(point) => chunk.points.push(DrawnPoint {
+                    point,
+                    negative_base_count: vm.take_negative_base_count(),
+                    unmatched_piecewise_count: vm.take_unmatched_piecewise_count(),
+                }),
+                Err(_) => {
+                    chunk.stopped_at = Some(i);
+                    break;
+                }
+            }
+        }
+        chunk
+    }
+
+    fn evaluate(&self, vm: &mut Vm, x_values: &[f64], y_values: &[f64]) -> exception::Result<(f64, f64)> {
+        let x = vm.evaluate(self.x.code, x_values)?;
+        let y = vm.evaluate(self.y.code, y_values)?;
+        Ok(self.coordinate_system.to_cartesian(x, y))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::super::expression::{Compiler, Evaluator};
+    use super::super::statement::{Expression, ForBody, Statement};
+    use super::*;
+
+    #[test]
+    fn test_split() {
+        assert_eq!(split(10, 3), vec![0..4, 4..7, 7..10]);
+        assert_eq!(split(8, 4), vec![0..2, 2..4, 4..6, 6..8]);
+        assert_eq!(worker_count(8, MIN_ITERATIONS_PER_WORKER * 3 + 1), 3);
+        assert_eq!(worker_count(8, 10), 1);
+        assert_eq!(worker_count(0, MIN_ITERATIONS_PER_WORKER * 2), 1);
+    }
+
+    #[test]
+    fn test_run_counts_warnings_per_point() {
+        let mut interpreter_obj = crate::Interpreter::from_str(
+            "For T from -9999 to 9999 step 1 draw(T ** 0.5, piecewise((T > 0, T)));"
+        );
+        let program = interpreter_obj.parse().unwrap();
+        let Statement::For(for_statement) = program.statements()[0].statement() else {
+            panic!("expected a FOR statement");
+        };
+        let ForBody::Draw(coordinate_system, x, y) = for_statement.body() else {
+            panic!("expected a DRAW body");
+        };
+        let counter = Rc::new(Cell::new(0));
+        let compile = |expression: &Expression| {
+            Evaluator::Compiled(CompiledEvaluator::new(Compiler::compile(expression.node()), &counter, &counter))
+        };
+        let point_evaluator = PointEvaluator::new(*coordinate_system, compile(x), compile(y));
+        let parallel_draw = ParallelDraw::new(&point_evaluator, for_statement.loop_variable()).unwrap();
+
+        let chunks = parallel_draw.run(-9999.0, 1.0, 19999, 4, &|| false);
+        assert_eq!(chunks.len(), 4);
+        //每个点带有计算它时的警告计数，按迭代顺序排列
+        let mut expected_index = 0;
+        let (mut negative_base_count, mut unmatched_piecewise_count) = (0, 0);
+        for chunk in &chunks {
+            assert_eq!(chunk.stopped_at(), None);
+            for (i, drawn_point) in chunk.points() {
+                assert_eq!(i, expected_index);
+                let t = -9999.0 + i as f64;
+                assert_eq!(drawn_point.negative_base_count, usize::from(t < 0.0));
+                assert_eq!(drawn_point.unmatched_piecewise_count, usize::from(t <= 0.0));
+                negative_base_count += drawn_point.negative_base_count;
+                unmatched_piecewise_count += drawn_point.unmatched_piecewise_count;
+                expected_index += 1;
+            }
+        }
+        assert_eq!(expected_index, 19999);
+        assert_eq!((negative_base_count, unmatched_piecewise_count), (9999, 10000));
+        //工作线程的计数由调用者累加，不直接修改共用的计数器
+        assert_eq!(counter.get(), 0);
+    }
+}
@@ -1,10 +1,128 @@
+///点被丢弃的原因
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DiscardReason {
+    //坐标含NaN
+    Nan,
+    //超出坐标范围
+    OutOfRange,
+}
+
+///一条语句中被丢弃的点数
+#[derive(Debug, Default)]
+pub struct DiscardCount {
+    nan: usize,
+    out_of_range: usize,
+}
+
+impl DiscardCount {
+    pub fn record(&mut self, reason: DiscardReason) {
+        match reason {
+            DiscardReason::Nan => self.nan += 1,
+            DiscardReason::OutOfRange => self.out_of_range += 1,
+        }
+    }
+    pub fn nan(&self) -> usize {
+        self.nan
+    }
+    pub fn out_of_range(&self) -> usize {
+        self.out_of_range
+    }
+}
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::lexer::Span;
+use crate::options;
+
+///生成点时生效的坐标变换
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Transform {
+    origin: (f64, f64),
+    scale: (f64, f64),
+    rot: f64,
+}
+
+impl Transform {
+    pub fn origin(&self) -> (f64, f64) {
+        self.origin
+    }
+    pub fn scale(&self) -> (f64, f64) {
+        self.scale
+    }
+    pub fn rot(&self) -> f64 {
+        self.rot
+    }
+}
+
+///点序列的种类
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SeriesKind {
+    //FOR语句按循环变量依次生成的点
+    Curve,
+    //IMPLICIT语句生成的等值线，每相邻两个点是一条线段的两个端点
+    Contour,
+}
+
+///一条FOR或IMPLICIT语句生成的点序列
+#[derive(Debug, Clone, PartialEq)]
+pub struct PointSeries {
+    kind: SeriesKind,
+    //语句的区间
+    span: Span,
+    //循环变量名及其范围和步长。对于等值线，是X及网格横向的范围和间距
+    loop_variable: String,
+    t_from: f64,
+    t_to: f64,
+    t_step: f64,
+    transform: Transform,
+    points: Vec<(f64, f64)>,
+    //points[i-1]与points[i]之间不连续的下标i，只有ADAPTIVE采样会检测
+    discontinuities: Vec<usize>,
+}
+
+impl PointSeries {
+    pub fn kind(&self) -> SeriesKind {
+        self.kind
+    }
+    pub fn span(&self) -> Span {
+        self.span
+    }
+    pub fn loop_variable(&self) -> &str {
+        &self.loop_variable
+    }
+    ///循环变量的范围 (from, to)
+    pub fn t_range(&self) -> (f64, f64) {
+        (self.t_from, self.t_to)
+    }
+    pub fn t_step(&self) -> f64 {
+        self.t_step
+    }
+    pub fn transform(&self) -> Transform {
+        self.transform
+    }
+    pub fn points(&self) -> &[(f64, f64)] {
+        &self.points
+    }
+    ///不连续处的下标i，绘制折线时不应连接points[i-1]与points[i]
+    pub fn discontinuities(&self) -> &[usize] {
+        &self.discontinuities
+    }
+    pub fn into_points(self) -> Vec<(f64, f64)> {
+        self.points
+    }
+}
+
 pub struct PointManager {
     min_x: f64,
     max_x: f64,
     min_y: f64,
     max_y: f64,
 
-    point_storage: Option<Vec<(f64, f64)>>, //可以低消耗地共享
+    //每条FOR或IMPLICIT语句一个点序列
+    series_storage: Vec<PointSeries>,
+    //所有点序列中的点数之和
+    total_points: usize,
 
     var_origin: (f64, f64),
     var_scale: (f64, f64),
@@ -12,63 +130,131 @@ pub struct PointManager {
     //提前运算以加速
     var_rot_sin: f64,
     var_rot_cos: f64,
+
+    tracer: Rc<RefCell<options::Tracer>>,
 }
 
 impl PointManager {
-    pub fn new() -> Self {
+    pub fn new(tracer: &Rc<RefCell<options::Tracer>>) -> Self {
         Self {
             min_x: -8000.0,
             max_x: 8000.0,
             min_y: -5000.0,
             max_y: 5000.0,
 
-            point_storage: Some(Vec::new()),
+            series_storage: Vec::new(),
+            total_points: 0,
 
             var_origin: (0.0, 0.0),
             var_scale: (1.0, 1.0),
             var_rot: 0.0,
             var_rot_sin: 0.0,
             var_rot_cos: 1.0,
+
+            tracer: tracer.clone(),
         }
     }
 
-    ///添加一个点。如果点添加失败则返回Err(())
-    pub fn add_point(&mut self,  new_point: &mut (f64, f64)) -> Result<(), ()> {
+    ///以当前的坐标变换开始一个新的点序列，返回其编号
+    pub fn begin_series(&mut self, kind: SeriesKind, span: Span, loop_variable: &str,
+                        t_from: f64, t_to: f64, t_step: f64) -> usize {
+        let transform = Transform {
+            origin: self.var_origin,
+            scale: self.var_scale,
+            rot: self.var_rot,
+        };
+        self.series_storage.push(PointSeries {
+            kind,
+            span,
+            loop_variable: String::from(loop_variable),
+            t_from,
+            t_to,
+            t_step,
+            transform,
+            points: Vec::new(),
+            discontinuities: Vec::new(),
+        });
+        self.series_storage.len() - 1
+    }
+
+    ///向某个点序列添加一个点。如果点被丢弃则返回丢弃的原因
+    pub fn add_point(&mut self, series_index: usize, new_point: &mut (f64, f64)) -> Result<(), DiscardReason> {
+        self.transform_point(new_point)?;
+        self.series_storage[series_index].points.push(*new_point);
+        self.total_points += 1;
+        Ok(())
+    }
+
+    ///向某个等值线点序列添加一条线段。任一端点被丢弃则整条线段都被丢弃
+    pub fn add_segment(&mut self, series_index: usize, segment: &mut ((f64, f64), (f64, f64))) -> Result<(), DiscardReason> {
+        self.transform_point(&mut segment.0)?;
+        self.transform_point(&mut segment.1)?;
+        let points = &mut self.series_storage[series_index].points;
+        points.push(segment.0);
+        points.push(segment.1);
+        self.total_points += 2;
+        Ok(())
+    }
+
+    ///按当前的SCALE、ROT、ORIGIN对点进行坐标变换
+    pub fn transform(&self, point: (f64, f64)) -> (f64, f64) {
+        let x = point.0 * self.var_scale.0;
+        let y = point.1 * self.var_scale.1;
+        let temp_x = x * self.var_rot_cos - y * self.var_rot_sin;
+        let temp_y = x * self.var_rot_sin + y * self.var_rot_cos;
+        (temp_x + self.var_origin.0, temp_y + self.var_origin.1)
+    }
+
+    ///坐标范围对角线的长度
+    pub fn coordinate_diagonal(&self) -> f64 {
+        (self.max_x - self.min_x).hypot(self.max_y - self.min_y)
+    }
+
+    ///标记某个点序列的最后一个点与下一个点之间不连续
+    pub fn mark_discontinuity(&mut self, series_index: usize) {
+        let series = &mut self.series_storage[series_index];
+        let index = series.points.len();
+        if index > 0 && series.discontinuities.last() != Some(&index) {
+            series.discontinuities.push(index);
+        }
+    }
+
+    ///对点进行坐标变换，并检查是否应被丢弃
+    fn transform_point(&mut self, new_point: &mut (f64, f64)) -> Result<(), DiscardReason> {
         // println!("Debug: before add_point: {:?}",new_point);
         //剔除非法点
         if new_point.0.is_nan() || new_point.1.is_nan(){
-            return Err(());
+            return Err(DiscardReason::Nan);
         }
 
         //计算点位置
-        new_point.0 *= &self.var_scale.0;
-        new_point.1 *= &self.var_scale.1;
-        let temp_x = &new_point.0 * &self.var_rot_cos - &new_point.1 * &self.var_rot_sin;
-        let temp_y = &new_point.0 * &self.var_rot_sin + &new_point.1 * &self.var_rot_cos;
-        new_point.0 = temp_x+&self.var_origin.0;
-        new_point.1 = temp_y+&self.var_origin.1;
+        *new_point = self.transform(*new_point);
 
-        println!("Debug: Add Point: {:?}", new_point);
+        self.tracer.borrow_mut().dump_point(new_point);
 
         if new_point.0 < self.min_x || new_point.0 > self.max_x
             || new_point.1 < self.min_y || new_point.1 > self.max_y {
             //越界，无视该点
-            return Err(());
+            return Err(DiscardReason::OutOfRange);
         }
-        self.extract_mut_point_storage().push(new_point.clone());
         Ok(())
     }
 
-    fn extract_mut_point_storage(&mut self) -> &mut Vec<(f64, f64)> {
-        if let Some(ps) = &mut self.point_storage {
-            return ps;
-        } else {
-            panic!("PointManager: point_storage is None.")
-        }
+    pub fn total_points(&self) -> usize {
+        self.total_points
+    }
+
+    ///清空点序列并恢复默认的坐标变换，用于再次执行程序
+    pub fn reset(&mut self) {
+        self.move_series_storage();
+        self.set_var_origin((0.0, 0.0));
+        self.set_var_scale((1.0, 1.0));
+        self.set_var_rot(0.0);
     }
 
-    pub fn move_point_storage(&mut self) -> Vec<(f64, f64)> {
-        self.point_storage.take().unwrap()
+    pub fn move_series_storage(&mut self) -> Vec<PointSeries> {
+        self.total_points = 0;
+        std::mem::take(&mut self.series_storage)
     }
 
     pub fn set_coordinate_range(&mut self, min_x: f64, max_x: f64, min_y: f64, max_y: f64) {
@@ -0,0 +1,555 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::exception;
+use crate::lexer::{Span, Token};
+use super::expression::{ASTNode, CompiledEvaluator, Evaluator, UserFunction};
+
+///DRAW使用的坐标系
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CoordinateSystem {
+    //DRAW(x,y)
+    Cartesian,
+    //DRAW POLAR(r,theta)，在坐标变换之前转换为直角坐标
+    Polar,
+}
+
+impl CoordinateSystem {
+    ///把DRAW的两个参数转换为直角坐标
+    pub fn to_cartesian(self, x: f64, y: f64) -> (f64, f64) {
+        match self {
+            CoordinateSystem::Cartesian => (x, y),
+            CoordinateSystem::Polar => (x * y.cos(), x * y.sin()),
+        }
+    }
+}
+
+///反复计算一个点变换前的直角坐标
+pub struct PointEvaluator<'a> {
+    coordinate_system: CoordinateSystem,
+    x: Evaluator<'a>,
+    y: Evaluator<'a>,
+}
+
+impl<'a> PointEvaluator<'a> {
+    pub fn new(coordinate_system: CoordinateSystem, x: Evaluator<'a>, y: Evaluator<'a>) -> Self {
+        PointEvaluator {
+            coordinate_system,
+            x,
+            y,
+        }
+    }
+
+    pub fn coordinate_system(&self) -> CoordinateSystem {
+        self.coordinate_system
+    }
+
+    pub fn evaluate(&mut self) -> exception::Result<(f64, f64)> {
+        let (x, y) = (self.x.evaluate()?, self.y.evaluate()?);
+        Ok(self.coordinate_system.to_cartesian(x, y))
+    }
+
+    ///两个坐标都编译为字节码时返回它们的求值器
+    pub fn compiled(&self) -> Option<(&CompiledEvaluator, &CompiledEvaluator)> {
+        match (&self.x, &self.y) {
+            (Evaluator::Compiled(x), Evaluator::Compiled(y)) => Some((x, y)),
+            _ => None,
+        }
+    }
+}
+
+///表达式：语法树及其在源码中的区间。语法树只在解释器内部使用，对外可以查看区间与常数值，或以文本形式输出
+#[derive(Clone)]
+pub struct Expression {
+    node: Rc<dyn ASTNode>,
+    span: Span,
+}
+
+impl Expression {
+    pub(crate) fn new(node: Box<dyn ASTNode>, span: Span) -> Self {
+        Expression {
+            node: Rc::from(node),
+            span,
+        }
+    }
+
+    pub(crate) fn node(&self) -> &dyn ASTNode {
+        self.node.as_ref()
+    }
+    pub(crate) fn shared_node(&self) -> &Rc<dyn ASTNode> {
+        &self.node
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    ///表达式是单个常数时的值
+    pub fn constant_value(&self) -> Option<f64> {
+        self.node.constant_value()
+    }
+
+    ///以文本形式输出语法树，格式与dump_ast选项相同
+    pub fn tree(&self) -> String {
+        let mut tree = String::new();
+        self.node.write_tree(0, &mut tree);
+        tree
+    }
+}
+
+//语法树没有实现Debug，只输出区间
+impl fmt::Debug for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Expression")
+            .field("span", &self.span)
+            .finish()
+    }
+}
+
+///语句。分析完整个程序后再执行，块中的语句会被执行多次
+#[derive(Debug)]
+pub enum Statement {
+    //ORIGIN IS (ex1,ex2)
+    Origin(Expression, Expression),
+    //SCALE IS (ex1,ex2)
+    Scale(Expression, Expression),
+    //ROT IS ex
+    Rot(Expression),
+    For(ForStatement),
+    //DRAW(ex1,ex2)或DRAW POLAR(ex1,ex2)，只能出现在循环体中
+    Draw(CoordinateSystem, Expression, Expression),
+    //IF cond THEN statement ELSE statement，两个分支都可以是块
+    If(Expression, Vec<Statement>, Vec<Statement>),
+    Implicit(ImplicitStatement),
+    //DEF var = ex，只能出现在顶层
+    Def(Assignment),
+    //LET var = ex，只能出现在顶层
+    Let(Assignment),
+    //FN name(var1,var2,...) => ex，只能出现在顶层。函数在分析时就已定义，执行时什么也不做
+    Fn(FunctionDefinition),
+    //PARAM var DEFAULT ex，只能出现在顶层
+    Param(ParameterStatement),
+}
+
+impl Statement {
+    ///这些语句中是否直接（不经过内层循环）绘制点
+    fn any_draws_directly(statements: &[Statement]) -> bool {
+        statements.iter().any(|s| match s {
+            Statement::Draw(..) => true,
+            Statement::If(_, then_branch, else_branch) =>
+                Statement::any_draws_directly(then_branch) || Statement::any_draws_directly(else_branch),
+            _ => false,
+        })
+    }
+}
+
+///循环体
+#[derive(Debug)]
+pub enum ForBody {
+    //DRAW(ex1,ex2)或DRAW POLAR(ex1,ex2)
+    Draw(CoordinateSystem, Expression, Expression),
+    //DO BEGIN statement; ... END
+    Block(Vec<Statement>),
+    //ADAPTIVE DRAW(ex1,ex2)，自适应地细分循环变量的区间
+    AdaptiveDraw(CoordinateSystem, Expression, Expression),
+}
+
+///FOR var FROM ex1 TO ex2 STEP ex3 [ADAPTIVE] body
+pub struct ForStatement {
+    //整条FOR语句的区间
+    span: Span,
+    loop_variable_name: String,
+    loop_variable: Rc<RefCell<f64>>,
+    from: Expression,
+    to: Expression,
+    step: Expression,
+    body: ForBody,
+    //是否嵌套在其他循环中
+    nested: bool,
+}
+
+impl ForStatement {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(span: Span, loop_variable_name: &str, loop_variable: &Rc<RefCell<f64>>,
+                      from: Expression, to: Expression, step: Expression,
+                      body: ForBody, nested: bool) -> Self {
+        ForStatement {
+            span,
+            loop_variable_name: String::from(loop_variable_name),
+            loop_variable: loop_variable.clone(),
+            from,
+            to,
+            step,
+            body,
+            nested,
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+    pub fn loop_variable_name(&self) -> &str {
+        &self.loop_variable_name
+    }
+    pub(crate) fn loop_variable(&self) -> &Rc<RefCell<f64>> {
+        &self.loop_variable
+    }
+    pub fn from(&self) -> &Expression {
+        &self.from
+    }
+    pub fn to(&self) -> &Expression {
+        &self.to
+    }
+    pub fn step(&self) -> &Expression {
+        &self.step
+    }
+    pub fn body(&self) -> &ForBody {
+        &self.body
+    }
+    pub fn is_nested(&self) -> bool {
+        self.nested
+    }
+
+    ///循环体中是否直接（不经过内层循环）绘制点，是则需要为该循环生成点序列
+    pub fn draws_directly(&self) -> bool {
+        match &self.body {
+            ForBody::Draw(..) | ForBody::AdaptiveDraw(..) => true,
+            ForBody::Block(statements) => Statement::any_draws_directly(statements),
+        }
+    }
+}
+
+//循环变量的值只在执行时有意义，不输出
+impl fmt::Debug for ForStatement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ForStatement")
+            .field("span", &self.span)
+            .field("loop_variable_name", &self.loop_variable_name)
+            .field("from", &self.from)
+            .field("to", &self.to)
+            .field("step", &self.step)
+            .field("body", &self.body)
+            .field("nested", &self.nested)
+            .finish()
+    }
+}
+
+///IMPLICIT ex OVER (ex1,ex2,ex3,ex4) RESOLUTION ex5
+///在网格上求F(X,Y)=0的曲线
+pub struct ImplicitStatement {
+    //整条IMPLICIT语句的区间
+    span: Span,
+    //X、Y只在表达式中可见
+    x_variable: Rc<RefCell<f64>>,
+    y_variable: Rc<RefCell<f64>>,
+    expression: Expression,
+    //x_min, x_max, y_min, y_max
+    bounds: [Expression; 4],
+    resolution: Expression,
+}
+
+impl ImplicitStatement {
+    pub(crate) fn new(span: Span, x_variable: &Rc<RefCell<f64>>, y_variable: &Rc<RefCell<f64>>,
+                      expression: Expression, bounds: [Expression; 4], resolution: Expression) -> Self {
+        ImplicitStatement {
+            span,
+            x_variable: x_variable.clone(),
+            y_variable: y_variable.clone(),
+            expression,
+            bounds,
+            resolution,
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+    pub(crate) fn x_variable(&self) -> &Rc<RefCell<f64>> {
+        &self.x_variable
+    }
+    pub(crate) fn y_variable(&self) -> &Rc<RefCell<f64>> {
+        &self.y_variable
+    }
+    pub fn expression(&self) -> &Expression {
+        &self.expression
+    }
+    pub fn bounds(&self) -> &[Expression; 4] {
+        &self.bounds
+    }
+    pub fn resolution(&self) -> &Expression {
+        &self.resolution
+    }
+}
+
+impl fmt::Debug for ImplicitStatement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ImplicitStatement")
+            .field("span", &self.span)
+            .field("expression", &self.expression)
+            .field("bounds", &self.bounds)
+            .field("resolution", &self.resolution)
+            .finish()
+    }
+}
+
+///DEF或LET：执行时把表达式放入变量，之后引用该变量的表达式都使用它
+pub struct Assignment {
+    variable_name: String,
+    //引用该变量的表达式共用的语法树
+    target: Rc<RefCell<Rc<dyn ASTNode>>>,
+    expression: Expression,
+}
+
+impl Assignment {
+    pub(crate) fn new(variable_name: &str, target: &Rc<RefCell<Rc<dyn ASTNode>>>, expression: Expression) -> Self {
+        Assignment {
+            variable_name: String::from(variable_name),
+            target: target.clone(),
+            expression,
+        }
+    }
+
+    pub fn variable_name(&self) -> &str {
+        &self.variable_name
+    }
+    pub fn expression(&self) -> &Expression {
+        &self.expression
+    }
+
+    pub(crate) fn assign(&self) {
+        *self.target.borrow_mut() = self.expression.shared_node().clone();
+    }
+}
+
+impl fmt::Debug for Assignment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Assignment")
+            .field("variable_name", &self.variable_name)
+            .field("expression", &self.expression)
+            .finish()
+    }
+}
+
+///FN语句定义的函数
+pub struct FunctionDefinition {
+    function: Rc<UserFunction>,
+    parameter_names: Vec<String>,
+}
+
+impl FunctionDefinition {
+    pub(crate) fn new(function: &Rc<UserFunction>, parameter_names: Vec<String>) -> Self {
+        FunctionDefinition {
+            function: function.clone(),
+            parameter_names,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        self.function.name()
+    }
+    pub fn parameter_names(&self) -> &[String] {
+        &self.parameter_names
+    }
+
+    ///以文本形式输出函数体的语法树，格式与dump_ast选项相同
+    pub fn body_tree(&self) -> String {
+        let mut tree = String::new();
+        self.function.body().write_tree(0, &mut tree);
+        tree
+    }
+}
+
+impl fmt::Debug for FunctionDefinition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FunctionDefinition")
+            .field("name", &self.name())
+            .field("parameter_names", &self.parameter_names)
+            .finish()
+    }
+}
+
+///PARAM：执行时宿主程序设置了该参数就使用设置的值，否则计算默认值
+pub struct ParameterStatement {
+    //声明该参数的token
+    name_token: Token,
+    value: Rc<RefCell<f64>>,
+    default: Expression,
+}
+
+impl ParameterStatement {
+    pub(crate) fn new(name_token: &Token, value: &Rc<RefCell<f64>>, default: Expression) -> Self {
+        ParameterStatement {
+            name_token: name_token.clone(),
+            value: value.clone(),
+            default,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        self.name_token.lexeme()
+    }
+    ///参数名所在的区间
+    pub fn span(&self) -> Span {
+        self.name_token.span()
+    }
+    pub fn default(&self) -> &Expression {
+        &self.default
+    }
+
+    ///host_value为宿主程序设置的值
+    pub(crate) fn assign(&self, host_value: Option<f64>) -> exception::Result<()> {
+        let value = match host_value {
+            Some(value) => value,
+            None => self.default.node().calculate()?,
+        };
+        *self.value.borrow_mut() = value;
+        Ok(())
+    }
+}
+
+impl fmt::Debug for ParameterStatement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ParameterStatement")
+            .field("name", &self.name())
+            .field("default", &self.default)
+            .finish()
+    }
+}
+
+///程序用PARAM声明的外部参数
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParameterDeclaration {
+    name: String,
+    //参数名所在的区间
+    span: Span,
+}
+
+impl ParameterDeclaration {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn span(&self) -> Span {
+        self.span
+    }
+}
+
+///顶层语句及其在源码中的位置
+pub struct ProgramStatement {
+    statement: Statement,
+    //首个token，报错时提示
+    begin_token: Token,
+    //从首个token到语句末尾的区间，不含分号
+    span: Span,
+    //语句之后的token（通常是分号）的区间，执行时的错误没有token时定位到这里
+    end_span: Span,
+}
+
+impl ProgramStatement {
+    pub(crate) fn new(statement: Statement, begin_token: Token, span: Span, end_span: Span) -> Self {
+        ProgramStatement {
+            statement,
+            begin_token,
+            span,
+            end_span,
+        }
+    }
+
+    pub fn statement(&self) -> &Statement {
+        &self.statement
+    }
+    pub fn begin_token(&self) -> &Token {
+        &self.begin_token
+    }
+    pub fn span(&self) -> Span {
+        self.span
+    }
+    pub fn end_span(&self) -> Span {
+        self.end_span
+    }
+}
+
+impl fmt::Debug for ProgramStatement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProgramStatement")
+            .field("statement", &self.statement)
+            .field("span", &self.span)
+            .finish()
+    }
+}
+
+///分析得到的整个程序：按顺序排列的顶层语句，以及分析时产生的警告。
+///每次执行前循环变量都会被重置，因此可以多次执行。复制时共享语句。
+///语句中的变量绑定在分析它的解释器的符号上，只能由该解释器执行
+#[derive(Clone)]
+pub struct Program {
+    //分析该程序的解释器的编号
+    parser_id: usize,
+    statements: Rc<[ProgramStatement]>,
+    //附带产生时已经分析完的顶层语句数，执行时按它与执行产生的警告交错排列
+    warnings: Vec<(usize, exception::InterpretWarning)>,
+    //程序中所有循环变量的值
+    loop_variables: Vec<Rc<RefCell<f64>>>,
+    //程序中所有PARAM声明的外部参数的名字与值，包括被重新声明的
+    external_parameters: Vec<(String, Rc<RefCell<f64>>)>,
+}
+
+impl Program {
+    pub(crate) fn new(parser_id: usize, statements: Vec<ProgramStatement>,
+                      warnings: Vec<(usize, exception::InterpretWarning)>, loop_variables: Vec<Rc<RefCell<f64>>>,
+                      external_parameters: Vec<(String, Rc<RefCell<f64>>)>) -> Self {
+        Program {
+            parser_id,
+            statements: statements.into(),
+            warnings,
+            loop_variables,
+            external_parameters,
+        }
+    }
+
+    pub fn statements(&self) -> &[ProgramStatement] {
+        &self.statements
+    }
+    ///分析时产生的警告
+    pub fn warnings(&self) -> impl Iterator<Item = &exception::InterpretWarning> {
+        self.warnings.iter().map(|(_, warning)| warning)
+    }
+    ///分析时产生的警告，以及产生时已经分析完的顶层语句数
+    pub(crate) fn indexed_warnings(&self) -> &[(usize, exception::InterpretWarning)] {
+        &self.warnings
+    }
+    pub(crate) fn parser_id(&self) -> usize {
+        self.parser_id
+    }
+    pub(crate) fn loop_variables(&self) -> &[Rc<RefCell<f64>>] {
+        &self.loop_variables
+    }
+    pub(crate) fn external_parameters(&self) -> &[(String, Rc<RefCell<f64>>)] {
+        &self.external_parameters
+    }
+
+    ///按声明顺序排列的PARAM语句声明的参数，重复声明的参数只保留第一次
+    pub fn parameters(&self) -> Vec<ParameterDeclaration> {
+        let mut parameters: Vec<ParameterDeclaration> = Vec::new();
+        for program_statement in self.statements.iter() {
+            if let Statement::Param(parameter) = program_statement.statement() {
+                if parameters.iter().all(|p| p.name() != parameter.name()) {
+                    parameters.push(ParameterDeclaration {
+                        name: String::from(parameter.name()),
+                        span: parameter.name_token.span(),
+                    });
+                }
+            }
+        }
+        parameters
+    }
+}
+
+impl fmt::Debug for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Program")
+            .field("statements", &self.statements)
+            .field("warnings", &self.warnings)
+            .finish()
+    }
+}
@@ -6,7 +6,21 @@ fn main() -> Result<(), Box<dyn std::error::Error>>{
     let mut interpreter_obj = interpreter::Interpreter::new(aim_file);
     //限制坐标范围
     interpreter_obj.set_coordinate_range(-20.0, 20.0, -20.0, 20.0);
-    let point_result = interpreter_obj.interpret().unwrap();
+    let series_result = match interpreter_obj.interpret() {
+        Ok(output) => {
+            for warning in output.warnings() {
+                println!("{}", warning);
+            }
+            output.into_series()
+        }
+        Err(errors) => {
+            //由调用者打印异常
+            for e in &errors {
+                println!("{}", e);
+            }
+            return Err(format!("interpret failed with {} error(s)", errors.len()).into());
+        }
+    };
 
     let mut drawer_obj = drawer::Drawer::new()
         //指定输出图像大小
@@ -16,8 +30,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>>{
         //指定输出文件名和标题
         .build_message("draw_test.png", "");
 
-    //添加点集和颜色
-    drawer_obj.add_task(point_result, drawer::colors::RED);
+    //每条FOR语句的点序列使用不同的颜色
+    let palette = [drawer::colors::RED, drawer::colors::BLUE, drawer::colors::GREEN, drawer::colors::MAGENTA];
+    for (i, series) in series_result.into_iter().enumerate() {
+        drawer_obj.add_task(series.into_points(), palette[i % palette.len()]);
+    }
     drawer_obj.draw()
 }
 